@@ -0,0 +1,186 @@
+/*!
+Reloading the assets when their files in `static/` change, only in the native debug builds.
+
+The modification times are polled a couple of times per second. The new version goes into
+the same slot, so everything holding a handle draws it right away. The sprites using an atlas
+get their frame looked up again, the ones drawing a whole image follow its new size. The
+animation clips are copied on spawn and stay as they were.
+The levels are already spawned, the game has to spawn the reloaded ones again.
+*/
+use super::loader::assets;
+use super::{AssetError, LevelData, Manifest, SoundData};
+use crate::game::{AtlasStorage, Game};
+use crate::gfx::atlas::{self, Atlas};
+use crate::gfx::Sprite;
+use golem::TextureFilter;
+use legion::prelude::*;
+use quicksilver::graphics::{Graphics, Image};
+use std::path::Path;
+use std::time::{Duration, Instant, SystemTime};
+
+const STATIC_DIR: &str = "static";
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+    Image,
+    Atlas,
+    Level,
+    Sound,
+}
+
+struct Watched {
+    kind: Kind,
+    name: String,
+    // Relative to the static directory, as the assets are loaded
+    path: String,
+    // The sheet of an atlas changes separately from the description
+    extra: Option<String>,
+    modified: Option<SystemTime>,
+}
+
+pub struct HotReload {
+    watched: Vec<Watched>,
+    last_poll: Instant,
+}
+
+impl HotReload {
+    /// Watch every asset of the manifest, call after it's loaded.
+    pub fn new(manifest: &Manifest, game: &Game) -> Self {
+        let atlases = game
+            .resources
+            .get::<AtlasStorage>()
+            .expect("AtlasStorage missing somehow");
+        let mut watched = Vec::new();
+        let mut watch = |kind, name: &str, path: &str, extra: Option<String>| {
+            let modified = last_modified(path, extra.as_deref());
+            watched.push(Watched {
+                kind,
+                name: name.into(),
+                path: path.into(),
+                extra,
+                modified,
+            });
+        };
+        for (name, path) in manifest.images.iter() {
+            watch(Kind::Image, name, path, None);
+        }
+        for (name, path) in manifest.atlases.iter() {
+            let sheet = game
+                .images
+                .handle(name)
+                .and_then(|handle| atlases.get(&handle))
+                .map(|atlas| atlas::sheet_path(path, &atlas.sheet));
+            watch(Kind::Atlas, name, path, sheet);
+        }
+        for (name, path) in manifest.levels.iter() {
+            watch(Kind::Level, name, path, None);
+        }
+        for (name, path) in manifest.sounds.iter() {
+            watch(Kind::Sound, name, path, None);
+        }
+        Self {
+            watched,
+            last_poll: Instant::now(),
+        }
+    }
+
+    /// Reload whatever changed since the last poll, returns the names of the reloaded levels.
+    pub async fn update(&mut self, gfx: &Graphics, game: &mut Game) -> Vec<String> {
+        let mut levels = Vec::new();
+        if self.last_poll.elapsed() < POLL_INTERVAL {
+            return levels;
+        }
+        self.last_poll = Instant::now();
+
+        for watched in self.watched.iter_mut() {
+            let modified = last_modified(&watched.path, watched.extra.as_deref());
+            if modified == watched.modified {
+                continue;
+            }
+            // Even when the reload fails, the file could be still being written
+            watched.modified = modified;
+            info!("Reloading `{}`", watched.path);
+            match reload(watched, gfx, game).await {
+                Ok(()) if watched.kind == Kind::Level => levels.push(watched.name.clone()),
+                Ok(()) => {}
+                Err(err) => warn!("{}", err),
+            }
+        }
+        levels
+    }
+}
+
+async fn reload(watched: &mut Watched, gfx: &Graphics, game: &mut Game) -> Result<(), AssetError> {
+    let (name, path) = (watched.name.clone(), watched.path.clone());
+    let (name, path) = (name.as_str(), path.as_str());
+    let load_error = |error| AssetError::Load {
+        path: path.into(),
+        error,
+    };
+    match watched.kind {
+        Kind::Image => {
+            let image = Image::load(gfx, path).await.map_err(load_error)?;
+            image
+                .set_magnification(TextureFilter::Nearest)
+                .map_err(load_error)?;
+            let handle = game.images.reserve(name);
+            let old_size = game.images.get(handle).map(Image::size);
+            let new_size = image.size();
+            game.images.insert(name, image);
+            if let Some(old_size) = old_size.filter(|size| *size != new_size) {
+                for mut sprite in <Write<Sprite>>::query().iter_mut(&mut game.world) {
+                    if sprite.src == handle {
+                        sprite.resize_whole_image(old_size, new_size);
+                    }
+                }
+            }
+        }
+        Kind::Atlas => {
+            let handle = game.images.reserve(name);
+            let (atlas, sheet) =
+                Atlas::load(gfx, path, handle)
+                    .await
+                    .map_err(|error| AssetError::Atlas {
+                        path: path.into(),
+                        error,
+                    })?;
+            watched.extra = Some(atlas::sheet_path(path, &atlas.sheet));
+            game.images.insert(name, sheet);
+            for mut sprite in <Write<Sprite>>::query().iter_mut(&mut game.world) {
+                if sprite.src != handle {
+                    continue;
+                }
+                if let Some(frame) = sprite.frame.clone() {
+                    if !sprite.set_frame(&atlas, &frame) {
+                        warn!("Reloaded `{}` has no frame `{}`", path, frame);
+                    }
+                }
+            }
+            game.resources
+                .get_mut::<AtlasStorage>()
+                .expect("AtlasStorage missing somehow")
+                .insert(handle, atlas);
+        }
+        Kind::Level => {
+            let bytes = quicksilver::load_file(path).await.map_err(load_error)?;
+            assets(game).levels.insert(name, LevelData { bytes });
+        }
+        Kind::Sound => {
+            let bytes = quicksilver::load_file(path).await.map_err(load_error)?;
+            assets(game).sounds.insert(name, SoundData { bytes });
+        }
+    }
+    Ok(())
+}
+
+// The later of the two, `None` while the files don't exist
+fn last_modified(path: &str, extra: Option<&str>) -> Option<SystemTime> {
+    let modified = |path: &str| {
+        std::fs::metadata(Path::new(STATIC_DIR).join(path))
+            .and_then(|metadata| metadata.modified())
+            .ok()
+    };
+    let extra = extra.and_then(modified);
+    modified(path).max(extra)
+}
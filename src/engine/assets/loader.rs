@@ -0,0 +1,158 @@
+use super::{AssetError, Assets, Handle, LevelData, Manifest, SoundData};
+use crate::game::{AtlasStorage, Game};
+use crate::gfx::Atlas;
+use golem::TextureFilter;
+use quicksilver::graphics::{Graphics, Image};
+use std::collections::VecDeque;
+use std::ops::DerefMut;
+
+/// References a scene took while loading, hand them back with `Game::release`.
+#[derive(Default)]
+pub struct AssetGroup {
+    pub images: Vec<Handle<Image>>,
+    pub levels: Vec<Handle<LevelData>>,
+    pub sounds: Vec<Handle<SoundData>>,
+}
+
+enum Job {
+    Image { name: String, path: String },
+    Atlas { name: String, path: String },
+    Level { name: String, path: String },
+    Sound { name: String, path: String },
+}
+
+/// Loads the assets of a manifest one by one, so a loading screen can be drawn in between.
+///
+/// The assets already loaded by another scene are only referenced again.
+pub struct Loader {
+    jobs: VecDeque<Job>,
+    total: usize,
+    group: AssetGroup,
+}
+
+impl Loader {
+    pub fn new(manifest: &Manifest) -> Self {
+        let mut jobs = VecDeque::new();
+        for (name, path) in manifest.images.iter() {
+            let (name, path) = (name.clone(), path.clone());
+            jobs.push_back(Job::Image { name, path });
+        }
+        for (name, path) in manifest.atlases.iter() {
+            let (name, path) = (name.clone(), path.clone());
+            jobs.push_back(Job::Atlas { name, path });
+        }
+        for (name, path) in manifest.levels.iter() {
+            let (name, path) = (name.clone(), path.clone());
+            jobs.push_back(Job::Level { name, path });
+        }
+        for (name, path) in manifest.sounds.iter() {
+            let (name, path) = (name.clone(), path.clone());
+            jobs.push_back(Job::Sound { name, path });
+        }
+        Self {
+            total: jobs.len(),
+            jobs,
+            group: AssetGroup::default(),
+        }
+    }
+
+    /// Part of the assets done, from 0 to 1.
+    pub fn progress(&self) -> f32 {
+        if self.total == 0 {
+            1.
+        } else {
+            (self.total - self.jobs.len()) as f32 / self.total as f32
+        }
+    }
+    pub fn is_done(&self) -> bool {
+        self.jobs.is_empty()
+    }
+
+    /// Load the next asset. A failed asset is skipped, the rest can still be loaded.
+    pub async fn step(&mut self, gfx: &Graphics, game: &mut Game) -> Result<(), AssetError> {
+        let job = match self.jobs.pop_front() {
+            Some(job) => job,
+            None => return Ok(()),
+        };
+        match job {
+            Job::Image { name, path } => {
+                let handle = game.images.reserve(&name);
+                if !game.images.is_loaded(handle) {
+                    let image = Image::load(gfx, &path)
+                        .await
+                        .map_err(|error| load_error(&path, error))?;
+                    image
+                        .set_magnification(TextureFilter::Nearest)
+                        .map_err(|error| load_error(&path, error))?;
+                    game.images.insert(&name, image);
+                }
+                game.images.acquire(handle);
+                self.group.images.push(handle);
+            }
+            Job::Atlas { name, path } => {
+                let handle = game.images.reserve(&name);
+                let loaded = game.images.is_loaded(handle)
+                    && game
+                        .resources
+                        .get::<AtlasStorage>()
+                        .expect("AtlasStorage missing somehow")
+                        .contains_key(&handle);
+                if !loaded {
+                    let (atlas, sheet) = Atlas::load(gfx, &path, handle)
+                        .await
+                        .map_err(|error| AssetError::Atlas { path, error })?;
+                    game.images.insert(&name, sheet);
+                    game.resources
+                        .get_mut::<AtlasStorage>()
+                        .expect("AtlasStorage missing somehow")
+                        .insert(handle, atlas);
+                }
+                game.images.acquire(handle);
+                self.group.images.push(handle);
+            }
+            Job::Level { name, path } => {
+                let handle = assets(game).levels.reserve(&name);
+                if !assets(game).levels.is_loaded(handle) {
+                    let bytes = load_file(&path).await?;
+                    assets(game).levels.insert(&name, LevelData { bytes });
+                }
+                assets(game).levels.acquire(handle);
+                self.group.levels.push(handle);
+            }
+            Job::Sound { name, path } => {
+                let handle = assets(game).sounds.reserve(&name);
+                if !assets(game).sounds.is_loaded(handle) {
+                    let bytes = load_file(&path).await?;
+                    assets(game).sounds.insert(&name, SoundData { bytes });
+                }
+                assets(game).sounds.acquire(handle);
+                self.group.sounds.push(handle);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn finish(self) -> AssetGroup {
+        self.group
+    }
+}
+
+fn load_error(path: &str, error: quicksilver::QuicksilverError) -> AssetError {
+    AssetError::Load {
+        path: path.into(),
+        error,
+    }
+}
+
+// The guard has to be dropped before every await
+pub(super) fn assets(game: &Game) -> impl DerefMut<Target = Assets> + '_ {
+    game.resources
+        .get_mut::<Assets>()
+        .expect("Assets missing somehow")
+}
+
+async fn load_file(path: &str) -> Result<Vec<u8>, AssetError> {
+    quicksilver::load_file(path)
+        .await
+        .map_err(|error| load_error(path, error))
+}
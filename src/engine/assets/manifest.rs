@@ -0,0 +1,43 @@
+/*!
+List of the assets a scene needs, a RON file mapping the asset names to their paths:
+```ron
+(
+    images: { "player": "player.png" },
+    atlases: { "tiles": "tiles.json" },
+    levels: { "intro": "levels/intro.tmx" },
+    sounds: { "jump": "jump.ogg" },
+)
+```
+The atlases are stored under their name as images, together with their frames.
+*/
+use super::AssetError;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+
+#[derive(Debug, Default, Deserialize)]
+pub struct Manifest {
+    #[serde(default)]
+    pub images: BTreeMap<String, String>,
+    #[serde(default)]
+    pub atlases: BTreeMap<String, String>,
+    #[serde(default)]
+    pub levels: BTreeMap<String, String>,
+    #[serde(default)]
+    pub sounds: BTreeMap<String, String>,
+}
+
+impl Manifest {
+    pub fn from_ron(source: &str) -> Result<Self, AssetError> {
+        Ok(ron::de::from_str(source)?)
+    }
+
+    pub async fn load(path: &str) -> Result<Self, AssetError> {
+        let bytes = quicksilver::load_file(path)
+            .await
+            .map_err(|error| AssetError::Load {
+                path: path.into(),
+                error,
+            })?;
+        Ok(ron::de::from_bytes(&bytes)?)
+    }
+}
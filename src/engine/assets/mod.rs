@@ -0,0 +1,242 @@
+/*!
+Asset storage with typed handles and reference counting.
+
+Every asset gets a slot the first time its name is seen, the handle to the slot stays valid
+even after the asset is unloaded or replaced, it just points at nothing in the meantime.
+The scenes take a reference to everything they load and release it when they end, whatever
+isn't referenced anymore gets dropped by `unload_unused`.
+*/
+use crate::gfx::atlas::AtlasError;
+use fxhash::FxHashMap;
+use quicksilver::QuicksilverError;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::marker::PhantomData;
+
+#[cfg(all(debug_assertions, not(target_arch = "wasm32")))]
+mod hot_reload;
+mod loader;
+mod manifest;
+
+#[cfg(all(debug_assertions, not(target_arch = "wasm32")))]
+pub use self::hot_reload::HotReload;
+pub use self::loader::{AssetGroup, Loader};
+pub use self::manifest::Manifest;
+
+/// Raw level file, parsed by the level importers.
+pub struct LevelData {
+    pub bytes: Vec<u8>,
+}
+
+/// Encoded sound file, kept until there is an audio backend to decode it.
+pub struct SoundData {
+    pub bytes: Vec<u8>,
+}
+
+/// The assets that are safe to share with the systems, the images live on `Game`.
+#[derive(Default)]
+pub struct Assets {
+    pub levels: AssetStore<LevelData>,
+    pub sounds: AssetStore<SoundData>,
+}
+
+/// Reference to an asset that isn't loaded.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum MissingAsset {
+    Image(String),
+    /// The atlas of the image has no such frame
+    Frame {
+        image: String,
+        frame: String,
+    },
+}
+
+impl fmt::Display for MissingAsset {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MissingAsset::Image(image) => write!(f, "missing image `{}`", image),
+            MissingAsset::Frame { image, frame } => {
+                write!(f, "missing frame `{}` in the atlas `{}`", frame, image)
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum AssetError {
+    Load {
+        path: String,
+        error: QuicksilverError,
+    },
+    Manifest(ron::de::Error),
+    Atlas {
+        path: String,
+        error: AtlasError,
+    },
+    Missing(Vec<MissingAsset>),
+}
+
+impl fmt::Display for AssetError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AssetError::Load { path, error } => write!(f, "can't load `{}`: {}", path, error),
+            AssetError::Manifest(err) => write!(f, "asset manifest is malformed: {}", err),
+            AssetError::Atlas { path, error } => write!(f, "`{}`: {}", path, error),
+            AssetError::Missing(missing) => {
+                write!(f, "the world refers to assets that aren't loaded:")?;
+                for asset in missing {
+                    write!(f, "\n  {}", asset)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for AssetError {}
+
+impl From<ron::de::Error> for AssetError {
+    fn from(err: ron::de::Error) -> Self {
+        AssetError::Manifest(err)
+    }
+}
+
+/// Typed index into an `AssetStore`.
+pub struct Handle<T> {
+    index: u32,
+    // Handles to the assets that aren't thread safe can still go into the components
+    marker: PhantomData<fn() -> T>,
+}
+
+impl<T> Handle<T> {
+    fn new(index: usize) -> Self {
+        Self {
+            index: index as u32,
+            marker: PhantomData,
+        }
+    }
+}
+
+// Derives would require `T` to implement the traits as well
+impl<T> Clone for Handle<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for Handle<T> {}
+
+impl<T> PartialEq for Handle<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.index == other.index
+    }
+}
+
+impl<T> Eq for Handle<T> {}
+
+impl<T> Hash for Handle<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.index.hash(state);
+    }
+}
+
+impl<T> fmt::Debug for Handle<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Handle({})", self.index)
+    }
+}
+
+struct Slot<T> {
+    name: String,
+    asset: Option<T>,
+    refs: u32,
+}
+
+pub struct AssetStore<T> {
+    slots: Vec<Slot<T>>,
+    names: FxHashMap<String, Handle<T>>,
+}
+
+impl<T> Default for AssetStore<T> {
+    fn default() -> Self {
+        Self {
+            slots: Vec::new(),
+            names: FxHashMap::default(),
+        }
+    }
+}
+
+impl<T> AssetStore<T> {
+    /// Handle for the name, with an empty slot if the name is new.
+    pub fn reserve(&mut self, name: &str) -> Handle<T> {
+        if let Some(handle) = self.names.get(name) {
+            return *handle;
+        }
+        let handle = Handle::new(self.slots.len());
+        self.slots.push(Slot {
+            name: name.into(),
+            asset: None,
+            refs: 0,
+        });
+        self.names.insert(name.into(), handle);
+        handle
+    }
+    pub fn handle(&self, name: &str) -> Option<Handle<T>> {
+        self.names.get(name).copied()
+    }
+    pub fn name(&self, handle: Handle<T>) -> &str {
+        &self.slots[handle.index as usize].name
+    }
+    pub fn get(&self, handle: Handle<T>) -> Option<&T> {
+        self.slots[handle.index as usize].asset.as_ref()
+    }
+    pub fn get_mut(&mut self, handle: Handle<T>) -> Option<&mut T> {
+        self.slots[handle.index as usize].asset.as_mut()
+    }
+    pub fn is_loaded(&self, handle: Handle<T>) -> bool {
+        self.get(handle).is_some()
+    }
+
+    /// Store the asset under the name, replacing the old one, the existing handles see the new
+    /// asset. Doesn't take a reference.
+    pub fn insert(&mut self, name: &str, asset: T) -> Handle<T> {
+        let handle = self.reserve(name);
+        self.slots[handle.index as usize].asset = Some(asset);
+        handle
+    }
+
+    pub fn acquire(&mut self, handle: Handle<T>) {
+        self.slots[handle.index as usize].refs += 1;
+    }
+    pub fn release(&mut self, handle: Handle<T>) {
+        let slot = &mut self.slots[handle.index as usize];
+        if slot.refs == 0 {
+            warn!("Asset `{}` released more times than acquired", slot.name);
+        }
+        slot.refs = slot.refs.saturating_sub(1);
+    }
+    pub fn refs(&self, handle: Handle<T>) -> u32 {
+        self.slots[handle.index as usize].refs
+    }
+
+    /// Drop the loaded assets without any reference, returns their handles.
+    pub fn unload_unused(&mut self) -> Vec<Handle<T>> {
+        self.slots
+            .iter_mut()
+            .enumerate()
+            .filter(|(_, slot)| slot.refs == 0 && slot.asset.is_some())
+            .map(|(index, slot)| {
+                slot.asset = None;
+                Handle::new(index)
+            })
+            .collect()
+    }
+
+    /// The loaded assets.
+    pub fn iter(&self) -> impl Iterator<Item = (Handle<T>, &str, &T)> {
+        self.slots.iter().enumerate().filter_map(|(index, slot)| {
+            let asset = slot.asset.as_ref()?;
+            Some((Handle::new(index), slot.name.as_str(), asset))
+        })
+    }
+}
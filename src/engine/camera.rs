@@ -0,0 +1,202 @@
+use crate::engine::components::Position;
+use crate::game::Resolution;
+use crate::phx::Velocity;
+use crate::UPDATE_RATE;
+use legion::prelude::*;
+use quicksilver::geom::{Rectangle, Vector};
+
+/// What part of the world is visible, updated once per tick and applied by `gfx::render`.
+pub struct Camera {
+    /// Center of the view in world coordinates
+    pub center: Vector,
+    /// Entity with `Position` to follow, optionally with `Velocity` for the look-ahead
+    pub target: Option<Entity>,
+    /// Size of the area around the center the target can move in without moving the camera
+    pub deadzone: Vector,
+    /// How many seconds of the target velocity the camera looks ahead
+    pub look_ahead: f32,
+    /// Part of the remaining distance covered each tick, 1 snaps immediately
+    pub smoothing: f32,
+    /// Level area the view never leaves
+    pub bounds: Option<Rectangle>,
+    /// Offset of the strongest shake, in game pixels
+    pub max_shake: Vector,
+    /// How much trauma wears off per second
+    pub trauma_decay: f32,
+    trauma: f32,
+    shake_offset: Vector,
+    ticks: u32,
+}
+
+impl Camera {
+    pub fn new(center: Vector) -> Self {
+        Self {
+            center,
+            target: None,
+            deadzone: Vector::new(32., 24.),
+            look_ahead: 0.25,
+            smoothing: 0.15,
+            bounds: None,
+            max_shake: Vector::new(8., 6.),
+            trauma_decay: 1.5,
+            trauma: 0.,
+            shake_offset: Vector::ZERO,
+            ticks: 0,
+        }
+    }
+    /// Shake the screen, trauma stacks up to 1 and the shake grows with its square.
+    pub fn add_trauma(&mut self, amount: f32) {
+        self.trauma = (self.trauma + amount).max(0.).min(1.);
+    }
+    pub fn trauma(&self) -> f32 {
+        self.trauma
+    }
+    /// Top left corner of the view of the given size, including the shake.
+    pub fn origin(&self, view_size: Vector) -> Vector {
+        self.center - view_size / 2 + self.shake_offset
+    }
+    /// Deadzone in world coordinates.
+    pub fn deadzone_area(&self) -> Rectangle {
+        Rectangle::new(self.center - self.deadzone / 2, self.deadzone)
+    }
+
+    /// Advance the camera by one tick towards the target focus point.
+    pub fn update(&mut self, focus: Option<Vector>, view_size: Vector) {
+        if let Some(focus) = focus {
+            let desired = self.deadzone_follow(focus);
+            self.center = self.center + (desired - self.center) * self.smoothing;
+        }
+        if let Some(bounds) = self.bounds {
+            self.center = clamp_to_bounds(self.center, view_size, &bounds);
+        }
+
+        self.ticks = self.ticks.wrapping_add(1);
+        self.trauma = (self.trauma - self.trauma_decay / UPDATE_RATE).max(0.);
+        let shake = self.trauma * self.trauma;
+        // Cheap deterministic noise, so the replays shake the same way
+        let t = self.ticks as f32 / UPDATE_RATE;
+        let noise = Vector::new(
+            (t * 47.).sin() * (t * 13.).cos(),
+            (t * 53. + 1.3).sin() * (t * 17.).cos(),
+        );
+        self.shake_offset = Vector::new(
+            self.max_shake.x * shake * noise.x,
+            self.max_shake.y * shake * noise.y,
+        );
+    }
+
+    // Center that keeps the focus at the edge of the deadzone once it leaves it
+    fn deadzone_follow(&self, focus: Vector) -> Vector {
+        let half = self.deadzone / 2;
+        let axis = |center: f32, focus: f32, half: f32| {
+            if focus < center - half {
+                focus + half
+            } else if focus > center + half {
+                focus - half
+            } else {
+                center
+            }
+        };
+        Vector::new(
+            axis(self.center.x, focus.x, half.x),
+            axis(self.center.y, focus.y, half.y),
+        )
+    }
+}
+
+fn clamp_to_bounds(center: Vector, view_size: Vector, bounds: &Rectangle) -> Vector {
+    let axis = |center: f32, view: f32, min: f32, size: f32| {
+        if size <= view {
+            // Level smaller than the view, keep it in the middle
+            min + size / 2.
+        } else {
+            center.max(min + view / 2.).min(min + size - view / 2.)
+        }
+    };
+    Vector::new(
+        axis(center.x, view_size.x, bounds.x(), bounds.width()),
+        axis(center.y, view_size.y, bounds.y(), bounds.height()),
+    )
+}
+
+pub fn camera_follow() -> Box<dyn Schedulable> {
+    SystemBuilder::new("camera_follow")
+        .read_resource::<Resolution>()
+        .write_resource::<Camera>()
+        .read_component::<Position>()
+        .read_component::<Velocity>()
+        .build(move |_, world, (resolution, camera), _| {
+            let focus = camera.target.and_then(|target| {
+                let position = world.get_component::<Position>(target)?;
+                let velocity = world
+                    .get_component::<Velocity>(target)
+                    .map_or(Vector::ZERO, |velocity| velocity.src);
+                Some(position.src + velocity * camera.look_ahead)
+            });
+            camera.update(focus, resolution.size());
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VIEW: Vector = Vector { x: 320., y: 180. };
+
+    // Camera in the middle of the view that snaps to where it wants to be
+    fn snapping_camera() -> Camera {
+        let mut camera = Camera::new(VIEW / 2);
+        camera.smoothing = 1.;
+        camera
+    }
+
+    #[test]
+    fn deadzone_holds_the_camera_until_the_target_leaves_it() {
+        let mut camera = snapping_camera();
+        // Deadzone of 32x24 around (160, 90)
+        camera.update(Some(Vector::new(175., 80.)), VIEW);
+        assert_eq!(camera.center, Vector::new(160., 90.));
+
+        camera.update(Some(Vector::new(200., 50.)), VIEW);
+        assert_eq!(camera.center, Vector::new(184., 62.));
+        assert_eq!(camera.deadzone_area().pos, Vector::new(168., 50.));
+    }
+
+    #[test]
+    fn bounds_keep_the_view_inside_the_level() {
+        let mut camera = snapping_camera();
+        camera.bounds = Some(Rectangle::new(Vector::ZERO, Vector::new(1000., 500.)));
+        camera.center = Vector::new(10., 490.);
+        camera.update(None, VIEW);
+        assert_eq!(camera.center, Vector::new(160., 410.));
+    }
+
+    #[test]
+    fn level_smaller_than_the_view_is_centered() {
+        let mut camera = snapping_camera();
+        camera.bounds = Some(Rectangle::new(
+            Vector::new(100., 40.),
+            Vector::new(200., 400.),
+        ));
+        camera.update(Some(Vector::new(0., 1000.)), VIEW);
+        // Only the width is smaller, the height still follows the target down to the bottom
+        assert_eq!(camera.center, Vector::new(200., 350.));
+    }
+
+    #[test]
+    fn trauma_decays_to_zero() {
+        let mut camera = snapping_camera();
+        camera.add_trauma(0.7);
+        camera.add_trauma(0.7);
+        assert_eq!(camera.trauma(), 1.);
+
+        // A tick more than it should take, the float steps don't add up to exactly 1
+        let ticks = (UPDATE_RATE / camera.trauma_decay).ceil() as usize + 1;
+        for _ in 0..ticks {
+            camera.update(None, VIEW);
+        }
+        assert_eq!(camera.trauma(), 0.);
+        // No shake left, the view is right where the camera is
+        assert_eq!(camera.origin(VIEW), Vector::ZERO);
+    }
+}
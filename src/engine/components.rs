@@ -1,6 +1,5 @@
 use crate::UPDATE_RATE;
-use quicksilver::geom::{Rectangle, Vector};
-use quicksilver::graphics::Image;
+use quicksilver::geom::Vector;
 use std::ops::Mul;
 // TODO: Move to phx/mod
 // Position of the entity
@@ -20,20 +19,3 @@ impl Mul<crate::phx::Velocity> for Position {
         }
     }
 }
-
-// Sprites are referenced by their center
-// TODO: Hold the actual subrectangle that is supposed to be drawn
-// TODO: Move to gfx/mod
-pub struct Sprite {
-    pub src: String,
-    pub offset: Vector,
-}
-
-impl Sprite {
-    pub fn new(name: String, image: &Image) -> Self {
-        Self {
-            src: name,
-            offset: -image.size() / 2.,
-        }
-    }
-}
@@ -0,0 +1,328 @@
+/*!
+Loading and saving the bindings from a human-editable RON file.
+
+The file maps the action names to the list of their bindings:
+```ron
+{
+    "Jump": ["Space"],
+    "Left": ["A", "Left"],
+    "Dash": ["Shift+Space", "Pad:East"],
+    "Right": ["D", "Pad:LeftStickX+"],
+}
+```
+*/
+use super::keys::{binding_from_name, binding_name};
+use super::{Action, ButtonsState};
+use std::collections::BTreeMap;
+use std::fmt;
+
+type BindingsFile = BTreeMap<String, Vec<String>>;
+
+/// Single problem found in the bindings config.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BindingProblem {
+    /// The game doesn't have an action with this name
+    UnknownAction(String),
+    /// The key name (or one of the modifiers) is not recognized
+    UnknownKey { action: String, key: String },
+    /// The same binding is used by two different actions
+    Conflict {
+        binding: String,
+        first: String,
+        second: String,
+    },
+    /// The action can't be triggered at all
+    Unbound(String),
+}
+
+impl fmt::Display for BindingProblem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BindingProblem::UnknownAction(action) => write!(f, "unknown action `{}`", action),
+            BindingProblem::UnknownKey { action, key } => {
+                write!(f, "unknown key `{}` bound to `{}`", key, action)
+            }
+            BindingProblem::Conflict {
+                binding,
+                first,
+                second,
+            } => write!(
+                f,
+                "`{}` is bound to both `{}` and `{}`",
+                binding, first, second
+            ),
+            BindingProblem::Unbound(action) => write!(f, "`{}` has no binding", action),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(std::io::Error),
+    Parse(ron::de::Error),
+    Serialize(ron::ser::Error),
+    /// The file is well formed, but the bindings in it are not usable
+    Invalid(Vec<BindingProblem>),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::Io(err) => write!(f, "bindings config io error: {}", err),
+            ConfigError::Parse(err) => write!(f, "bindings config is malformed: {}", err),
+            ConfigError::Serialize(err) => write!(f, "bindings config can't be written: {}", err),
+            ConfigError::Invalid(problems) => {
+                write!(f, "bindings config is invalid:")?;
+                for problem in problems {
+                    write!(f, "\n  {}", problem)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl From<std::io::Error> for ConfigError {
+    fn from(err: std::io::Error) -> Self {
+        ConfigError::Io(err)
+    }
+}
+
+impl From<ron::de::Error> for ConfigError {
+    fn from(err: ron::de::Error) -> Self {
+        ConfigError::Parse(err)
+    }
+}
+
+impl From<ron::ser::Error> for ConfigError {
+    fn from(err: ron::ser::Error) -> Self {
+        ConfigError::Serialize(err)
+    }
+}
+
+fn action_name<A: Action>(action: A) -> String {
+    format!("{:?}", action)
+}
+
+impl<A: Action> ButtonsState<A> {
+    /// Parse the bindings, reporting every problem found instead of stopping on the first one.
+    ///
+    /// The actions missing from the file, like the ones added to the game since it was written,
+    /// get the bindings of `defaults` that no other action uses yet.
+    pub fn from_ron(source: &str, defaults: &Self) -> Result<Self, ConfigError> {
+        let file: BindingsFile = ron::de::from_str(source)?;
+        let mut buttons_state = Self::default();
+        let mut problems = Vec::new();
+        let mut listed = Vec::new();
+
+        for (name, bindings) in file.iter() {
+            let action = match buttons_state
+                .bindings
+                .iter()
+                .map(|(action, _)| action)
+                .find(|action| action_name(*action) == *name)
+            {
+                Some(action) => action,
+                None => {
+                    problems.push(BindingProblem::UnknownAction(name.clone()));
+                    continue;
+                }
+            };
+            listed.push(action);
+            for binding_str in bindings {
+                match binding_from_name(binding_str) {
+                    Some(binding) => buttons_state.bind(action, binding),
+                    None => problems.push(BindingProblem::UnknownKey {
+                        action: name.clone(),
+                        key: binding_str.clone(),
+                    }),
+                }
+            }
+        }
+        let missing: Vec<A> = defaults
+            .bindings
+            .iter()
+            .map(|(action, _)| action)
+            .filter(|action| !listed.contains(action))
+            .collect();
+        for action in missing.iter().copied() {
+            warn!(
+                "`{}` isn't in the bindings config, using its default bindings",
+                action_name(action)
+            );
+            for binding in defaults.bindings(action) {
+                let taken = buttons_state
+                    .bindings
+                    .values()
+                    .any(|state| state.bindings.contains(binding));
+                if !taken {
+                    buttons_state.bind(action, *binding);
+                }
+            }
+        }
+        problems.extend(buttons_state.problems());
+
+        if problems.is_empty() {
+            // Written back with the missing actions filled in
+            buttons_state.changed = !missing.is_empty();
+            Ok(buttons_state)
+        } else {
+            Err(ConfigError::Invalid(problems))
+        }
+    }
+
+    /// Format the bindings, failing on those without a name instead of dropping them.
+    pub fn to_ron(&self) -> Result<String, ConfigError> {
+        let mut file = BindingsFile::new();
+        let mut problems = Vec::new();
+        for (action, state) in self.bindings.iter() {
+            let mut bindings = Vec::new();
+            for binding in state.bindings.iter() {
+                match binding_name(binding) {
+                    Some(name) => bindings.push(name),
+                    None => problems.push(BindingProblem::UnknownKey {
+                        action: action_name(action),
+                        key: format!("{:?}", binding),
+                    }),
+                }
+            }
+            file.insert(action_name(action), bindings);
+        }
+        if !problems.is_empty() {
+            return Err(ConfigError::Invalid(problems));
+        }
+        Ok(ron::ser::to_string_pretty(
+            &file,
+            ron::ser::PrettyConfig::default(),
+        )?)
+    }
+
+    /// Conflicting bindings and actions that can't be triggered.
+    pub fn problems(&self) -> Vec<BindingProblem> {
+        let mut problems = Vec::new();
+        let actions: Vec<_> = self.bindings.iter().collect();
+        for (i, (action, state)) in actions.iter().enumerate() {
+            if state.bindings.is_empty() {
+                problems.push(BindingProblem::Unbound(action_name(*action)));
+            }
+            for (other, other_state) in actions.iter().skip(i + 1) {
+                for binding in state
+                    .bindings
+                    .iter()
+                    .filter(|binding| other_state.bindings.contains(binding))
+                {
+                    problems.push(BindingProblem::Conflict {
+                        binding: binding_name(binding).unwrap_or_else(|| format!("{:?}", binding)),
+                        first: action_name(*action),
+                        second: action_name(*other),
+                    });
+                }
+            }
+        }
+        problems
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn load(path: impl AsRef<std::path::Path>, defaults: &Self) -> Result<Self, ConfigError> {
+        let source = std::fs::read_to_string(path)?;
+        Self::from_ron(&source, defaults)
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn save(&self, path: impl AsRef<std::path::Path>) -> Result<(), ConfigError> {
+        std::fs::write(path, self.to_ron()?)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::input::{AxisDirection, Binding, Modifiers};
+    use enum_map::Enum;
+    use quicksilver::lifecycle::{GamepadAxis, GamepadButton, Key, MouseButton};
+
+    #[derive(Debug, Enum, Clone, Copy, PartialEq)]
+    enum Act {
+        Jump,
+        Left,
+        Dash,
+    }
+
+    fn defaults() -> ButtonsState<Act> {
+        let mut buttons_state = ButtonsState::default();
+        buttons_state.bind(Act::Jump, Key::Space);
+        buttons_state.bind(Act::Left, Key::A);
+        buttons_state.bind(Act::Dash, Key::LShift);
+        buttons_state.bind(Act::Dash, GamepadButton::East);
+        buttons_state
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut buttons_state = ButtonsState::default();
+        buttons_state.bind(Act::Jump, Key::Space);
+        buttons_state.bind(Act::Jump, MouseButton::Left);
+        buttons_state.bind(Act::Left, Key::A);
+        buttons_state.bind(
+            Act::Left,
+            Binding::axis(GamepadAxis::LeftStickX, AxisDirection::Negative),
+        );
+        buttons_state.bind(
+            Act::Dash,
+            Binding::chord(Modifiers::SHIFT | Modifiers::CTRL, Key::Space),
+        );
+
+        let source = buttons_state.to_ron().unwrap();
+        let loaded = ButtonsState::from_ron(&source, &defaults()).unwrap();
+        for action in [Act::Jump, Act::Left, Act::Dash].iter().copied() {
+            assert_eq!(loaded.bindings(action), buttons_state.bindings(action));
+        }
+        assert!(source.contains("\"Shift+Ctrl+Space\""));
+        assert!(source.contains("\"Pad:LeftStickX-\""));
+    }
+
+    #[test]
+    fn unknown_names_are_reported() {
+        let source = r#"{ "Jump": ["Space", "Hyper+Space"], "Left": ["A"], "Dash": ["Pad:Turbo"], "Fly": [] }"#;
+        match ButtonsState::from_ron(source, &defaults()) {
+            Err(ConfigError::Invalid(problems)) => {
+                assert!(problems.contains(&BindingProblem::UnknownKey {
+                    action: "Jump".into(),
+                    key: "Hyper+Space".into(),
+                }));
+                assert!(problems.contains(&BindingProblem::UnknownKey {
+                    action: "Dash".into(),
+                    key: "Pad:Turbo".into(),
+                }));
+                assert!(problems.contains(&BindingProblem::UnknownAction("Fly".into())));
+            }
+            other => panic!("unexpected {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn missing_action_takes_the_defaults() {
+        // Written before there was a dash, Shift is already taken by the jump
+        let source = r#"{ "Jump": ["LShift"], "Left": ["Left"] }"#;
+        let mut loaded = ButtonsState::from_ron(source, &defaults()).unwrap();
+        assert_eq!(loaded.bindings(Act::Jump), &[Binding::from(Key::LShift)]);
+        assert_eq!(loaded.bindings(Act::Left), &[Binding::from(Key::Left)]);
+        assert_eq!(
+            loaded.bindings(Act::Dash),
+            &[Binding::from(GamepadButton::East)]
+        );
+        assert!(loaded.take_changed());
+
+        // Listed without any binding is a choice of the player, and a problem
+        let source = r#"{ "Jump": ["Space"], "Left": [], "Dash": ["E"] }"#;
+        match ButtonsState::from_ron(source, &defaults()) {
+            Err(ConfigError::Invalid(problems)) => {
+                assert_eq!(problems, vec![BindingProblem::Unbound("Left".into())])
+            }
+            other => panic!("unexpected {:?}", other.map(|_| ())),
+        }
+    }
+}
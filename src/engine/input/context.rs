@@ -0,0 +1,166 @@
+/*!
+Stack of input contexts, so the menus, gameplay and dialogs don't fight over the same keys.
+
+Every context lists the actions it consumes. A system reads the input through the view of its
+context and doesn't see the actions consumed by any context above it. Once the context above is
+popped, the presses it consumed are forgotten instead of showing up in the history below.
+*/
+use super::{Action, ButtonsState, History};
+
+#[derive(Debug, Clone)]
+pub struct InputContext<A> {
+    pub name: &'static str,
+    consumes: Vec<A>,
+}
+
+impl<A: Action> InputContext<A> {
+    /// Context that doesn't hide anything from the ones below.
+    pub fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            consumes: Vec::new(),
+        }
+    }
+    pub fn consuming(mut self, actions: &[A]) -> Self {
+        self.consumes.extend_from_slice(actions);
+        self
+    }
+    pub fn consumes(&self, action: A) -> bool {
+        self.consumes.contains(&action)
+    }
+}
+
+/// Input as seen by one of the contexts.
+pub struct InputView<'a, A: Action> {
+    buttons_state: &'a ButtonsState<A>,
+    // Contexts above the viewing one, `None` if the context is not on the stack
+    above: Option<&'a [InputContext<A>]>,
+}
+
+impl<'a, A: Action> InputView<'a, A> {
+    /// History of the action, `None` if it's hidden from this context.
+    pub fn history(&self, action: A) -> Option<&'a History> {
+        let above = self.above?;
+        if above.iter().any(|context| context.consumes(action)) {
+            None
+        } else {
+            Some(self.buttons_state.history(action))
+        }
+    }
+    pub fn axis(&self, action: A) -> f32 {
+        self.history(action)
+            .map_or(0., |_| self.buttons_state.axis(action))
+    }
+    pub fn is_pressed(&self, action: A) -> bool {
+        self.history(action).map_or(false, History::is_pressed)
+    }
+    pub fn pressed(&self, action: A) -> bool {
+        self.history(action).map_or(false, History::pressed)
+    }
+    pub fn released(&self, action: A) -> bool {
+        self.history(action).map_or(false, History::released)
+    }
+    pub fn held_for(&self, action: A, frames: usize) -> bool {
+        self.history(action)
+            .map_or(false, |history| history.held_for(frames))
+    }
+    pub fn pressed_within(&self, action: A, frames: usize) -> bool {
+        self.history(action)
+            .map_or(false, |history| history.pressed_within(frames))
+    }
+    pub fn double_tapped(&self, action: A, window: usize) -> bool {
+        self.history(action)
+            .map_or(false, |history| history.double_tapped(window))
+    }
+    pub fn released_after_hold(&self, action: A, min_frames: usize) -> bool {
+        self.history(action)
+            .map_or(false, |history| history.released_after_hold(min_frames))
+    }
+}
+
+impl<A: Action> ButtonsState<A> {
+    pub fn push_context(&mut self, context: InputContext<A>) {
+        self.contexts.push(context);
+    }
+    pub fn pop_context(&mut self) -> Option<InputContext<A>> {
+        let context = self.contexts.pop()?;
+        // The contexts below never saw these presses, they must not turn up buffered
+        for (action, state) in self.bindings.iter_mut() {
+            if context.consumes(action) {
+                state.history.forget();
+            }
+        }
+        Some(context)
+    }
+    pub fn top_context(&self) -> Option<&InputContext<A>> {
+        self.contexts.last()
+    }
+    /// Input visible to the context, a context missing from the stack sees nothing.
+    pub fn view(&self, name: &str) -> InputView<'_, A> {
+        let above = self
+            .contexts
+            .iter()
+            .rposition(|context| context.name == name)
+            .map(|index| &self.contexts[index + 1..]);
+        InputView {
+            buttons_state: self,
+            above,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::input::gamepad::FakeGamepad;
+    use crate::engine::input::{Cursor, Devices};
+    use enum_map::Enum;
+    use quicksilver::geom::Vector;
+    use quicksilver::lifecycle::{EventCache, GamepadButton};
+
+    #[derive(Debug, Enum, Clone, Copy, PartialEq)]
+    enum Pad {
+        Jump,
+        Dash,
+    }
+
+    fn update(buttons_state: &mut ButtonsState<Pad>, gamepad: &FakeGamepad) {
+        let keyboard = EventCache::default();
+        let cursor = Cursor::new(Vector::new(1., 1.));
+        buttons_state.update(&Devices {
+            keyboard: &keyboard,
+            gamepad,
+            cursor: &cursor,
+        });
+    }
+
+    #[test]
+    fn consumed_presses_stay_hidden_after_pop() {
+        let mut buttons_state = ButtonsState::default();
+        buttons_state.bind(Pad::Jump, GamepadButton::South);
+        buttons_state.bind(Pad::Dash, GamepadButton::West);
+        buttons_state.push_context(InputContext::new("gameplay"));
+        buttons_state.push_context(InputContext::new("menu").consuming(&[Pad::Jump]));
+        let mut gamepad = FakeGamepad::default();
+
+        gamepad.press(GamepadButton::South);
+        gamepad.press(GamepadButton::West);
+        update(&mut buttons_state, &gamepad);
+        assert!(buttons_state.view("menu").pressed(Pad::Jump));
+        assert!(!buttons_state.view("gameplay").pressed(Pad::Jump));
+        assert!(buttons_state.view("gameplay").pressed(Pad::Dash));
+
+        gamepad.release(GamepadButton::South);
+        update(&mut buttons_state, &gamepad);
+        buttons_state.pop_context();
+        update(&mut buttons_state, &gamepad);
+        let gameplay = buttons_state.view("gameplay");
+        assert!(!gameplay.pressed_within(Pad::Jump, 10));
+        // Actions the menu didn't consume keep their history
+        assert!(gameplay.pressed_within(Pad::Dash, 10));
+
+        gamepad.press(GamepadButton::South);
+        update(&mut buttons_state, &gamepad);
+        assert!(buttons_state.view("gameplay").pressed(Pad::Jump));
+    }
+}
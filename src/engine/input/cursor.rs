@@ -0,0 +1,131 @@
+/*!
+Mouse cursor and buttons, mapped from the window into the game space.
+
+The content is letterboxed and scaled by `ResizeStrategy`, so the cursor has to go through
+the current viewport to land in the view, and through the camera to land in the world.
+*/
+use crate::engine::Camera;
+use quicksilver::geom::{Rectangle, Vector};
+use quicksilver::lifecycle::{Event, MouseButton};
+
+/// Map the point in window pixels into the view, `None` if it's outside the viewport.
+pub fn window_to_view(viewport: &Rectangle, view_size: Vector, point: Vector) -> Option<Vector> {
+    let relative = point - viewport.pos;
+    if relative.x < 0.
+        || relative.y < 0.
+        || relative.x >= viewport.width()
+        || relative.y >= viewport.height()
+    {
+        return None;
+    }
+    Some(Vector::new(
+        relative.x * view_size.x / viewport.width(),
+        relative.y * view_size.y / viewport.height(),
+    ))
+}
+
+pub struct Cursor {
+    // In physical pixels, same as the viewport
+    window: Vector,
+    // In game pixels, relative to the top left corner of the view
+    view: Option<Vector>,
+    viewport: Rectangle,
+    view_size: Vector,
+    // Only the presses that started inside the game area
+    buttons: Vec<MouseButton>,
+    clicked_outside: bool,
+}
+
+impl Cursor {
+    pub fn new(view_size: Vector) -> Self {
+        Self {
+            window: Vector::ZERO,
+            view: None,
+            viewport: Rectangle::new_sized(view_size),
+            view_size,
+            buttons: Vec::new(),
+            clicked_outside: false,
+        }
+    }
+    /// Call whenever the viewport changes, so the cursor keeps pointing at the right place.
+    pub fn set_viewport(&mut self, viewport: Rectangle, view_size: Vector) {
+        self.viewport = viewport;
+        self.view_size = view_size;
+        self.view = window_to_view(&self.viewport, self.view_size, self.window);
+    }
+    pub fn process_event(&mut self, event: &Event, scale_factor: f32) {
+        match event {
+            Event::PointerMoved(moved) => {
+                // The location is in logical pixels, the viewport in physical ones
+                self.window = moved.location() * scale_factor;
+                self.view = window_to_view(&self.viewport, self.view_size, self.window);
+            }
+            Event::PointerInput(input) => {
+                let button = input.button();
+                if !input.is_down() {
+                    self.buttons.retain(|b| *b != button);
+                } else if self.view.is_some() {
+                    self.buttons.retain(|b| *b != button);
+                    self.buttons.push(button);
+                } else {
+                    self.clicked_outside = true;
+                }
+            }
+            Event::PointerLeft(_) => self.view = None,
+            _ => {}
+        }
+    }
+    pub fn window_position(&self) -> Vector {
+        self.window
+    }
+    /// Position in the view, `None` when the cursor is over the black bars.
+    pub fn view_position(&self) -> Option<Vector> {
+        self.view
+    }
+    /// Put the cursor where the replay had it, until the real one moves again.
+    pub fn set_view_position(&mut self, view: Option<Vector>) {
+        self.view = view;
+    }
+    /// Position in the world seen through the camera, `None` when the cursor is over the black
+    /// bars.
+    pub fn world_position(&self, camera: &Camera) -> Option<Vector> {
+        let origin = camera.origin(self.view_size);
+        self.view.map(|view| view + origin)
+    }
+    /// Held down, after being pressed inside the game area.
+    pub fn button(&self, button: MouseButton) -> bool {
+        self.buttons.contains(&button)
+    }
+    /// Whether there was a click outside the game area since the last call.
+    pub fn take_clicked_outside(&mut self) -> bool {
+        std::mem::replace(&mut self.clicked_outside, false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn world_position_follows_the_camera() {
+        let view_size = Vector::new(320., 180.);
+        let viewport = Rectangle::new(Vector::new(60., 0.), Vector::new(640., 360.));
+        let mut cursor = Cursor::new(view_size);
+        // Middle of the view, upscaled 2x with black bars on the sides
+        cursor.window = Vector::new(380., 180.);
+        cursor.set_viewport(viewport, view_size);
+
+        let camera = Camera::new(view_size / 2);
+        assert_eq!(cursor.world_position(&camera), Some(Vector::new(160., 90.)));
+        let camera = Camera::new(Vector::new(500., 300.));
+        assert_eq!(
+            cursor.world_position(&camera),
+            Some(Vector::new(500., 300.))
+        );
+
+        // Over the black bar
+        cursor.window = Vector::new(20., 180.);
+        cursor.set_viewport(viewport, view_size);
+        assert_eq!(cursor.world_position(&camera), None);
+    }
+}
@@ -0,0 +1,258 @@
+/*!
+Gamepad side of the input.
+
+`ButtonsState` reads the controller through the `GamepadBackend` trait, so the game uses
+`GamepadState` fed from the event stream, while headless tests can drive a `FakeGamepad`.
+*/
+use quicksilver::lifecycle::{Event, GamepadAxis, GamepadButton, GamepadId};
+
+/// Axis value past which an axis binding counts as a pressed button.
+pub const AXIS_PRESS_THRESHOLD: f32 = 0.5;
+/// Deadzone used for every axis that doesn't have its own.
+pub const DEFAULT_DEADZONE: f32 = 0.2;
+
+/// Which half of the axis the binding reacts to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AxisDirection {
+    Negative,
+    Positive,
+}
+
+impl AxisDirection {
+    /// Part of the raw axis value pointing in this direction, always positive.
+    pub fn of(self, value: f32) -> f32 {
+        match self {
+            AxisDirection::Negative => (-value).max(0.),
+            AxisDirection::Positive => value.max(0.),
+        }
+    }
+}
+
+pub trait GamepadBackend {
+    fn button(&self, button: GamepadButton) -> bool;
+    /// Raw value in the -1..=1 range, before the deadzone is applied.
+    fn axis(&self, axis: GamepadAxis) -> f32;
+}
+
+/// Per-axis deadzones, values inside it are treated as 0 and the rest is rescaled to 0..=1.
+#[derive(Debug, Clone)]
+pub struct Deadzones {
+    default: f32,
+    overrides: Vec<(GamepadAxis, f32)>,
+}
+
+impl Default for Deadzones {
+    fn default() -> Self {
+        Self {
+            default: DEFAULT_DEADZONE,
+            overrides: Vec::new(),
+        }
+    }
+}
+
+impl Deadzones {
+    pub fn set_default(&mut self, deadzone: f32) {
+        self.default = deadzone.max(0.).min(0.99);
+    }
+    pub fn set(&mut self, axis: GamepadAxis, deadzone: f32) {
+        let deadzone = deadzone.max(0.).min(0.99);
+        match self.overrides.iter_mut().find(|(a, _)| *a == axis) {
+            Some((_, value)) => *value = deadzone,
+            None => self.overrides.push((axis, deadzone)),
+        }
+    }
+    pub fn get(&self, axis: GamepadAxis) -> f32 {
+        self.overrides
+            .iter()
+            .find(|(a, _)| *a == axis)
+            .map_or(self.default, |(_, deadzone)| *deadzone)
+    }
+    /// Magnitude (0..=1) after applying the deadzone.
+    pub fn apply(&self, axis: GamepadAxis, magnitude: f32) -> f32 {
+        let deadzone = self.get(axis);
+        if magnitude <= deadzone {
+            0.
+        } else {
+            ((magnitude - deadzone) / (1. - deadzone)).min(1.)
+        }
+    }
+}
+
+/// State of the first gamepad that sent any input, built from the event stream.
+#[derive(Default)]
+pub struct GamepadState {
+    id: Option<GamepadId>,
+    buttons: Vec<GamepadButton>,
+    axes: Vec<(GamepadAxis, f32)>,
+}
+
+impl GamepadState {
+    pub fn process_event(&mut self, event: &Event) {
+        match event {
+            Event::GamepadButton(button_event) => {
+                if !self.is_tracked(button_event.id()) {
+                    return;
+                }
+                let button = button_event.button();
+                self.buttons.retain(|b| *b != button);
+                if button_event.is_down() {
+                    self.buttons.push(button);
+                }
+            }
+            Event::GamepadAxis(axis_event) => {
+                if !self.is_tracked(axis_event.id()) {
+                    return;
+                }
+                let axis = axis_event.axis();
+                self.axes.retain(|(a, _)| *a != axis);
+                self.axes.push((axis, axis_event.value()));
+            }
+            Event::GamepadDisconnected(gamepad_event) => {
+                if self.id.as_ref() == Some(&gamepad_event.id()) {
+                    *self = Self::default();
+                }
+            }
+            _ => {}
+        }
+    }
+    fn is_tracked(&mut self, id: GamepadId) -> bool {
+        match &self.id {
+            Some(tracked) => *tracked == id,
+            None => {
+                self.id = Some(id);
+                true
+            }
+        }
+    }
+}
+
+impl GamepadBackend for GamepadState {
+    fn button(&self, button: GamepadButton) -> bool {
+        self.buttons.contains(&button)
+    }
+    fn axis(&self, axis: GamepadAxis) -> f32 {
+        self.axes
+            .iter()
+            .find(|(a, _)| *a == axis)
+            .map_or(0., |(_, value)| *value)
+    }
+}
+
+/// Controller set by hand, for driving the input in tests without a real device.
+#[cfg(test)]
+#[derive(Default, Clone)]
+pub struct FakeGamepad {
+    buttons: Vec<GamepadButton>,
+    axes: Vec<(GamepadAxis, f32)>,
+}
+
+#[cfg(test)]
+impl FakeGamepad {
+    pub fn press(&mut self, button: GamepadButton) {
+        self.release(button);
+        self.buttons.push(button);
+    }
+    pub fn release(&mut self, button: GamepadButton) {
+        self.buttons.retain(|b| *b != button);
+    }
+    pub fn set_axis(&mut self, axis: GamepadAxis, value: f32) {
+        self.axes.retain(|(a, _)| *a != axis);
+        self.axes.push((axis, value.max(-1.).min(1.)));
+    }
+}
+
+#[cfg(test)]
+impl GamepadBackend for FakeGamepad {
+    fn button(&self, button: GamepadButton) -> bool {
+        self.buttons.contains(&button)
+    }
+    fn axis(&self, axis: GamepadAxis) -> f32 {
+        self.axes
+            .iter()
+            .find(|(a, _)| *a == axis)
+            .map_or(0., |(_, value)| *value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::input::{Binding, ButtonsState, Cursor, Devices};
+    use enum_map::Enum;
+    use quicksilver::geom::Vector;
+    use quicksilver::lifecycle::EventCache;
+
+    #[derive(Debug, Enum, Clone, Copy, PartialEq)]
+    enum Pad {
+        Jump,
+        Left,
+        Right,
+    }
+
+    fn buttons_state() -> ButtonsState<Pad> {
+        use AxisDirection::*;
+        let mut buttons_state = ButtonsState::default();
+        buttons_state.bind(Pad::Jump, GamepadButton::South);
+        buttons_state.bind(Pad::Left, Binding::axis(GamepadAxis::LeftStickX, Negative));
+        buttons_state.bind(Pad::Right, Binding::axis(GamepadAxis::LeftStickX, Positive));
+        buttons_state
+    }
+
+    fn update(buttons_state: &mut ButtonsState<Pad>, gamepad: &FakeGamepad) {
+        let keyboard = EventCache::default();
+        let cursor = Cursor::new(Vector::new(1., 1.));
+        buttons_state.update(&Devices {
+            keyboard: &keyboard,
+            gamepad,
+            cursor: &cursor,
+        });
+    }
+
+    #[test]
+    fn press_and_release() {
+        let mut buttons_state = buttons_state();
+        let mut gamepad = FakeGamepad::default();
+
+        gamepad.press(GamepadButton::South);
+        update(&mut buttons_state, &gamepad);
+        assert!(buttons_state.history(Pad::Jump).pressed());
+        assert_eq!(buttons_state.axis(Pad::Jump), 1.);
+
+        update(&mut buttons_state, &gamepad);
+        assert!(!buttons_state.history(Pad::Jump).pressed());
+        assert!(buttons_state.history(Pad::Jump).is_pressed());
+
+        gamepad.release(GamepadButton::South);
+        update(&mut buttons_state, &gamepad);
+        assert!(buttons_state.history(Pad::Jump).released());
+        assert!(!buttons_state.history(Pad::Jump).is_pressed());
+        assert_eq!(buttons_state.axis(Pad::Jump), 0.);
+    }
+
+    #[test]
+    fn tilt_axis() {
+        let mut buttons_state = buttons_state();
+        let mut gamepad = FakeGamepad::default();
+
+        gamepad.set_axis(GamepadAxis::LeftStickX, -2.);
+        update(&mut buttons_state, &gamepad);
+        assert_eq!(buttons_state.axis(Pad::Left), 1.);
+        assert_eq!(buttons_state.axis(Pad::Right), 0.);
+        assert!(buttons_state.history(Pad::Left).pressed());
+
+        // Inside the deadzone
+        gamepad.set_axis(GamepadAxis::LeftStickX, DEFAULT_DEADZONE / 2.);
+        update(&mut buttons_state, &gamepad);
+        assert_eq!(buttons_state.axis(Pad::Right), 0.);
+        assert!(buttons_state.history(Pad::Left).released());
+
+        // Rescaled past the deadzone, and pressed past the threshold
+        gamepad.set_axis(GamepadAxis::LeftStickX, 0.4);
+        update(&mut buttons_state, &gamepad);
+        assert!((buttons_state.axis(Pad::Right) - 0.25).abs() < 1e-4);
+        assert!(!buttons_state.history(Pad::Right).is_pressed());
+        gamepad.set_axis(GamepadAxis::LeftStickX, 0.9);
+        update(&mut buttons_state, &gamepad);
+        assert!(buttons_state.history(Pad::Right).pressed());
+    }
+}
@@ -0,0 +1,123 @@
+use std::collections::VecDeque;
+
+/// How many frames are remembered by default, one second at the `UPDATE_RATE` of 60.
+pub const DEFAULT_HISTORY_LEN: usize = 60;
+
+/// Level-based state of a button for the last `len` frames, newest first.
+#[derive(Debug, Clone)]
+pub struct History {
+    frames: VecDeque<bool>,
+    len: usize,
+    // Frames remembered since the history was created or forgotten
+    known: usize,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self::new(DEFAULT_HISTORY_LEN)
+    }
+}
+
+impl History {
+    pub fn new(len: usize) -> Self {
+        // Needs at least two frames to detect the edges
+        let len = len.max(2);
+        Self {
+            frames: VecDeque::from(vec![false; len]),
+            len,
+            known: len,
+        }
+    }
+    /// Change the amount of remembered frames, keeping the newest ones.
+    pub fn resize(&mut self, len: usize) {
+        self.len = len.max(2);
+        self.frames.resize(self.len, false);
+        self.known = self.known.min(self.len);
+    }
+    pub fn push(&mut self, is_down: bool) {
+        self.frames.pop_back();
+        self.frames.push_front(is_down);
+        self.known = (self.known + 1).min(self.len);
+    }
+    /// Treat every frame so far as unknown, a button held down now won't count as pressed.
+    pub fn forget(&mut self) {
+        self.known = 0;
+    }
+    /// State `frames_ago` frames back, 0 being the current one. `None` for the frames too old
+    /// to be remembered, or forgotten.
+    pub fn get(&self, frames_ago: usize) -> Option<bool> {
+        if frames_ago < self.known {
+            self.frames.get(frames_ago).copied()
+        } else {
+            None
+        }
+    }
+    fn is_down(&self, frames_ago: usize) -> bool {
+        self.get(frames_ago) == Some(true)
+    }
+    // The oldest frame is never an edge, it might have been held since before the history
+    fn is_press_edge(&self, frames_ago: usize) -> bool {
+        self.is_down(frames_ago) && self.get(frames_ago + 1) == Some(false)
+    }
+
+    pub fn is_pressed(&self) -> bool {
+        self.is_down(0)
+    }
+    pub fn pressed(&self) -> bool {
+        self.is_press_edge(0)
+    }
+    pub fn released(&self) -> bool {
+        !self.is_down(0) && self.is_down(1)
+    }
+    /// Held down for at least the last `frames` frames, including the current one.
+    pub fn held_for(&self, frames: usize) -> bool {
+        frames <= self.len && (0..frames).all(|i| self.is_down(i))
+    }
+    /// Pressed at some point during the last `frames` frames, including the current one.
+    pub fn pressed_within(&self, frames: usize) -> bool {
+        (0..frames.min(self.len)).any(|i| self.is_press_edge(i))
+    }
+    /// Pressed this frame, and the previous press happened at most `window` frames ago.
+    pub fn double_tapped(&self, window: usize) -> bool {
+        self.pressed() && (1..=window.min(self.len)).any(|i| self.is_press_edge(i))
+    }
+    /// Released this frame after being held for at least `min_frames` frames.
+    pub fn released_after_hold(&self, min_frames: usize) -> bool {
+        self.released() && min_frames < self.len && (1..=min_frames).all(|i| self.is_down(i))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn long_hold_is_not_pressed_again() {
+        let mut history = History::new(8);
+        for frame in 0..20 {
+            history.push(true);
+            assert_eq!(history.pressed(), frame == 0);
+            // The press leaves the history once the frame before it does
+            assert_eq!(history.pressed_within(8), frame < 7);
+            assert!(!history.double_tapped(8));
+        }
+        assert!(history.held_for(8));
+        history.push(false);
+        assert!(history.released());
+        assert!(!history.released_after_hold(8));
+        assert!(history.released_after_hold(7));
+    }
+
+    #[test]
+    fn taps_within_the_window() {
+        let mut history = History::new(8);
+        for is_down in [true, false, false, true].iter() {
+            history.push(*is_down);
+        }
+        assert!(history.double_tapped(3));
+        assert!(!history.double_tapped(2));
+        assert!(history.pressed_within(4));
+        history.push(false);
+        assert!(!history.pressed_within(1));
+    }
+}
@@ -0,0 +1,119 @@
+//! Human readable names of the keys and gamepad inputs, used by the bindings config.
+use super::{AxisDirection, Binding, Modifiers};
+use quicksilver::lifecycle::{GamepadAxis, GamepadButton, Key, MouseButton};
+
+macro_rules! names {
+    ($table:ident: $ty:ident => $($variant:ident),* $(,)?) => {
+        const $table: &[($ty, &str)] = &[$(($ty::$variant, stringify!($variant))),*];
+    };
+}
+
+/// Gamepad and mouse bindings are prefixed so they can't be mistaken for keys.
+const PAD_PREFIX: &str = "Pad:";
+const MOUSE_PREFIX: &str = "Mouse:";
+
+names!(KEYS: Key =>
+    Key1, Key2, Key3, Key4, Key5, Key6, Key7, Key8, Key9, Key0, A, B, C, D, E, F, G, H, I, J, K, L,
+    M, N, O, P, Q, R, S, T, U, V, W, X, Y, Z, Escape, F1, F2, F3, F4, F5, F6, F7, F8, F9, F10, F11,
+    F12, Insert, Home, Delete, End, PageDown, PageUp, Left, Up, Right, Down, Back, Return, Space,
+    Numpad0, Numpad1, Numpad2, Numpad3, Numpad4, Numpad5, Numpad6, Numpad7, Numpad8, Numpad9,
+    Apostrophe, Backslash, Comma, Equals, Grave, LAlt, LBracket, LControl, LShift, LWin, Minus,
+    Period, RAlt, RBracket, RControl, RShift, RWin, Semicolon, Slash, Tab,
+);
+
+names!(BUTTONS: GamepadButton =>
+    South, East, North, West, LeftShoulder, RightShoulder, LeftTrigger, RightTrigger, Select,
+    Start, Home, LeftStick, RightStick, DPadUp, DPadDown, DPadLeft, DPadRight,
+);
+
+names!(AXES: GamepadAxis => LeftStickX, LeftStickY, RightStickX, RightStickY);
+
+names!(MOUSE_BUTTONS: MouseButton => Left, Right, Middle);
+
+const MODIFIERS: &[(Modifiers, &str)] = &[
+    (Modifiers::SHIFT, "Shift"),
+    (Modifiers::CTRL, "Ctrl"),
+    (Modifiers::ALT, "Alt"),
+    (Modifiers::LOGO, "Logo"),
+];
+
+pub fn key_name(key: Key) -> Option<&'static str> {
+    find_name(KEYS, &key)
+}
+
+pub fn key_from_name(name: &str) -> Option<Key> {
+    find_value(KEYS, name)
+}
+
+fn find_name<T: PartialEq>(table: &[(T, &'static str)], value: &T) -> Option<&'static str> {
+    table
+        .iter()
+        .find(|(v, _)| v == value)
+        .map(|(_, name)| *name)
+}
+
+fn find_value<T: Copy>(table: &[(T, &'static str)], name: &str) -> Option<T> {
+    table.iter().find(|(_, n)| *n == name).map(|(v, _)| *v)
+}
+
+/// Format the binding, e.g. `Shift+Space`, `Pad:South`, `Pad:LeftStickX-` or `Mouse:Left`.
+pub fn binding_name(binding: &Binding) -> Option<String> {
+    match binding {
+        Binding::Key { key, modifiers } => {
+            let mut name = String::new();
+            for (modifier, modifier_name) in MODIFIERS {
+                if modifiers.contains(*modifier) {
+                    name.push_str(modifier_name);
+                    name.push('+');
+                }
+            }
+            name.push_str(key_name(*key)?);
+            Some(name)
+        }
+        Binding::Button(button) => Some(format!("{}{}", PAD_PREFIX, find_name(BUTTONS, button)?)),
+        Binding::Mouse(button) => Some(format!(
+            "{}{}",
+            MOUSE_PREFIX,
+            find_name(MOUSE_BUTTONS, button)?
+        )),
+        Binding::Axis { axis, direction } => {
+            let sign = match direction {
+                AxisDirection::Negative => '-',
+                AxisDirection::Positive => '+',
+            };
+            Some(format!("{}{}{}", PAD_PREFIX, find_name(AXES, axis)?, sign))
+        }
+    }
+}
+
+/// Inverse of `binding_name`.
+pub fn binding_from_name(name: &str) -> Option<Binding> {
+    if name.starts_with(MOUSE_PREFIX) {
+        let button = find_value(MOUSE_BUTTONS, &name[MOUSE_PREFIX.len()..])?;
+        return Some(Binding::Mouse(button));
+    }
+    if name.starts_with(PAD_PREFIX) {
+        let name = &name[PAD_PREFIX.len()..];
+        if let Some(button) = find_value(BUTTONS, name) {
+            return Some(Binding::Button(button));
+        }
+        let direction = if name.ends_with('-') {
+            AxisDirection::Negative
+        } else if name.ends_with('+') {
+            AxisDirection::Positive
+        } else {
+            return None;
+        };
+        let axis = find_value(AXES, &name[..name.len() - 1])?;
+        return Some(Binding::axis(axis, direction));
+    }
+
+    let mut parts = name.split('+').map(str::trim).rev();
+    let key = key_from_name(parts.next()?)?;
+    let mut modifiers = Modifiers::empty();
+    for part in parts {
+        let (modifier, _) = MODIFIERS.iter().find(|(_, n)| *n == part)?;
+        modifiers |= *modifier;
+    }
+    Some(Binding::chord(modifiers, key))
+}
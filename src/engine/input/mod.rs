@@ -0,0 +1,373 @@
+/*!
+Module for processing the raw input into easy for the game to reason about information.
+Handles keyboard, mouse and a single gamepad, all of them can be bound to the same actions.
+
+The set of buttons is defined by the game itself - any `enum_map::Enum` works as an action type.
+*/
+use bitflags::bitflags;
+use enum_map::{Enum, EnumMap};
+use quicksilver::lifecycle::{EventCache, GamepadAxis, GamepadButton, Key, MouseButton};
+use std::fmt::Debug;
+
+mod config;
+mod context;
+mod cursor;
+mod gamepad;
+mod history;
+mod keys;
+mod rebind;
+mod replay;
+
+pub use self::config::{BindingProblem, ConfigError};
+pub use self::context::{InputContext, InputView};
+pub use self::cursor::{window_to_view, Cursor};
+pub use self::gamepad::{
+    AxisDirection, Deadzones, GamepadBackend, GamepadState, AXIS_PRESS_THRESHOLD,
+};
+pub use self::history::{History, DEFAULT_HISTORY_LEN};
+pub use self::keys::{binding_from_name, binding_name};
+pub use self::rebind::{CaptureMode, CaptureOutcome, CANCEL_KEY};
+pub use self::replay::{InputReplay, Playback, Recorder, ReplayError};
+
+/// Anything the game wants to treat as a button on its dedicated controller.
+///
+/// Implemented automatically for every `#[derive(Enum)]` type that is `Copy`, `PartialEq` and `Debug`.
+/// The `Debug` output is used as the action name in the bindings config.
+pub trait Action: Enum<ActionState> + Copy + PartialEq + Debug + Send + Sync + 'static {}
+
+impl<T> Action for T where T: Enum<ActionState> + Copy + PartialEq + Debug + Send + Sync + 'static {}
+
+bitflags! {
+    /// Modifier keys that have to be held for a binding to trigger.
+    #[derive(Default)]
+    pub struct Modifiers: u8 {
+        const SHIFT = 0b1;
+        const CTRL = 0b1 << 1;
+        const ALT = 0b1 << 2;
+        const LOGO = 0b1 << 3;
+    }
+}
+
+impl Modifiers {
+    /// Modifiers currently held down, left and right variants are treated the same.
+    pub fn held(keyboard: &dyn KeyboardBackend) -> Self {
+        const KEYS: &[(Modifiers, Key, Key)] = &[
+            (Modifiers::SHIFT, Key::LShift, Key::RShift),
+            (Modifiers::CTRL, Key::LControl, Key::RControl),
+            (Modifiers::ALT, Key::LAlt, Key::RAlt),
+            (Modifiers::LOGO, Key::LWin, Key::RWin),
+        ];
+        KEYS.iter()
+            .filter(|(_, left, right)| keyboard.key(*left) || keyboard.key(*right))
+            .fold(Modifiers::empty(), |acc, (modifier, _, _)| acc | *modifier)
+    }
+}
+
+/// Physical input an action can be bound to.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Binding {
+    /// A key, optionally with the modifiers that have to be held together with it
+    Key {
+        key: Key,
+        modifiers: Modifiers,
+    },
+    Button(GamepadButton),
+    Mouse(MouseButton),
+    /// One half of the analog axis
+    Axis {
+        axis: GamepadAxis,
+        direction: AxisDirection,
+    },
+}
+
+impl Binding {
+    pub fn chord(modifiers: Modifiers, key: Key) -> Self {
+        Binding::Key { key, modifiers }
+    }
+    pub fn axis(axis: GamepadAxis, direction: AxisDirection) -> Self {
+        Binding::Axis { axis, direction }
+    }
+    /// How much the binding is held down in the 0..=1 range, digital inputs are always 0 or 1.
+    fn value(&self, devices: &Devices, held: Modifiers, deadzones: &Deadzones) -> f32 {
+        let is_down = match *self {
+            Binding::Key { key, modifiers } => {
+                devices.keyboard.key(key) && held.contains(modifiers)
+            }
+            Binding::Button(button) => devices.gamepad.button(button),
+            Binding::Mouse(button) => devices.cursor.button(button),
+            Binding::Axis { axis, direction } => {
+                let value = devices.gamepad.axis(axis);
+                return deadzones.apply(axis, direction.of(value));
+            }
+        };
+        is_down as u8 as f32
+    }
+    // Chords shadow the bindings of the same key with fewer modifiers,
+    // so Shift+Space doesn't trigger the action bound to plain Space.
+    fn shadows(&self, other: &Binding) -> bool {
+        match (self, other) {
+            (
+                Binding::Key { key, modifiers },
+                Binding::Key {
+                    key: other_key,
+                    modifiers: other_modifiers,
+                },
+            ) => {
+                key == other_key
+                    && modifiers != other_modifiers
+                    && modifiers.contains(*other_modifiers)
+            }
+            _ => false,
+        }
+    }
+}
+
+impl From<Key> for Binding {
+    fn from(key: Key) -> Self {
+        Binding::chord(Modifiers::empty(), key)
+    }
+}
+
+impl From<GamepadButton> for Binding {
+    fn from(button: GamepadButton) -> Self {
+        Binding::Button(button)
+    }
+}
+
+impl From<MouseButton> for Binding {
+    fn from(button: MouseButton) -> Self {
+        Binding::Mouse(button)
+    }
+}
+
+/// Keys held down, the game reads them from the `EventCache`.
+pub trait KeyboardBackend {
+    fn key(&self, key: Key) -> bool;
+}
+
+impl KeyboardBackend for EventCache {
+    fn key(&self, key: Key) -> bool {
+        EventCache::key(self, key)
+    }
+}
+
+/// Keyboard set by hand, for driving the input in tests.
+#[cfg(test)]
+#[derive(Default)]
+pub struct FakeKeyboard {
+    keys: Vec<Key>,
+}
+
+#[cfg(test)]
+impl FakeKeyboard {
+    pub fn press(&mut self, key: Key) {
+        self.release(key);
+        self.keys.push(key);
+    }
+    pub fn release(&mut self, key: Key) {
+        self.keys.retain(|k| *k != key);
+    }
+}
+
+#[cfg(test)]
+impl KeyboardBackend for FakeKeyboard {
+    fn key(&self, key: Key) -> bool {
+        self.keys.contains(&key)
+    }
+}
+
+/// Everything `ButtonsState::update` reads the input from.
+pub struct Devices<'a> {
+    pub keyboard: &'a dyn KeyboardBackend,
+    pub gamepad: &'a dyn GamepadBackend,
+    pub cursor: &'a Cursor,
+}
+
+/// Bindings and history of a single action.
+#[derive(Debug, Default, Clone)]
+pub struct ActionState {
+    bindings: Vec<Binding>,
+    history: History,
+    // Strongest of the bindings, in the 0..=1 range
+    value: f32,
+}
+
+// Reads the edge-based input and turn it into level-based.
+pub struct ButtonsState<A: Action> {
+    bindings: EnumMap<A, ActionState>,
+    // Set whenever the bindings are modified, so they can be written back
+    changed: bool,
+    capture: Option<rebind::Capture<A>>,
+    capture_outcome: Option<CaptureOutcome<A>>,
+    pub deadzones: Deadzones,
+    contexts: Vec<InputContext<A>>,
+}
+
+impl<A: Action> Default for ButtonsState<A> {
+    fn default() -> Self {
+        Self {
+            bindings: EnumMap::default(),
+            changed: false,
+            capture: None,
+            capture_outcome: None,
+            deadzones: Deadzones::default(),
+            contexts: Vec::new(),
+        }
+    }
+}
+
+impl<A: Action> ButtonsState<A> {
+    /// Add another binding to the action, the previous ones stay active.
+    pub fn bind(&mut self, action: A, binding: impl Into<Binding>) {
+        let binding = binding.into();
+        let bindings = &mut self.bindings[action].bindings;
+        if !bindings.contains(&binding) {
+            bindings.push(binding);
+            self.changed = true;
+        }
+    }
+    /// Remove all the bindings of the action.
+    pub fn unbind(&mut self, action: A) {
+        self.bindings[action].bindings.clear();
+        self.changed = true;
+    }
+    /// Whether the bindings changed since the last call.
+    pub fn take_changed(&mut self) -> bool {
+        std::mem::replace(&mut self.changed, false)
+    }
+    /// Remove a single binding of the action, the others stay.
+    pub fn remove_binding(&mut self, action: A, binding: Binding) {
+        let bindings = &mut self.bindings[action].bindings;
+        let len = bindings.len();
+        bindings.retain(|b| *b != binding);
+        self.changed |= bindings.len() != len;
+    }
+    pub fn bindings(&self, action: A) -> &[Binding] {
+        &self.bindings[action].bindings
+    }
+    pub fn update(&mut self, devices: &Devices) {
+        // Keys pressed while rebinding are not meant for the game
+        if self.capture.is_some() {
+            for state in self.bindings.values_mut() {
+                state.history.push(false);
+                state.value = 0.;
+            }
+            return;
+        }
+        let held = Modifiers::held(devices.keyboard);
+        let deadzones = &self.deadzones;
+        let down: Vec<(Binding, f32)> = self
+            .bindings
+            .values()
+            .flat_map(|state| state.bindings.iter())
+            .map(|binding| (*binding, binding.value(devices, held, deadzones)))
+            .filter(|(_, value)| *value > 0.)
+            .collect();
+        let value_of = |binding: &Binding| {
+            if down.iter().any(|(other, _)| other.shadows(binding)) {
+                return 0.;
+            }
+            down.iter()
+                .find(|(other, _)| other == binding)
+                .map_or(0., |(_, value)| *value)
+        };
+
+        for state in self.bindings.values_mut() {
+            state.value = state.bindings.iter().map(&value_of).fold(0., f32::max);
+            state.history.push(state.value >= AXIS_PRESS_THRESHOLD);
+        }
+    }
+    /// Analog value of the action in the 0..=1 range, keys and buttons give either 0 or 1.
+    pub fn axis(&self, action: A) -> f32 {
+        self.bindings[action].value
+    }
+    /// Remember `frames` frames of every action, `DEFAULT_HISTORY_LEN` unless changed.
+    pub fn set_history_len(&mut self, frames: usize) {
+        for state in self.bindings.values_mut() {
+            state.history.resize(frames);
+        }
+    }
+    pub fn history(&self, action: A) -> &History {
+        &self.bindings[action].history
+    }
+    /// Drop the presses remembered so far, so they don't leak into what comes next.
+    pub fn forget_history(&mut self) {
+        for state in self.bindings.values_mut() {
+            state.history.forget();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::input::gamepad::FakeGamepad;
+    use quicksilver::geom::Vector;
+
+    #[derive(Debug, Enum, Clone, Copy, PartialEq)]
+    enum Act {
+        Save,
+        Sneak,
+        Jump,
+    }
+
+    fn update(buttons_state: &mut ButtonsState<Act>, keyboard: &FakeKeyboard) {
+        let cursor = Cursor::new(Vector::new(1., 1.));
+        buttons_state.update(&Devices {
+            keyboard,
+            gamepad: &FakeGamepad::default(),
+            cursor: &cursor,
+        });
+    }
+
+    #[test]
+    fn chord_shadows_its_plain_key() {
+        let mut buttons_state = ButtonsState::default();
+        buttons_state.bind(Act::Save, Binding::chord(Modifiers::CTRL, Key::S));
+        buttons_state.bind(Act::Sneak, Key::S);
+        let mut keyboard = FakeKeyboard::default();
+
+        keyboard.press(Key::S);
+        update(&mut buttons_state, &keyboard);
+        assert!(buttons_state.history(Act::Sneak).is_pressed());
+        assert!(!buttons_state.history(Act::Save).is_pressed());
+
+        keyboard.press(Key::RControl);
+        update(&mut buttons_state, &keyboard);
+        assert!(buttons_state.history(Act::Save).pressed());
+        assert!(!buttons_state.history(Act::Sneak).is_pressed());
+
+        // The chord stops with its modifier, the plain key takes over again
+        keyboard.release(Key::RControl);
+        update(&mut buttons_state, &keyboard);
+        assert!(buttons_state.history(Act::Save).released());
+        assert!(buttons_state.history(Act::Sneak).pressed());
+    }
+
+    #[test]
+    fn any_binding_triggers_the_action() {
+        let mut buttons_state = ButtonsState::default();
+        buttons_state.bind(Act::Jump, Key::Space);
+        buttons_state.bind(Act::Jump, Key::W);
+        buttons_state.bind(Act::Jump, Binding::chord(Modifiers::SHIFT, Key::Up));
+        assert_eq!(buttons_state.bindings(Act::Jump).len(), 3);
+        let mut keyboard = FakeKeyboard::default();
+
+        for key in [Key::Space, Key::W].iter() {
+            keyboard.press(*key);
+            update(&mut buttons_state, &keyboard);
+            assert!(buttons_state.history(Act::Jump).pressed());
+            keyboard.release(*key);
+            update(&mut buttons_state, &keyboard);
+            assert!(buttons_state.history(Act::Jump).released());
+        }
+
+        // Without its modifier the chord doesn't count
+        keyboard.press(Key::Up);
+        update(&mut buttons_state, &keyboard);
+        assert!(!buttons_state.history(Act::Jump).is_pressed());
+        keyboard.press(Key::LShift);
+        update(&mut buttons_state, &keyboard);
+        assert!(buttons_state.history(Act::Jump).pressed());
+    }
+}
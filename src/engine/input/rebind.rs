@@ -0,0 +1,286 @@
+//! Interactive rebinding: "press a key for Jump".
+//!
+//! The keys pressed while capturing don't reach the game, and the histories are forgotten when
+//! the capture ends, so the key that ended it isn't seen as a fresh press once the game gets it.
+use super::keys::binding_name;
+use super::{Action, AxisDirection, Binding, ButtonsState, Modifiers, AXIS_PRESS_THRESHOLD};
+use quicksilver::lifecycle::{Event, Key};
+
+/// Pressing it while capturing cancels the capture instead of being bound.
+pub const CANCEL_KEY: Key = Key::Escape;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CaptureMode {
+    /// Keep the existing bindings of the action
+    Add,
+    /// The captured binding becomes the only one of the action
+    Replace,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CaptureOutcome<A> {
+    /// Binding was assigned, `clashes` lists other actions that use it as well
+    Bound {
+        action: A,
+        binding: Binding,
+        clashes: Vec<A>,
+    },
+    Cancelled {
+        action: A,
+    },
+}
+
+pub(super) struct Capture<A> {
+    action: A,
+    mode: CaptureMode,
+    // Modifiers pressed since the capture started, a lone modifier is bound on release
+    modifiers: Modifiers,
+    last_modifier: Option<Key>,
+}
+
+fn modifier_of(key: Key) -> Option<Modifiers> {
+    match key {
+        Key::LShift | Key::RShift => Some(Modifiers::SHIFT),
+        Key::LControl | Key::RControl => Some(Modifiers::CTRL),
+        Key::LAlt | Key::RAlt => Some(Modifiers::ALT),
+        Key::LWin | Key::RWin => Some(Modifiers::LOGO),
+        _ => None,
+    }
+}
+
+impl<A: Action> ButtonsState<A> {
+    /// Assign the next key, mouse button, gamepad button or stick direction pressed to the action.
+    pub fn start_capture(&mut self, action: A, mode: CaptureMode) {
+        self.capture = Some(Capture {
+            action,
+            mode,
+            modifiers: Modifiers::empty(),
+            last_modifier: None,
+        });
+        self.capture_outcome = None;
+    }
+    pub fn cancel_capture(&mut self) {
+        if let Some(capture) = self.capture.take() {
+            self.forget_history();
+            self.capture_outcome = Some(CaptureOutcome::Cancelled {
+                action: capture.action,
+            });
+        }
+    }
+    /// Action currently waiting for a key.
+    pub fn capturing(&self) -> Option<A> {
+        self.capture.as_ref().map(|capture| capture.action)
+    }
+    /// Result of the last finished capture, returned only once.
+    pub fn take_capture_outcome(&mut self) -> Option<CaptureOutcome<A>> {
+        self.capture_outcome.take()
+    }
+    /// Feed the raw events here, they're ignored unless a capture is in progress.
+    pub fn capture_event(&mut self, event: &Event) {
+        if self.capture.is_none() {
+            return;
+        }
+        match event {
+            Event::KeyboardInput(key_event) => {
+                self.capture_key(key_event.key(), key_event.is_down())
+            }
+            Event::PointerInput(pointer_event) if pointer_event.is_down() => {
+                self.finish_capture(Binding::Mouse(pointer_event.button()))
+            }
+            Event::GamepadButton(button_event) if button_event.is_down() => {
+                self.finish_capture(Binding::Button(button_event.button()))
+            }
+            Event::GamepadAxis(axis_event) if axis_event.value().abs() >= AXIS_PRESS_THRESHOLD => {
+                let direction = if axis_event.value() < 0. {
+                    AxisDirection::Negative
+                } else {
+                    AxisDirection::Positive
+                };
+                self.finish_capture(Binding::axis(axis_event.axis(), direction));
+            }
+            _ => {}
+        }
+    }
+
+    // The keys are bound on press, along with the modifiers held at the time
+    fn capture_key(&mut self, key: Key, is_down: bool) {
+        if is_down && key == CANCEL_KEY {
+            self.cancel_capture();
+            return;
+        }
+        let capture = match self.capture.as_mut() {
+            Some(capture) => capture,
+            None => return,
+        };
+
+        let binding = match (modifier_of(key), is_down) {
+            (Some(modifier), true) => {
+                capture.modifiers |= modifier;
+                capture.last_modifier = Some(key);
+                return;
+            }
+            // Modifier released without any other key, bind the modifier itself
+            (Some(modifier), false) if capture.last_modifier == Some(key) => {
+                Binding::chord(capture.modifiers - modifier, key)
+            }
+            (Some(modifier), false) => {
+                capture.modifiers -= modifier;
+                return;
+            }
+            (None, true) => Binding::chord(capture.modifiers, key),
+            (None, false) => return,
+        };
+        self.finish_capture(binding);
+    }
+
+    // Bindings without a name couldn't be saved, the capture goes on until another one comes
+    fn finish_capture(&mut self, binding: Binding) {
+        if binding_name(&binding).is_none() {
+            return;
+        }
+        let capture = match self.capture.take() {
+            Some(capture) => capture,
+            None => return,
+        };
+        self.forget_history();
+        if capture.mode == CaptureMode::Replace {
+            self.unbind(capture.action);
+        }
+        self.bind(capture.action, binding);
+
+        let clashes = self
+            .bindings
+            .iter()
+            .filter(|(action, state)| {
+                *action != capture.action && state.bindings.contains(&binding)
+            })
+            .map(|(action, _)| action)
+            .collect();
+        self.capture_outcome = Some(CaptureOutcome::Bound {
+            action: capture.action,
+            binding,
+            clashes,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use enum_map::Enum;
+
+    #[derive(Debug, Enum, Clone, Copy, PartialEq)]
+    enum Act {
+        Jump,
+        Left,
+    }
+
+    fn buttons_state() -> ButtonsState<Act> {
+        let mut buttons_state = ButtonsState::default();
+        buttons_state.bind(Act::Jump, Key::Space);
+        buttons_state.bind(Act::Left, Key::A);
+        buttons_state
+    }
+
+    fn bound(binding: Binding, clashes: Vec<Act>) -> Option<CaptureOutcome<Act>> {
+        Some(CaptureOutcome::Bound {
+            action: Act::Jump,
+            binding,
+            clashes,
+        })
+    }
+
+    #[test]
+    fn chord_is_bound_on_press() {
+        let mut buttons_state = buttons_state();
+        buttons_state.start_capture(Act::Jump, CaptureMode::Replace);
+        buttons_state.capture_key(Key::LControl, true);
+        assert_eq!(buttons_state.capturing(), Some(Act::Jump));
+        buttons_state.capture_key(Key::S, true);
+
+        let chord = Binding::chord(Modifiers::CTRL, Key::S);
+        assert_eq!(buttons_state.take_capture_outcome(), bound(chord, vec![]));
+        assert_eq!(buttons_state.take_capture_outcome(), None);
+        assert_eq!(buttons_state.capturing(), None);
+        assert_eq!(buttons_state.bindings(Act::Jump), &[chord]);
+    }
+
+    #[test]
+    fn modifier_alone_is_bound_on_release() {
+        let mut buttons_state = buttons_state();
+        buttons_state.start_capture(Act::Jump, CaptureMode::Replace);
+        buttons_state.capture_key(Key::LShift, true);
+        buttons_state.capture_key(Key::LShift, false);
+        assert_eq!(
+            buttons_state.take_capture_outcome(),
+            bound(Key::LShift.into(), vec![])
+        );
+
+        // Released after another modifier went down, it's not part of the chord anymore
+        buttons_state.start_capture(Act::Jump, CaptureMode::Replace);
+        buttons_state.capture_key(Key::LShift, true);
+        buttons_state.capture_key(Key::RAlt, true);
+        buttons_state.capture_key(Key::LShift, false);
+        assert_eq!(buttons_state.capturing(), Some(Act::Jump));
+        buttons_state.capture_key(Key::Q, true);
+        assert_eq!(
+            buttons_state.take_capture_outcome(),
+            bound(Binding::chord(Modifiers::ALT, Key::Q), vec![])
+        );
+    }
+
+    #[test]
+    fn cancel_keeps_the_bindings() {
+        let mut buttons_state = buttons_state();
+        buttons_state.start_capture(Act::Jump, CaptureMode::Replace);
+        buttons_state.capture_key(CANCEL_KEY, true);
+        assert_eq!(
+            buttons_state.take_capture_outcome(),
+            Some(CaptureOutcome::Cancelled { action: Act::Jump })
+        );
+        assert_eq!(buttons_state.capturing(), None);
+        assert_eq!(
+            buttons_state.bindings(Act::Jump),
+            &[Binding::from(Key::Space)]
+        );
+    }
+
+    #[test]
+    fn add_keeps_and_replace_drops_the_old_bindings() {
+        let mut buttons_state = buttons_state();
+        buttons_state.start_capture(Act::Jump, CaptureMode::Add);
+        buttons_state.capture_key(Key::W, true);
+        assert_eq!(
+            buttons_state.bindings(Act::Jump),
+            &[Binding::from(Key::Space), Binding::from(Key::W)]
+        );
+
+        buttons_state.start_capture(Act::Jump, CaptureMode::Replace);
+        buttons_state.capture_key(Key::Up, true);
+        assert_eq!(buttons_state.bindings(Act::Jump), &[Binding::from(Key::Up)]);
+    }
+
+    #[test]
+    fn clashes_are_listed() {
+        let mut buttons_state = buttons_state();
+        buttons_state.start_capture(Act::Jump, CaptureMode::Add);
+        buttons_state.capture_key(Key::A, true);
+        assert_eq!(
+            buttons_state.take_capture_outcome(),
+            bound(Key::A.into(), vec![Act::Left])
+        );
+        // Both keep it, the game decides what to do about the clash
+        assert_eq!(buttons_state.bindings(Act::Left), &[Binding::from(Key::A)]);
+    }
+
+    #[test]
+    fn keys_without_a_name_are_skipped() {
+        let mut buttons_state = buttons_state();
+        buttons_state.start_capture(Act::Jump, CaptureMode::Replace);
+        buttons_state.capture_key(Key::Snapshot, true);
+        assert_eq!(buttons_state.capturing(), Some(Act::Jump));
+        assert_eq!(buttons_state.take_capture_outcome(), None);
+        buttons_state.capture_key(Key::W, true);
+        assert_eq!(buttons_state.bindings(Act::Jump), &[Binding::from(Key::W)]);
+    }
+}
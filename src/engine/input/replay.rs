@@ -0,0 +1,440 @@
+/*!
+Recording the per-tick level state of the actions and feeding it back instead of the live input.
+
+The file starts with a header: `SLRP` magic, format version and the number of actions.
+The rest are runs of identical frames: `u16` little-endian repeat count followed by one byte
+per action, the analog value quantized to 0..=255, and the cursor position in the view.
+
+The replay only stands in for the input, playing it back gives the same game only from the same
+starting state, so the game has to start its scene over when a looping replay does.
+*/
+use super::{Action, ButtonsState, Cursor, Devices, GamepadBackend, KeyboardBackend};
+use quicksilver::geom::Vector;
+use std::fmt;
+use std::io::{self, Read, Write};
+
+const MAGIC: &[u8; 4] = b"SLRP";
+const VERSION: u8 = 2;
+/// The writer is flushed at least this often, so the recording cut off by quitting the game
+/// loses at most a second at the `UPDATE_RATE` of 60.
+const FLUSH_TICKS: u16 = 60;
+/// Bytes of the cursor after the actions of every frame: 1 when it's over the view, then its
+/// position in the view as two little-endian `f32`.
+const CURSOR_BYTES: usize = 9;
+
+#[derive(Debug)]
+pub enum ReplayError {
+    Io(io::Error),
+    /// Not a replay file at all
+    BadMagic,
+    UnsupportedVersion(u8),
+    /// The header stores the action count in a single byte
+    TooManyActions(usize),
+    /// Recorded with a different set of actions
+    ActionCountMismatch {
+        expected: usize,
+        found: usize,
+    },
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReplayError::Io(err) => write!(f, "replay io error: {}", err),
+            ReplayError::BadMagic => write!(f, "not a replay file"),
+            ReplayError::UnsupportedVersion(version) => {
+                write!(f, "unsupported replay version {}", version)
+            }
+            ReplayError::TooManyActions(count) => {
+                write!(f, "can't record {} actions, at most 255 fit", count)
+            }
+            ReplayError::ActionCountMismatch { expected, found } => write!(
+                f,
+                "replay has {} actions, the game expects {}",
+                found, expected
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ReplayError {}
+
+impl From<io::Error> for ReplayError {
+    fn from(err: io::Error) -> Self {
+        ReplayError::Io(err)
+    }
+}
+
+fn quantize(value: f32) -> u8 {
+    (value.max(0.).min(1.) * 255.).round() as u8
+}
+
+fn dequantize(value: u8) -> f32 {
+    value as f32 / 255.
+}
+
+fn cursor_to_bytes(view: Option<Vector>) -> [u8; CURSOR_BYTES] {
+    let mut bytes = [0; CURSOR_BYTES];
+    if let Some(view) = view {
+        bytes[0] = 1;
+        bytes[1..5].copy_from_slice(&view.x.to_le_bytes());
+        bytes[5..].copy_from_slice(&view.y.to_le_bytes());
+    }
+    bytes
+}
+
+fn cursor_from_bytes(bytes: &[u8]) -> Option<Vector> {
+    if bytes[0] == 0 {
+        return None;
+    }
+    let float = |bytes: &[u8]| {
+        let mut le = [0; 4];
+        le.copy_from_slice(bytes);
+        f32::from_le_bytes(le)
+    };
+    Some(Vector::new(float(&bytes[1..5]), float(&bytes[5..])))
+}
+
+impl<A: Action> ButtonsState<A> {
+    /// Level state of every action this tick, in the format stored in the replay.
+    pub fn frame(&self) -> Vec<u8> {
+        self.bindings
+            .values()
+            .map(|state| quantize(state.value))
+            .collect()
+    }
+    /// Use the recorded frame instead of reading the devices, counterpart of `update`.
+    pub fn apply_frame(&mut self, frame: &[u8]) {
+        for (state, value) in self.bindings.values_mut().zip(frame.iter()) {
+            state.value = dequantize(*value);
+            state
+                .history
+                .push(state.value >= super::AXIS_PRESS_THRESHOLD);
+        }
+    }
+    pub fn action_count(&self) -> usize {
+        self.bindings.len()
+    }
+}
+
+/// Writes the frames as they come, a run once it ends. The writer is flushed, along with the
+/// unfinished run, every `FLUSH_TICKS`.
+pub struct Recorder {
+    writer: Box<dyn Write + Send + Sync>,
+    run: Option<(Vec<u8>, u16)>,
+    // Ticks recorded since the last flush
+    unflushed: u16,
+}
+
+impl Recorder {
+    pub fn new(
+        mut writer: Box<dyn Write + Send + Sync>,
+        action_count: usize,
+    ) -> Result<Self, ReplayError> {
+        if action_count > u8::MAX as usize {
+            return Err(ReplayError::TooManyActions(action_count));
+        }
+        writer.write_all(MAGIC)?;
+        writer.write_all(&[VERSION, action_count as u8])?;
+        Ok(Self {
+            writer,
+            run: None,
+            unflushed: 0,
+        })
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn create(
+        path: impl AsRef<std::path::Path>,
+        action_count: usize,
+    ) -> Result<Self, ReplayError> {
+        let file = std::fs::File::create(path)?;
+        Self::new(Box::new(io::BufWriter::new(file)), action_count)
+    }
+    pub fn record(&mut self, frame: Vec<u8>) -> Result<(), ReplayError> {
+        match &mut self.run {
+            Some((current, count)) if *current == frame && *count < u16::MAX => *count += 1,
+            _ => {
+                if let Some(run) = self.run.replace((frame, 1)) {
+                    self.write_run(run)?;
+                }
+            }
+        }
+        self.unflushed += 1;
+        if self.unflushed >= FLUSH_TICKS {
+            self.flush()?;
+        }
+        Ok(())
+    }
+    /// Write out the unfinished run and flush the writer, the recording can be continued
+    /// afterwards.
+    pub fn flush(&mut self) -> Result<(), ReplayError> {
+        self.unflushed = 0;
+        if let Some(run) = self.run.take() {
+            self.write_run(run)?;
+        }
+        self.writer.flush()?;
+        Ok(())
+    }
+    /// Write out the rest of the recording. Dropping the recorder does it as well, but can only
+    /// log the errors.
+    pub fn finish(mut self) -> Result<(), ReplayError> {
+        self.flush()
+    }
+    fn write_run(&mut self, (frame, count): (Vec<u8>, u16)) -> Result<(), ReplayError> {
+        self.writer.write_all(&count.to_le_bytes())?;
+        self.writer.write_all(&frame)?;
+        Ok(())
+    }
+}
+
+impl Drop for Recorder {
+    fn drop(&mut self) {
+        if let Err(err) = self.flush() {
+            warn!("Failed to finish the replay: {}", err);
+        }
+    }
+}
+
+/// Recorded frames, handed out one per tick.
+pub struct Playback {
+    runs: Vec<(Vec<u8>, u16)>,
+    run: usize,
+    repeat: u16,
+    /// Start over once the end is reached, for attract-mode demos
+    pub looping: bool,
+}
+
+impl Playback {
+    pub fn read(mut reader: impl Read, action_count: usize) -> Result<Self, ReplayError> {
+        let mut header = [0; 6];
+        reader.read_exact(&mut header)?;
+        if &header[..4] != MAGIC {
+            return Err(ReplayError::BadMagic);
+        }
+        if header[4] != VERSION {
+            return Err(ReplayError::UnsupportedVersion(header[4]));
+        }
+        if header[5] as usize != action_count {
+            return Err(ReplayError::ActionCountMismatch {
+                expected: action_count,
+                found: header[5] as usize,
+            });
+        }
+
+        let mut runs = Vec::new();
+        let mut count = [0; 2];
+        loop {
+            match reader.read_exact(&mut count) {
+                Ok(()) => {}
+                Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => break,
+                Err(err) => return Err(err.into()),
+            }
+            let mut frame = vec![0; action_count + CURSOR_BYTES];
+            reader.read_exact(&mut frame)?;
+            runs.push((frame, u16::from_le_bytes(count)));
+        }
+        Ok(Self {
+            runs,
+            run: 0,
+            repeat: 0,
+            looping: false,
+        })
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn open(
+        path: impl AsRef<std::path::Path>,
+        action_count: usize,
+    ) -> Result<Self, ReplayError> {
+        let file = std::fs::File::open(path)?;
+        Self::read(io::BufReader::new(file), action_count)
+    }
+    /// Whether every frame was handed out already.
+    pub fn is_finished(&self) -> bool {
+        self.run >= self.runs.len()
+    }
+    /// Start over from the first frame.
+    pub fn rewind(&mut self) {
+        self.run = 0;
+        self.repeat = 0;
+    }
+    /// Frame for the current tick, `None` once the recording ran out.
+    pub fn next_frame(&mut self) -> Option<&[u8]> {
+        let (frame, count) = self.runs.get(self.run)?;
+        self.repeat += 1;
+        if self.repeat >= *count {
+            self.run += 1;
+            self.repeat = 0;
+        }
+        Some(frame)
+    }
+}
+
+/// Where the input of the current tick comes from.
+pub enum InputReplay {
+    Live,
+    Recording(Recorder),
+    Playing(Playback),
+}
+
+impl Default for InputReplay {
+    fn default() -> Self {
+        InputReplay::Live
+    }
+}
+
+impl InputReplay {
+    /// Switch to the live input, finishing the recording in progress.
+    pub fn stop(&mut self) {
+        if let InputReplay::Recording(recorder) = std::mem::take(self) {
+            if let Err(err) = recorder.finish() {
+                warn!("Failed to finish the replay: {}", err);
+            }
+        }
+    }
+    /// Whether the looping playback reached its end, it starts over from the first frame then.
+    ///
+    /// Call before the tick, and put the scene back the way it was when the recording started.
+    pub fn take_loop(&mut self) -> bool {
+        match self {
+            InputReplay::Playing(playback)
+                if playback.looping && playback.is_finished() && !playback.runs.is_empty() =>
+            {
+                playback.rewind();
+                true
+            }
+            _ => false,
+        }
+    }
+    /// Update the buttons and the cursor for this tick, from the devices or from the recording.
+    pub fn update<A: Action>(
+        &mut self,
+        buttons_state: &mut ButtonsState<A>,
+        keyboard: &dyn KeyboardBackend,
+        gamepad: &dyn GamepadBackend,
+        cursor: &mut Cursor,
+    ) {
+        if let InputReplay::Playing(playback) = self {
+            if let Some(frame) = playback.next_frame() {
+                let (actions, view) = frame.split_at(frame.len() - CURSOR_BYTES);
+                buttons_state.apply_frame(actions);
+                cursor.set_view_position(cursor_from_bytes(view));
+                return;
+            }
+            info!("Replay finished, switching to live input");
+            *self = InputReplay::Live;
+        }
+        buttons_state.update(&Devices {
+            keyboard,
+            gamepad,
+            cursor: &*cursor,
+        });
+        if let InputReplay::Recording(recorder) = self {
+            let mut frame = buttons_state.frame();
+            frame.extend_from_slice(&cursor_to_bytes(cursor.view_position()));
+            if let Err(err) = recorder.record(frame) {
+                warn!("{}, recording stopped", err);
+                self.stop();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::input::gamepad::FakeGamepad;
+    use crate::engine::input::FakeKeyboard;
+    use enum_map::Enum;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Clone, Default)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[derive(Debug, Enum, Clone, Copy, PartialEq)]
+    enum Act {
+        Jump,
+        Dash,
+    }
+
+    fn frames(bytes: &[u8]) -> Vec<Vec<u8>> {
+        let mut playback = Playback::read(bytes, 2).unwrap();
+        std::iter::from_fn(|| playback.next_frame().map(<[u8]>::to_vec)).collect()
+    }
+
+    // Two actions, the cursor outside of the view
+    fn frame(jump: u8, dash: u8) -> Vec<u8> {
+        let mut frame = vec![jump, dash];
+        frame.extend_from_slice(&cursor_to_bytes(None));
+        frame
+    }
+
+    #[test]
+    fn unchanged_input_is_written_without_finishing() {
+        let file = Shared::default();
+        let mut recorder = Recorder::new(Box::new(file.clone()), 2).unwrap();
+        recorder.record(frame(0, 255)).unwrap();
+        for _ in 0..FLUSH_TICKS {
+            recorder.record(frame(255, 0)).unwrap();
+        }
+        // The recorder is still alive, as it is when the game gets closed
+        let bytes = file.0.lock().unwrap().clone();
+        let mut expected = vec![frame(0, 255)];
+        expected.extend(vec![frame(255, 0); FLUSH_TICKS as usize - 1]);
+        assert_eq!(frames(&bytes), expected);
+
+        recorder.finish().unwrap();
+        let bytes = file.0.lock().unwrap().clone();
+        assert_eq!(frames(&bytes).len(), FLUSH_TICKS as usize + 1);
+    }
+
+    #[test]
+    fn action_count_has_to_fit_the_header() {
+        let result = Recorder::new(Box::new(Shared::default()), 256);
+        assert!(matches!(result, Err(ReplayError::TooManyActions(256))));
+    }
+
+    #[test]
+    fn cursor_is_replayed() {
+        let file = Shared::default();
+        let mut buttons_state = ButtonsState::<Act>::default();
+        let (keyboard, gamepad) = (FakeKeyboard::default(), FakeGamepad::default());
+        let view_size = Vector::new(320., 180.);
+        let positions = [
+            Some(Vector::new(10., 20.5)),
+            None,
+            Some(Vector::new(0., 179.)),
+        ];
+
+        let recorder = Recorder::new(Box::new(file.clone()), buttons_state.action_count());
+        let mut replay = InputReplay::Recording(recorder.unwrap());
+        let mut cursor = Cursor::new(view_size);
+        for position in positions.iter() {
+            cursor.set_view_position(*position);
+            replay.update(&mut buttons_state, &keyboard, &gamepad, &mut cursor);
+        }
+        replay.stop();
+
+        let bytes = file.0.lock().unwrap().clone();
+        let mut playback = Playback::read(&bytes[..], 2).unwrap();
+        playback.looping = true;
+        let mut replay = InputReplay::Playing(playback);
+        let mut cursor = Cursor::new(view_size);
+        for _ in 0..2 {
+            assert!(!replay.take_loop());
+            for position in positions.iter() {
+                replay.update(&mut buttons_state, &keyboard, &gamepad, &mut cursor);
+                assert_eq!(cursor.view_position(), *position);
+            }
+            assert!(replay.take_loop());
+        }
+    }
+}
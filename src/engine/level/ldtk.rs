@@ -0,0 +1,955 @@
+/*!
+Importer for the projects of the LDtk editor.
+
+A project lays out its levels in one world, each level is imported on its own at its world
+position. The tile, auto-layer and IntGrid layers of a level become the layers of one tilemap,
+so they must share the grid size. An auto-layer stacking tiles in a cell takes as many layers
+as the deepest stack. Every non-zero cell of the IntGrid layers is solid, the cells are gathered
+into a hidden collision layer. The entities are spawned by their identifier, with their fields
+as the properties.
+
+`LevelTravel` keeps one level of the project in the world at a time, and swaps it for the linked
+level the player walks into.
+*/
+use super::{
+    despawn, load_file, loaded_images, parse_error, tileset_image, EntityRegistry, Level,
+    LevelError, LevelObject, Properties, PropertyValue,
+};
+use crate::engine::assets::Handle;
+use crate::engine::tilemap::{Tile, Tilemap, Tileset};
+use crate::gfx::atlas::sheet_path;
+use crate::gfx::Images;
+use crate::phx::{PhysicsWorld, TileCollision};
+use fxhash::FxHashMap;
+use legion::prelude::*;
+use quicksilver::geom::{Rectangle, Vector};
+use quicksilver::graphics::Image;
+use serde::Deserialize;
+use serde_json::Value;
+
+const FLIPPED_X: u8 = 0b01;
+const FLIPPED_Y: u8 = 0b10;
+
+/// Name of the hidden layer with the solid cells of the IntGrid layers.
+pub const COLLISION_LAYER: &str = "collision";
+
+/// A parsed project, with its external level files still to be loaded.
+pub struct LdtkProject {
+    path: String,
+    raw: RawProject,
+}
+
+impl LdtkProject {
+    pub fn parse(path: &str, bytes: &[u8]) -> Result<Self, LevelError> {
+        let raw: RawProject =
+            serde_json::from_slice(bytes).map_err(|err| parse_error(path, err))?;
+        if raw.levels.is_empty() && !raw.worlds.is_empty() {
+            return Err(LevelError::Unsupported {
+                path: path.into(),
+                feature: "multiple worlds".into(),
+            });
+        }
+        Ok(Self {
+            path: path.into(),
+            raw,
+        })
+    }
+
+    /// Read the project and its external level files.
+    pub async fn load(path: &str) -> Result<Self, LevelError> {
+        let mut project = Self::parse(path, &load_file(path).await?)?;
+        project.load_levels().await?;
+        Ok(project)
+    }
+
+    /// Read the external level files, relative to the project.
+    pub async fn load_levels(&mut self) -> Result<(), LevelError> {
+        for source in self.external_levels() {
+            let path = sheet_path(&self.path, &source);
+            let bytes = load_file(&path).await?;
+            self.resolve_level(&source, &path, &bytes)?;
+        }
+        Ok(())
+    }
+
+    /// Files of the levels saved apart from the project, and not loaded yet.
+    pub fn external_levels(&self) -> Vec<String> {
+        self.raw
+            .levels
+            .iter()
+            .filter(|level| level.layer_instances.is_none())
+            .filter_map(|level| level.external_rel_path.clone())
+            .collect()
+    }
+
+    /// Fill in the level saved to `source` with the file read from `path`.
+    pub fn resolve_level(
+        &mut self,
+        source: &str,
+        path: &str,
+        bytes: &[u8],
+    ) -> Result<(), LevelError> {
+        let level: RawLevel =
+            serde_json::from_slice(bytes).map_err(|err| parse_error(path, err))?;
+        if let Some(slot) = self
+            .raw
+            .levels
+            .iter_mut()
+            .find(|slot| slot.external_rel_path.as_deref() == Some(source))
+        {
+            slot.layer_instances = level.layer_instances;
+        }
+        Ok(())
+    }
+
+    /// Identifiers of the levels, in the order of the project.
+    pub fn levels(&self) -> impl Iterator<Item = &str> {
+        self.raw
+            .levels
+            .iter()
+            .map(|level| level.identifier.as_str())
+    }
+
+    /// Area of the level in the world.
+    pub fn bounds(&self, level: &str) -> Option<Rectangle> {
+        let index = self.index(level)?;
+        let raw = &self.raw.levels[index];
+        Some(Rectangle::new(
+            self.position(index),
+            Vector::new(raw.px_wid as f32, raw.px_hei as f32),
+        ))
+    }
+
+    /// Identifiers of the levels linked to the level.
+    pub fn neighbours(&self, level: &str) -> Vec<&str> {
+        let index = match self.index(level) {
+            Some(index) => index,
+            None => return Vec::new(),
+        };
+        let levels = &self.raw.levels;
+        if self.is_linear() {
+            // The linear layouts only link the levels next to each other
+            return levels
+                .iter()
+                .enumerate()
+                .filter(|(other, _)| *other + 1 == index || *other == index + 1)
+                .map(|(_, level)| level.identifier.as_str())
+                .collect();
+        }
+        levels[index]
+            .neighbours
+            .iter()
+            .filter_map(|neighbour| {
+                levels.iter().find(|level| match neighbour {
+                    RawNeighbour {
+                        level_iid: Some(iid),
+                        ..
+                    } => level.iid == *iid,
+                    RawNeighbour {
+                        level_uid: Some(uid),
+                        ..
+                    } => level.uid == *uid,
+                    _ => false,
+                })
+            })
+            .map(|level| level.identifier.as_str())
+            .collect()
+    }
+
+    /// Build the tilemap and collect the entities of the level, the tilesets must be loaded images.
+    pub fn level(&self, level: &str, images: &Images) -> Result<Level, LevelError> {
+        self.level_with(level, loaded_images(images))
+    }
+
+    fn level_with(
+        &self,
+        level: &str,
+        image: impl Fn(&str) -> Option<Handle<Image>>,
+    ) -> Result<Level, LevelError> {
+        let path = self.path.as_str();
+        let missing = || LevelError::MissingLevel {
+            path: path.into(),
+            level: level.into(),
+        };
+        let index = self.index(level).ok_or_else(missing)?;
+        let raw = &self.raw.levels[index];
+        let layers = raw.layer_instances.as_ref().ok_or_else(missing)?;
+        let position = self.position(index);
+
+        let mut tilemap = layers.iter().find(|layer| layer.has_tiles()).map(|layer| {
+            let grid = layer.grid_size as f32;
+            Tilemap::new(layer.width, layer.height, Vector::new(grid, grid))
+        });
+        // Index in the tilemap of the tilesets by uid
+        let mut tilesets = FxHashMap::default();
+        let mut solid = Vec::new();
+        let mut objects = Vec::new();
+        // The first layer is drawn on top
+        for layer in layers.iter().rev() {
+            if (layer.offset_x, layer.offset_y) != (0, 0) {
+                return Err(LevelError::Unsupported {
+                    path: path.into(),
+                    feature: format!("the offset of the layer `{}`", layer.identifier),
+                });
+            }
+            if layer.kind == "Entities" {
+                for entity in layer.entities.iter() {
+                    objects.push(entity.build(path, position)?);
+                }
+                continue;
+            }
+            let tilemap = match tilemap.as_mut() {
+                Some(tilemap) if layer.has_tiles() => tilemap,
+                _ => {
+                    warn!("Skipping the {} layer `{}`", layer.kind, layer.identifier);
+                    continue;
+                }
+            };
+            if (layer.width, layer.height, layer.grid_size as f32)
+                != (tilemap.width(), tilemap.height(), tilemap.tile_size.x)
+            {
+                return Err(LevelError::Unsupported {
+                    path: path.into(),
+                    feature: format!(
+                        "layers of different grid sizes, like `{}`",
+                        layer.identifier
+                    ),
+                });
+            }
+            let tiles = if layer.kind == "Tiles" {
+                &layer.grid_tiles
+            } else {
+                &layer.auto_tiles
+            };
+            if !tiles.is_empty() {
+                let uid = layer.override_tileset.or(layer.tileset).ok_or_else(|| {
+                    parse_error(
+                        path,
+                        format!("layer `{}` has tiles but no tileset", layer.identifier),
+                    )
+                })?;
+                let tileset = match tilesets.get(&uid) {
+                    Some(tileset) => *tileset,
+                    None => {
+                        let tileset = tilemap.add_tileset(self.tileset(uid, &image)?);
+                        tilesets.insert(uid, tileset);
+                        tileset
+                    }
+                };
+                add_tiles(tilemap, layer, tileset, tiles);
+            }
+            if layer.kind == "IntGrid" {
+                solid.extend(
+                    layer
+                        .int_grid
+                        .iter()
+                        .enumerate()
+                        .filter(|(_, value)| **value != 0)
+                        .map(|(cell, _)| (cell as u32 % layer.width, cell as u32 / layer.width)),
+                );
+                if tilemap.collision.is_none() {
+                    let collision = tilemap.add_layer(COLLISION_LAYER, 0);
+                    tilemap.collision = Some(TileCollision::solid(collision));
+                }
+            }
+        }
+        if let Some(tilemap) = tilemap.as_mut() {
+            if let Some(collision) = tilemap.collision {
+                if let Some(layer) = tilemap.layer_mut(collision.layer) {
+                    layer.visible = false;
+                }
+                for (x, y) in solid {
+                    tilemap.set_tile(collision.layer, x, y, Some(Tile::new(0)));
+                }
+            }
+        }
+
+        Ok(Level {
+            bounds: Rectangle::new(position, Vector::new(raw.px_wid as f32, raw.px_hei as f32)),
+            tilemaps: tilemap
+                .filter(|tilemap| !tilemap.layers().is_empty())
+                .map(|tilemap| (position, tilemap))
+                .into_iter()
+                .collect(),
+            objects,
+        })
+    }
+
+    fn tileset(
+        &self,
+        uid: i64,
+        image: impl Fn(&str) -> Option<Handle<Image>>,
+    ) -> Result<Tileset, LevelError> {
+        let raw = match self.raw.defs.tilesets.iter().find(|raw| raw.uid == uid) {
+            Some(raw) => raw,
+            None => return Err(parse_error(&self.path, format!("no tileset {}", uid))),
+        };
+        let file = match &raw.rel_path {
+            Some(file) => file,
+            None => {
+                return Err(LevelError::Unsupported {
+                    path: self.path.clone(),
+                    feature: format!("the embedded atlas of the tileset `{}`", raw.identifier),
+                })
+            }
+        };
+        let size = raw.tile_grid_size as f32;
+        Ok(Tileset::grid(
+            tileset_image(image, &raw.identifier, file)?,
+            Vector::new(size, size),
+            raw.columns,
+            raw.columns * raw.rows,
+            raw.padding as f32,
+            raw.spacing as f32,
+        ))
+    }
+
+    fn index(&self, level: &str) -> Option<usize> {
+        self.raw
+            .levels
+            .iter()
+            .position(|raw| raw.identifier == level)
+    }
+
+    fn is_linear(&self) -> bool {
+        matches!(
+            self.raw.world_layout.as_deref(),
+            Some("LinearHorizontal") | Some("LinearVertical")
+        )
+    }
+
+    // The linear layouts leave the world coordinates out, the levels follow each other
+    fn position(&self, index: usize) -> Vector {
+        let before = &self.raw.levels[..index];
+        match self.raw.world_layout.as_deref() {
+            Some("LinearHorizontal") => {
+                Vector::new(before.iter().map(|level| level.px_wid as f32).sum(), 0.)
+            }
+            Some("LinearVertical") => {
+                Vector::new(0., before.iter().map(|level| level.px_hei as f32).sum())
+            }
+            _ => {
+                let level = &self.raw.levels[index];
+                Vector::new(level.world_x as f32, level.world_y as f32)
+            }
+        }
+    }
+}
+
+// The auto-layer rules can stack tiles in a cell, each goes above the ones before it
+fn add_tiles(tilemap: &mut Tilemap, layer: &RawLayer, tileset: usize, tiles: &[RawTile]) {
+    let mut stack: Vec<usize> = Vec::new();
+    for raw in tiles {
+        let (x, y) = (
+            raw.px[0] / layer.grid_size as i32,
+            raw.px[1] / layer.grid_size as i32,
+        );
+        if x < 0 || y < 0 {
+            continue;
+        }
+        let (x, y) = (x as u32, y as u32);
+        let above = stack
+            .iter()
+            .rposition(|index| tilemap.tile(*index, x, y).is_some())
+            .map_or(0, |depth| depth + 1);
+        let index = match stack.get(above) {
+            Some(index) => *index,
+            None => {
+                let index = tilemap.add_layer(&layer.identifier, tileset);
+                if let Some(added) = tilemap.layer_mut(index) {
+                    added.visible = layer.visible;
+                }
+                stack.push(index);
+                index
+            }
+        };
+        let tile = Tile {
+            id: raw.t,
+            flip_x: raw.f & FLIPPED_X != 0,
+            flip_y: raw.f & FLIPPED_Y != 0,
+        };
+        tilemap.set_tile(index, x, y, Some(tile));
+    }
+}
+
+fn contains(area: Rectangle, point: Vector) -> bool {
+    point.x >= area.pos.x
+        && point.y >= area.pos.y
+        && point.x < area.pos.x + area.size.x
+        && point.y < area.pos.y + area.size.y
+}
+
+/// One level of a project spawned at a time, swapped for the linked level the player walks into.
+pub struct LevelTravel {
+    project: LdtkProject,
+    current: Option<String>,
+    spawned: Vec<Entity>,
+    // Of the persistent types, despawned only when leaving the project
+    kept: Vec<Entity>,
+    // Not entered again until the point leaves it
+    failed: Option<String>,
+    /// Types spawned only with the first level, they come along to the next ones
+    pub persistent: Vec<String>,
+}
+
+impl LevelTravel {
+    pub fn new(project: LdtkProject) -> Self {
+        Self {
+            project,
+            current: None,
+            spawned: Vec::new(),
+            kept: Vec::new(),
+            failed: None,
+            persistent: Vec::new(),
+        }
+    }
+
+    pub fn project(&self) -> &LdtkProject {
+        &self.project
+    }
+    /// The spawned level.
+    pub fn current(&self) -> Option<&str> {
+        self.current.as_deref()
+    }
+
+    /// The linked level the point is in, once it left the current level.
+    pub fn linked_at(&self, point: Vector) -> Option<&str> {
+        let current = self.current.as_deref()?;
+        if self
+            .project
+            .bounds(current)
+            .map_or(false, |bounds| contains(bounds, point))
+        {
+            return None;
+        }
+        self.project.neighbours(current).into_iter().find(|level| {
+            self.project
+                .bounds(level)
+                .map_or(false, |bounds| contains(bounds, point))
+        })
+    }
+
+    /// The linked level to enter for the point, like `linked_at`.
+    ///
+    /// The level that failed to enter is skipped until the point leaves it, so a broken level
+    /// isn't imported again every tick.
+    pub fn level_to_enter(&mut self, point: Vector) -> Option<String> {
+        let level = self.linked_at(point).map(String::from);
+        if level.is_some() && level == self.failed {
+            return None;
+        }
+        self.failed = None;
+        level
+    }
+
+    /// Replace the spawned level with `level`, returns its bounds.
+    ///
+    /// The new level is spawned before the current one goes, so the current level stays if the
+    /// new one fails to import, has unknown types or one of its spawners fails.
+    pub fn enter(
+        &mut self,
+        level: &str,
+        world: &mut World,
+        resources: &Resources,
+        images: &Images,
+        registry: &EntityRegistry,
+    ) -> Result<Rectangle, LevelError> {
+        // Until it spawned
+        self.failed = Some(level.into());
+        let mut imported = self.project.level(level, images)?;
+        let persistent = &self.persistent;
+        if self.current.is_some() {
+            imported
+                .objects
+                .retain(|object| !persistent.contains(&object.kind));
+        }
+        // The persistent entities aren't despawned with the level
+        let tilemaps = imported.tilemaps.len();
+        let travelling: Vec<bool> = imported
+            .objects
+            .iter()
+            .map(|object| persistent.contains(&object.kind))
+            .collect();
+        let bounds = imported.bounds;
+        let (kept, spawned): (Vec<_>, Vec<_>) = imported
+            .spawn(world, resources, images, registry)?
+            .into_iter()
+            .enumerate()
+            .partition(|(index, _)| *index >= tilemaps && travelling[index - tilemaps]);
+        {
+            let mut pworld = resources
+                .get_mut::<PhysicsWorld>()
+                .expect("PhysicsWorld missing somehow");
+            despawn(world, &mut pworld, &self.spawned);
+        }
+        self.kept.extend(kept.into_iter().map(|(_, entity)| entity));
+        self.spawned = spawned.into_iter().map(|(_, entity)| entity).collect();
+        self.current = Some(level.into());
+        self.failed = None;
+        Ok(bounds)
+    }
+
+    /// Despawn the current level along with the persistent entities.
+    pub fn leave(&mut self, world: &mut World, pworld: &mut PhysicsWorld) {
+        despawn(world, pworld, &self.spawned);
+        despawn(world, pworld, &self.kept);
+        self.spawned.clear();
+        self.kept.clear();
+        self.current = None;
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RawProject {
+    world_layout: Option<String>,
+    defs: RawDefs,
+    #[serde(default)]
+    levels: Vec<RawLevel>,
+    #[serde(default)]
+    worlds: Vec<Value>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RawDefs {
+    #[serde(default)]
+    tilesets: Vec<RawTileset>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RawTileset {
+    uid: i64,
+    identifier: String,
+    rel_path: Option<String>,
+    tile_grid_size: u32,
+    #[serde(default)]
+    spacing: u32,
+    #[serde(default)]
+    padding: u32,
+    #[serde(rename = "__cWid")]
+    columns: u32,
+    #[serde(rename = "__cHei")]
+    rows: u32,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RawLevel {
+    identifier: String,
+    #[serde(default)]
+    iid: String,
+    uid: i64,
+    world_x: i32,
+    world_y: i32,
+    px_wid: u32,
+    px_hei: u32,
+    external_rel_path: Option<String>,
+    layer_instances: Option<Vec<RawLayer>>,
+    #[serde(default, rename = "__neighbours")]
+    neighbours: Vec<RawNeighbour>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RawNeighbour {
+    // Older versions refer to the uid
+    level_iid: Option<String>,
+    level_uid: Option<i64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RawLayer {
+    #[serde(rename = "__identifier")]
+    identifier: String,
+    #[serde(rename = "__type")]
+    kind: String,
+    #[serde(rename = "__cWid")]
+    width: u32,
+    #[serde(rename = "__cHei")]
+    height: u32,
+    #[serde(rename = "__gridSize")]
+    grid_size: u32,
+    #[serde(rename = "__tilesetDefUid")]
+    tileset: Option<i64>,
+    #[serde(default, rename = "overrideTilesetUid")]
+    override_tileset: Option<i64>,
+    #[serde(default, rename = "__pxTotalOffsetX")]
+    offset_x: i32,
+    #[serde(default, rename = "__pxTotalOffsetY")]
+    offset_y: i32,
+    #[serde(default = "visible")]
+    visible: bool,
+    #[serde(default, rename = "intGridCsv")]
+    int_grid: Vec<i64>,
+    #[serde(default, rename = "autoLayerTiles")]
+    auto_tiles: Vec<RawTile>,
+    #[serde(default, rename = "gridTiles")]
+    grid_tiles: Vec<RawTile>,
+    #[serde(default, rename = "entityInstances")]
+    entities: Vec<RawEntity>,
+}
+
+fn visible() -> bool {
+    true
+}
+
+impl RawLayer {
+    fn has_tiles(&self) -> bool {
+        matches!(self.kind.as_str(), "Tiles" | "AutoLayer" | "IntGrid")
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct RawTile {
+    px: [i32; 2],
+    #[serde(default)]
+    f: u8,
+    t: u32,
+}
+
+#[derive(Debug, Deserialize)]
+struct RawEntity {
+    #[serde(rename = "__identifier")]
+    identifier: String,
+    #[serde(rename = "__pivot")]
+    pivot: [f32; 2],
+    px: [f32; 2],
+    width: f32,
+    height: f32,
+    #[serde(default, rename = "fieldInstances")]
+    fields: Vec<RawField>,
+}
+
+impl RawEntity {
+    // Placed by the pivot, relative to the level
+    fn build(&self, path: &str, level: Vector) -> Result<LevelObject, LevelError> {
+        let size = Vector::new(self.width, self.height);
+        let corner = Vector::new(
+            self.px[0] - self.pivot[0] * self.width,
+            self.px[1] - self.pivot[1] * self.height,
+        );
+        let mut object = LevelObject {
+            name: String::new(),
+            kind: self.identifier.clone(),
+            position: level + corner + size / 2.,
+            size,
+            properties: Properties::default(),
+        };
+        for field in self.fields.iter() {
+            if let Some(value) = field.value(path, &object)? {
+                object.properties.insert(field.identifier.clone(), value);
+            }
+        }
+        Ok(object)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct RawField {
+    #[serde(rename = "__identifier")]
+    identifier: String,
+    #[serde(rename = "__type")]
+    kind: String,
+    #[serde(rename = "__value")]
+    value: Value,
+}
+
+impl RawField {
+    // `None` for the fields left empty
+    fn value(&self, path: &str, object: &LevelObject) -> Result<Option<PropertyValue>, LevelError> {
+        let bad = |expected| object.bad_property(&self.identifier, expected);
+        let is_enum = self.kind.starts_with("LocalEnum.")
+            || self.kind.starts_with("ExternEnum.")
+            || self.kind.starts_with("Enum(");
+        let value = match (self.kind.as_str(), &self.value) {
+            (_, Value::Null) => return Ok(None),
+            ("Int", value) => PropertyValue::Int(value.as_i64().ok_or_else(|| bad("an int"))?),
+            ("Float", value) => {
+                PropertyValue::Float(value.as_f64().ok_or_else(|| bad("a float"))? as f32)
+            }
+            ("Bool", Value::Bool(value)) => PropertyValue::Bool(*value),
+            ("Bool", _) => return Err(bad("a bool")),
+            ("String", Value::String(value))
+            | ("Multilines", Value::String(value))
+            | ("Color", Value::String(value))
+            | ("FilePath", Value::String(value)) => PropertyValue::String(value.clone()),
+            (_, Value::String(value)) if is_enum => PropertyValue::String(value.clone()),
+            ("String", _) | ("Multilines", _) | ("Color", _) | ("FilePath", _) => {
+                return Err(bad("a string"))
+            }
+            (_, _) if is_enum => return Err(bad("a string")),
+            (other, _) => {
+                return Err(LevelError::Unsupported {
+                    path: path.into(),
+                    feature: format!("the {} field `{}` of {}", other, self.identifier, object),
+                })
+            }
+        };
+        Ok(Some(value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::assets::AssetStore;
+
+    // Three levels in a row, only the first one has tiles
+    const PROJECT: &str = r#"{
+        "jsonVersion": "1.1.3", "worldLayout": "GridVania",
+        "defs": {
+            "layers": [],
+            "tilesets": [{
+                "uid": 1, "identifier": "tiles", "relPath": "../tiles.png",
+                "pxWid": 16, "pxHei": 16, "tileGridSize": 8, "spacing": 0, "padding": 0,
+                "__cWid": 2, "__cHei": 2
+            }]
+        },
+        "levels": [{
+            "identifier": "A", "iid": "a", "uid": 0, "worldX": 0, "worldY": 0,
+            "pxWid": 24, "pxHei": 16, "externalRelPath": null,
+            "__neighbours": [{ "levelIid": "b", "dir": "e" }],
+            "layerInstances": [{
+                "__identifier": "Entities", "__type": "Entities", "__cWid": 3, "__cHei": 2,
+                "__gridSize": 8, "__tilesetDefUid": null, "visible": true,
+                "entityInstances": [{
+                    "__identifier": "player", "__pivot": [0.5, 1], "px": [12, 16],
+                    "width": 8, "height": 16,
+                    "fieldInstances": [
+                        { "__identifier": "speed", "__type": "Float", "__value": 1.5 },
+                        { "__identifier": "lives", "__type": "Int", "__value": 3 },
+                        { "__identifier": "item", "__type": "LocalEnum.Item", "__value": "Key" },
+                        { "__identifier": "note", "__type": "String", "__value": null }
+                    ]
+                }]
+            }, {
+                "__identifier": "decor", "__type": "Tiles", "__cWid": 3, "__cHei": 2,
+                "__gridSize": 8, "__tilesetDefUid": 1, "visible": true,
+                "gridTiles": [{ "px": [0, 0], "src": [8, 8], "f": 1, "t": 3 }]
+            }, {
+                "__identifier": "walls", "__type": "IntGrid", "__cWid": 3, "__cHei": 2,
+                "__gridSize": 8, "__tilesetDefUid": 1, "visible": true,
+                "intGridCsv": [0, 0, 0, 1, 1, 0],
+                "autoLayerTiles": [
+                    { "px": [0, 8], "src": [0, 0], "f": 0, "t": 0 },
+                    { "px": [8, 8], "src": [8, 0], "f": 0, "t": 1 },
+                    { "px": [8, 8], "src": [0, 8], "f": 2, "t": 2 }
+                ]
+            }]
+        }, {
+            "identifier": "B", "iid": "b", "uid": 1, "worldX": 24, "worldY": 0,
+            "pxWid": 24, "pxHei": 16, "externalRelPath": null,
+            "__neighbours": [{ "levelIid": "a", "dir": "w" }, { "levelUid": 2, "dir": "e" }],
+            "layerInstances": [{
+                "__identifier": "Entities", "__type": "Entities", "__cWid": 3, "__cHei": 2,
+                "__gridSize": 8, "__tilesetDefUid": null, "visible": true,
+                "entityInstances": [
+                    { "__identifier": "player", "__pivot": [0, 1], "px": [4, 16], "width": 8, "height": 8 },
+                    { "__identifier": "coin", "__pivot": [0.5, 0.5], "px": [16, 8], "width": 4, "height": 4 }
+                ]
+            }]
+        }, {
+            "identifier": "C", "iid": "c", "uid": 2, "worldX": 48, "worldY": 0,
+            "pxWid": 24, "pxHei": 16, "externalRelPath": "project/C.ldtkl",
+            "__neighbours": [{ "levelIid": "b", "dir": "w" }],
+            "layerInstances": null
+        }]
+    }"#;
+
+    const LEVEL_C: &str = r#"{
+        "identifier": "C", "iid": "c", "uid": 2, "worldX": 48, "worldY": 0,
+        "pxWid": 24, "pxHei": 16,
+        "layerInstances": [{
+            "__identifier": "Entities", "__type": "Entities", "__cWid": 3, "__cHei": 2,
+            "__gridSize": 8, "__tilesetDefUid": null, "visible": true,
+            "entityInstances": [
+                { "__identifier": "coin", "__pivot": [0, 0], "px": [8, 0], "width": 4, "height": 4 }
+            ]
+        }]
+    }"#;
+
+    // Every image is loaded
+    fn any_image(name: &str) -> Option<Handle<Image>> {
+        Some(AssetStore::<Image>::default().reserve(name))
+    }
+
+    fn project() -> LdtkProject {
+        let mut project = LdtkProject::parse("levels/test.ldtk", PROJECT.as_bytes()).unwrap();
+        assert_eq!(
+            project.external_levels(),
+            vec!["project/C.ldtkl".to_string()]
+        );
+        project
+            .resolve_level(
+                "project/C.ldtkl",
+                "levels/project/C.ldtkl",
+                LEVEL_C.as_bytes(),
+            )
+            .unwrap();
+        assert!(project.external_levels().is_empty());
+        project
+    }
+
+    #[test]
+    fn layers_and_entities() {
+        let tiles = AssetStore::<Image>::default().reserve("tiles");
+        let level = project()
+            .level_with("A", |name| if name == "tiles" { Some(tiles) } else { None })
+            .unwrap();
+        assert_eq!(level.bounds, Rectangle::new((0., 0.), (24., 16.)));
+
+        let (_, tilemap) = &level.tilemaps[0];
+        let names: Vec<_> = tilemap
+            .layers()
+            .iter()
+            .map(|layer| layer.name.as_str())
+            .collect();
+        assert_eq!(names, vec!["walls", "walls", COLLISION_LAYER, "decor"]);
+        // The stacked tile went one layer up
+        assert_eq!(tilemap.tile(0, 1, 1), Some(Tile::new(1)));
+        let flipped = Tile {
+            flip_y: true,
+            ..Tile::new(2)
+        };
+        assert_eq!(tilemap.tile(1, 1, 1), Some(flipped));
+        assert_eq!(tilemap.tile(1, 0, 1), None);
+        assert_eq!(tilemap.tile(3, 0, 0).map(|tile| tile.flip_x), Some(true));
+
+        assert_eq!(tilemap.collision, Some(TileCollision::solid(2)));
+        assert!(!tilemap.layers()[2].visible);
+        let solid: Vec<_> = (0..2)
+            .flat_map(|y| (0..3).map(move |x| (x, y)))
+            .filter(|(x, y)| tilemap.tile(2, *x, *y).is_some())
+            .collect();
+        assert_eq!(solid, vec![(0, 1), (1, 1)]);
+
+        let player = &level.objects[0];
+        assert_eq!(player.position, Vector::new(12., 8.));
+        assert_eq!(player.float("speed").unwrap(), Some(1.5));
+        assert_eq!(player.int("lives").unwrap(), Some(3));
+        assert_eq!(player.string("item").unwrap(), Some("Key"));
+        assert_eq!(player.string("note").unwrap(), None);
+    }
+
+    #[test]
+    fn linked_levels() {
+        let project = project();
+        assert_eq!(project.neighbours("B"), vec!["A", "C"]);
+        assert_eq!(project.neighbours("C"), vec!["B"]);
+        let level = project.level_with("C", |_| None).unwrap();
+        assert!(level.tilemaps.is_empty());
+        // Placed in the world
+        assert_eq!(level.objects[0].position, Vector::new(58., 2.));
+
+        let linear = PROJECT.replace("GridVania", "LinearVertical");
+        let project = LdtkProject::parse("test.ldtk", linear.as_bytes()).unwrap();
+        assert_eq!(
+            project.bounds("B"),
+            Some(Rectangle::new((0., 16.), (24., 16.)))
+        );
+        assert_eq!(project.neighbours("A"), vec!["B"]);
+    }
+
+    #[test]
+    fn travel_between_levels() {
+        let mut world = Universe::new().create_world();
+        let mut resources = Resources::default();
+        resources.insert(PhysicsWorld::new());
+        let images = Images::default();
+        let registry =
+            ["player", "coin"]
+                .iter()
+                .fold(EntityRegistry::default(), |registry, kind| {
+                    registry.with(kind, |ctx, object| {
+                        Ok(ctx
+                            .world
+                            .insert((), vec![(object.kind.clone(), object.position)])[0])
+                    })
+                });
+        let spawned = |world: &World| {
+            let mut spawned: Vec<_> = <(Read<String>, Read<Vector>)>::query()
+                .iter(world)
+                .map(|(kind, position)| (kind.to_string(), *position))
+                .collect();
+            spawned.sort_by(|a, b| a.0.cmp(&b.0));
+            spawned
+        };
+
+        let mut travel = LevelTravel::new(project());
+        travel.persistent = vec!["player".into()];
+        let bounds = travel
+            .enter("B", &mut world, &resources, &images, &registry)
+            .unwrap();
+        assert_eq!(bounds, Rectangle::new((24., 0.), (24., 16.)));
+        assert_eq!(spawned(&world).len(), 2);
+
+        assert_eq!(travel.linked_at(Vector::new(30., 8.)), None);
+        assert_eq!(travel.linked_at(Vector::new(50., 8.)), Some("C"));
+        assert_eq!(travel.linked_at(Vector::new(10., 8.)), Some("A"));
+        assert_eq!(travel.linked_at(Vector::new(30., 40.)), None);
+
+        // The tileset of A isn't loaded, B stays
+        assert!(travel
+            .enter("A", &mut world, &resources, &images, &registry)
+            .is_err());
+        assert_eq!(travel.current(), Some("B"));
+        assert_eq!(spawned(&world).len(), 2);
+
+        // The coin of C fails to spawn, B stays as well
+        let broken = EntityRegistry::default().with("coin", |_, object| {
+            Err(object.bad_property("value", "an int"))
+        });
+        assert!(travel
+            .enter("C", &mut world, &resources, &images, &broken)
+            .is_err());
+        assert_eq!(travel.current(), Some("B"));
+        assert_eq!(spawned(&world).len(), 2);
+        // Not tried again until the point leaves C
+        assert_eq!(travel.level_to_enter(Vector::new(50., 8.)), None);
+        assert_eq!(travel.level_to_enter(Vector::new(30., 8.)), None);
+        assert_eq!(
+            travel.level_to_enter(Vector::new(50., 8.)),
+            Some("C".to_string())
+        );
+
+        travel
+            .enter("C", &mut world, &resources, &images, &registry)
+            .unwrap();
+        assert_eq!(travel.current(), Some("C"));
+        assert_eq!(
+            spawned(&world),
+            vec![
+                ("coin".to_string(), Vector::new(58., 2.)),
+                ("player".to_string(), Vector::new(32., 12.))
+            ]
+        );
+    }
+
+    #[test]
+    fn clear_errors() {
+        let project = project();
+        assert!(matches!(
+            project.level_with("D", |_| None),
+            Err(LevelError::MissingLevel { .. })
+        ));
+        assert!(matches!(
+            project.level_with("A", |_| None),
+            Err(LevelError::MissingTileset { .. })
+        ));
+
+        let point = PROJECT.replace(r#""Float", "__value": 1.5"#, r#""Point", "__value": {}"#);
+        let project = LdtkProject::parse("test.ldtk", point.as_bytes()).unwrap();
+        match project.level_with("A", any_image) {
+            Err(LevelError::Unsupported { feature, .. }) => assert!(feature.contains("speed")),
+            other => panic!("unexpected {:?}", other.map(|level| level.objects)),
+        }
+
+        let bad = PROJECT.replace(r#""Int", "__value": 3"#, r#""Int", "__value": "three""#);
+        let project = LdtkProject::parse("test.ldtk", bad.as_bytes()).unwrap();
+        match project.level_with("A", any_image) {
+            Err(LevelError::BadProperty { property, .. }) => assert_eq!(property, "lives"),
+            other => panic!("unexpected {:?}", other.map(|level| level.objects)),
+        }
+    }
+}
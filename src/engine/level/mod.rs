@@ -0,0 +1,382 @@
+/*!
+Levels made in external editors, spawned through a registry of entity types.
+
+The importers turn their format into a `Level`, tilemaps placed in the world and objects with a
+type, a rectangle and properties. The objects are spawned by the function registered for their
+type, the same ones the hand written levels call.
+*/
+use super::assets::Handle;
+use super::tilemap::{self, Tilemap};
+use crate::gfx::Images;
+use crate::phx::{Hitbox, PhysicsWorld, TileColliders};
+use fxhash::FxHashMap;
+use legion::prelude::*;
+use quicksilver::geom::{Rectangle, Vector};
+use quicksilver::graphics::Image;
+use quicksilver::QuicksilverError;
+use std::fmt;
+
+pub mod ldtk;
+pub mod tiled;
+
+#[derive(Debug)]
+pub enum LevelError {
+    Load {
+        path: String,
+        error: QuicksilverError,
+    },
+    /// The file isn't a valid level
+    Parse { path: String, message: String },
+    /// Valid for the editor, but the importer can't handle it
+    Unsupported { path: String, feature: String },
+    /// The image of the tileset isn't loaded, `None` when the tileset file itself wasn't
+    MissingTileset {
+        tileset: String,
+        image: Option<String>,
+    },
+    /// Not in the project, or in a level file that isn't loaded
+    MissingLevel { path: String, level: String },
+    /// No spawner is registered for the type
+    UnknownType { object: String, kind: String },
+    BadProperty {
+        object: String,
+        property: String,
+        expected: &'static str,
+    },
+}
+
+impl fmt::Display for LevelError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LevelError::Load { path, error } => write!(f, "can't load `{}`: {}", path, error),
+            LevelError::Parse { path, message } => {
+                write!(f, "`{}` is malformed: {}", path, message)
+            }
+            LevelError::Unsupported { path, feature } => {
+                write!(f, "`{}` uses {}, which isn't supported", path, feature)
+            }
+            LevelError::MissingTileset {
+                tileset,
+                image: Some(image),
+            } => write!(
+                f,
+                "tileset `{}` needs the image `{}`, but neither name is a loaded image",
+                tileset, image
+            ),
+            LevelError::MissingTileset {
+                tileset,
+                image: None,
+            } => write!(f, "tileset `{}` isn't loaded", tileset),
+            LevelError::MissingLevel { path, level } => {
+                write!(f, "level `{}` of `{}` isn't loaded", level, path)
+            }
+            LevelError::UnknownType { object, kind } => {
+                write!(f, "{} has the type `{}` which nothing spawns", object, kind)
+            }
+            LevelError::BadProperty {
+                object,
+                property,
+                expected,
+            } => write!(
+                f,
+                "property `{}` of {} should be {}",
+                property, object, expected
+            ),
+        }
+    }
+}
+
+impl std::error::Error for LevelError {}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PropertyValue {
+    Bool(bool),
+    Int(i64),
+    Float(f32),
+    String(String),
+}
+
+pub type Properties = FxHashMap<String, PropertyValue>;
+
+/// Something placed in the level to be spawned.
+#[derive(Debug, Clone)]
+pub struct LevelObject {
+    /// Name of the object in the editor, for the error messages
+    pub name: String,
+    /// Picks the spawner
+    pub kind: String,
+    /// Center of the object in the world
+    pub position: Vector,
+    /// Zero for the points
+    pub size: Vector,
+    pub properties: Properties,
+}
+
+impl LevelObject {
+    /// Covered area in the world.
+    pub fn area(&self) -> Rectangle {
+        Rectangle::new(self.position - self.size / 2., self.size)
+    }
+
+    pub fn bool(&self, property: &str) -> Result<Option<bool>, LevelError> {
+        match self.properties.get(property) {
+            None => Ok(None),
+            Some(PropertyValue::Bool(value)) => Ok(Some(*value)),
+            Some(_) => Err(self.bad_property(property, "a bool")),
+        }
+    }
+    pub fn int(&self, property: &str) -> Result<Option<i64>, LevelError> {
+        match self.properties.get(property) {
+            None => Ok(None),
+            Some(PropertyValue::Int(value)) => Ok(Some(*value)),
+            Some(_) => Err(self.bad_property(property, "an int")),
+        }
+    }
+    /// Ints are accepted as well.
+    pub fn float(&self, property: &str) -> Result<Option<f32>, LevelError> {
+        match self.properties.get(property) {
+            None => Ok(None),
+            Some(PropertyValue::Float(value)) => Ok(Some(*value)),
+            Some(PropertyValue::Int(value)) => Ok(Some(*value as f32)),
+            Some(_) => Err(self.bad_property(property, "a number")),
+        }
+    }
+    pub fn string(&self, property: &str) -> Result<Option<&str>, LevelError> {
+        match self.properties.get(property) {
+            None => Ok(None),
+            Some(PropertyValue::String(value)) => Ok(Some(value)),
+            Some(_) => Err(self.bad_property(property, "a string")),
+        }
+    }
+
+    pub fn bad_property(&self, property: &str, expected: &'static str) -> LevelError {
+        LevelError::BadProperty {
+            object: self.to_string(),
+            property: property.into(),
+            expected,
+        }
+    }
+}
+
+impl fmt::Display for LevelObject {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.name.is_empty() {
+            write!(
+                f,
+                "`{}` at {}, {}",
+                self.kind, self.position.x, self.position.y
+            )
+        } else {
+            write!(f, "`{}`", self.name)
+        }
+    }
+}
+
+/// Everything an importer read from a level file.
+pub struct Level {
+    /// Top left corner and size of the level in the world
+    pub bounds: Rectangle,
+    /// With the positions of their top left corners
+    pub tilemaps: Vec<(Vector, Tilemap)>,
+    pub objects: Vec<LevelObject>,
+}
+
+impl Level {
+    /// Whether the registry spawns every object.
+    pub fn check(&self, registry: &EntityRegistry) -> Result<(), LevelError> {
+        match self
+            .objects
+            .iter()
+            .find(|object| !registry.contains(&object.kind))
+        {
+            Some(object) => Err(LevelError::UnknownType {
+                object: object.to_string(),
+                kind: object.kind.clone(),
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// Spawn the tilemaps and the objects. Nothing stays spawned if any object has an unknown type
+    /// or its spawner fails.
+    pub fn spawn(
+        self,
+        world: &mut World,
+        resources: &Resources,
+        images: &Images,
+        registry: &EntityRegistry,
+    ) -> Result<Vec<Entity>, LevelError> {
+        self.check(registry)?;
+        let mut entities = Vec::new();
+        for (position, tilemap) in self.tilemaps {
+            entities.push(tilemap::spawn(world, position, tilemap));
+        }
+        let mut ctx = SpawnContext {
+            world,
+            resources,
+            images,
+        };
+        for object in self.objects.iter() {
+            match registry.spawn(&mut ctx, object) {
+                Ok(entity) => entities.push(entity),
+                Err(err) => {
+                    let mut pworld = resources
+                        .get_mut::<PhysicsWorld>()
+                        .expect("PhysicsWorld missing somehow");
+                    despawn(ctx.world, &mut pworld, &entities);
+                    return Err(err);
+                }
+            }
+        }
+        Ok(entities)
+    }
+}
+
+/// What the spawners get to work with.
+pub struct SpawnContext<'a> {
+    pub world: &'a mut World,
+    pub resources: &'a Resources,
+    pub images: &'a Images,
+}
+
+type Spawner = Box<dyn Fn(&mut SpawnContext, &LevelObject) -> Result<Entity, LevelError>>;
+
+/// Spawn functions by the type of the object.
+#[derive(Default)]
+pub struct EntityRegistry {
+    spawners: FxHashMap<String, Spawner>,
+}
+
+impl EntityRegistry {
+    pub fn register(
+        &mut self,
+        kind: &str,
+        spawner: impl Fn(&mut SpawnContext, &LevelObject) -> Result<Entity, LevelError> + 'static,
+    ) {
+        self.spawners.insert(kind.into(), Box::new(spawner));
+    }
+    pub fn with(
+        mut self,
+        kind: &str,
+        spawner: impl Fn(&mut SpawnContext, &LevelObject) -> Result<Entity, LevelError> + 'static,
+    ) -> Self {
+        self.register(kind, spawner);
+        self
+    }
+    pub fn contains(&self, kind: &str) -> bool {
+        self.spawners.contains_key(kind)
+    }
+
+    pub fn spawn(
+        &self,
+        ctx: &mut SpawnContext,
+        object: &LevelObject,
+    ) -> Result<Entity, LevelError> {
+        match self.spawners.get(&object.kind) {
+            Some(spawner) => spawner(ctx, object),
+            None => Err(LevelError::UnknownType {
+                object: object.to_string(),
+                kind: object.kind.clone(),
+            }),
+        }
+    }
+}
+
+/// Remove the entities of a level from the world, with their bodies.
+///
+/// The only place the entities with a `Hitbox` or `TileColliders` should be deleted, after
+/// `world.delete` alone their bodies keep colliding until `phx::remove_orphan_bodies` runs.
+pub fn despawn(world: &mut World, pworld: &mut PhysicsWorld, entities: &[Entity]) {
+    for entity in entities.iter().copied() {
+        if let Some(hitbox) = world.get_component::<Hitbox>(entity) {
+            pworld.remove_body(hitbox.src);
+        }
+        if let Some(mut colliders) = world.get_component_mut::<TileColliders>(entity) {
+            colliders.clear(pworld);
+        }
+        world.delete(entity);
+    }
+}
+
+/// The loaded images by name, where the importers look for the tileset images.
+fn loaded_images(images: &Images) -> impl Fn(&str) -> Option<Handle<Image>> + '_ {
+    move |name| {
+        images
+            .handle(name)
+            .filter(|handle| images.is_loaded(*handle))
+    }
+}
+
+/// Image of the tileset, loaded under the name of the tileset or the file name of the image
+/// without the extension.
+fn tileset_image(
+    image: impl Fn(&str) -> Option<Handle<Image>>,
+    tileset: &str,
+    file: &str,
+) -> Result<Handle<Image>, LevelError> {
+    let name = file.rsplit('/').next().unwrap_or(file);
+    let stem = name.split('.').next().unwrap_or(name);
+    image(tileset)
+        .or_else(|| image(stem))
+        .ok_or_else(|| LevelError::MissingTileset {
+            tileset: tileset.into(),
+            image: Some(stem.into()),
+        })
+}
+
+fn parse_error(path: &str, message: impl ToString) -> LevelError {
+    LevelError::Parse {
+        path: path.into(),
+        message: message.to_string(),
+    }
+}
+
+async fn load_file(path: &str) -> Result<Vec<u8>, LevelError> {
+    quicksilver::load_file(path)
+        .await
+        .map_err(|error| LevelError::Load {
+            path: path.into(),
+            error,
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn object(kind: &str, x: f32) -> LevelObject {
+        LevelObject {
+            name: String::new(),
+            kind: kind.into(),
+            position: Vector::new(x, 0.),
+            size: Vector::ZERO,
+            properties: Properties::default(),
+        }
+    }
+
+    #[test]
+    fn failed_spawner_leaves_nothing_behind() {
+        let mut world = Universe::new().create_world();
+        let mut resources = Resources::default();
+        resources.insert(PhysicsWorld::new());
+        let registry = EntityRegistry::default()
+            .with("coin", |ctx, object| {
+                Ok(ctx.world.insert((), vec![(object.position,)])[0])
+            })
+            .with("broken", |_, object| {
+                Err(object.bad_property("value", "an int"))
+            });
+        let level = Level {
+            bounds: Rectangle::new((0., 0.), (16., 16.)),
+            tilemaps: vec![(Vector::ZERO, Tilemap::new(2, 2, Vector::new(8., 8.)))],
+            objects: vec![object("coin", 1.), object("broken", 2.), object("coin", 3.)],
+        };
+
+        match level.spawn(&mut world, &resources, &Images::default(), &registry) {
+            Err(LevelError::BadProperty { property, .. }) => assert_eq!(property, "value"),
+            other => panic!("unexpected {:?}", other),
+        }
+        assert_eq!(<Read<Vector>>::query().iter(&world).count(), 0);
+        assert_eq!(<Read<Tilemap>>::query().iter(&world).count(), 0);
+    }
+}
@@ -0,0 +1,1061 @@
+/*!
+Importer for the maps of the Tiled editor, in the TMX and the JSON format.
+
+Only the finite orthogonal maps are supported. The tile layers become the layers of one tilemap,
+a layer drawing from several tilesets is split into one layer per tileset. The layer with the
+bool property `collides` is the collision layer of the map. The objects are spawned by their
+type, or their class in the newer versions of the editor.
+
+The tileset images are looked up in the loaded images by the name of the tileset, then by the
+file name of the image without the extension.
+*/
+use super::{
+    load_file, loaded_images, parse_error, tileset_image, Level, LevelError, LevelObject,
+    Properties, PropertyValue,
+};
+use crate::engine::assets::Handle;
+use crate::engine::tilemap::{Tile, TileFrame, Tilemap, Tileset};
+use crate::gfx::animation::ms_to_ticks;
+use crate::gfx::atlas::sheet_path;
+use crate::gfx::Images;
+use crate::phx::TileCollision;
+use quicksilver::geom::{Rectangle, Vector};
+use quicksilver::graphics::Image;
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+const FLIPPED_X: u32 = 0x8000_0000;
+const FLIPPED_Y: u32 = 0x4000_0000;
+const FLIPPED_DIAGONALLY: u32 = 0x2000_0000;
+const ROTATED_HEXAGONAL: u32 = 0x1000_0000;
+const GID_MASK: u32 = !(FLIPPED_X | FLIPPED_Y | FLIPPED_DIAGONALLY | ROTATED_HEXAGONAL);
+
+/// A parsed map, with its external tilesets still to be loaded.
+pub struct TiledMap {
+    path: String,
+    raw: RawMap,
+}
+
+impl TiledMap {
+    /// Parse the map, TMX or JSON depending on the extension of `path`.
+    pub fn parse(path: &str, bytes: &[u8]) -> Result<Self, LevelError> {
+        let raw = if is_json(path) {
+            serde_json::from_slice(bytes).map_err(|err| parse_error(path, err))?
+        } else {
+            tmx::map(path, bytes)?
+        };
+        Ok(Self {
+            path: path.into(),
+            raw,
+        })
+    }
+
+    /// Read the map and its external tilesets.
+    pub async fn load(path: &str) -> Result<Self, LevelError> {
+        let mut map = Self::parse(path, &load_file(path).await?)?;
+        map.load_tilesets().await?;
+        Ok(map)
+    }
+
+    /// Read the external tilesets, relative to the map.
+    pub async fn load_tilesets(&mut self) -> Result<(), LevelError> {
+        for source in self.external_tilesets() {
+            let path = sheet_path(&self.path, &source);
+            let bytes = load_file(&path).await?;
+            self.resolve_tileset(&source, &path, &bytes)?;
+        }
+        Ok(())
+    }
+
+    /// Sources of the tilesets that aren't embedded in the map.
+    pub fn external_tilesets(&self) -> Vec<String> {
+        let mut sources: Vec<String> = self
+            .raw
+            .tilesets
+            .iter()
+            .filter(|tileset| tileset.image.is_none())
+            .filter_map(|tileset| tileset.source.clone())
+            .collect();
+        sources.dedup();
+        sources
+    }
+
+    /// Fill in the external tileset `source` with the file read from `path`.
+    pub fn resolve_tileset(
+        &mut self,
+        source: &str,
+        path: &str,
+        bytes: &[u8],
+    ) -> Result<(), LevelError> {
+        let tileset: RawTileset = if is_json(path) {
+            serde_json::from_slice(bytes).map_err(|err| parse_error(path, err))?
+        } else {
+            tmx::tileset(path, bytes)?
+        };
+        for slot in self.raw.tilesets.iter_mut() {
+            if slot.source.as_deref() == Some(source) {
+                *slot = RawTileset {
+                    firstgid: slot.firstgid,
+                    source: slot.source.take(),
+                    ..tileset.clone()
+                };
+            }
+        }
+        Ok(())
+    }
+
+    /// Build the tilemap and collect the objects, the tilesets must be loaded images.
+    pub fn into_level(self, images: &Images) -> Result<Level, LevelError> {
+        self.into_level_with(loaded_images(images))
+    }
+
+    fn into_level_with(
+        self,
+        image: impl Fn(&str) -> Option<Handle<Image>>,
+    ) -> Result<Level, LevelError> {
+        let path = self.path.as_str();
+        let raw = self.raw;
+        let unsupported = |feature: String| LevelError::Unsupported {
+            path: path.into(),
+            feature,
+        };
+        if raw.orientation != "orthogonal" {
+            return Err(unsupported(format!("the {} orientation", raw.orientation)));
+        }
+        if raw.infinite {
+            return Err(unsupported("an infinite map".into()));
+        }
+
+        let tile_size = Vector::new(raw.tilewidth as f32, raw.tileheight as f32);
+        let mut tilemap = Tilemap::new(raw.width, raw.height, tile_size);
+        // By first gid, with the index in the tilemap and the tile count
+        let mut tilesets = Vec::new();
+        for tileset in raw.tilesets.iter() {
+            let index = tilemap.add_tileset(tileset.build(&image)?);
+            tilesets.push((tileset.firstgid, index, tileset.tilecount));
+        }
+        tilesets.sort_by_key(|(firstgid, ..)| *firstgid);
+
+        let mut importer = Importer {
+            path,
+            tilemap,
+            tilesets,
+            objects: Vec::new(),
+        };
+        for layer in raw.layers.iter() {
+            importer.layer(layer, true)?;
+        }
+        Ok(Level {
+            bounds: Rectangle::new(Vector::ZERO, importer.tilemap.size()),
+            tilemaps: if importer.tilemap.layers().is_empty() {
+                Vec::new()
+            } else {
+                vec![(Vector::ZERO, importer.tilemap)]
+            },
+            objects: importer.objects,
+        })
+    }
+}
+
+struct Importer<'a> {
+    path: &'a str,
+    tilemap: Tilemap,
+    tilesets: Vec<(u32, usize, u32)>,
+    objects: Vec<LevelObject>,
+}
+
+impl Importer<'_> {
+    // The layers of the groups are flattened, hidden along with their group
+    fn layer(&mut self, layer: &RawLayer, visible: bool) -> Result<(), LevelError> {
+        match layer {
+            RawLayer::Tiles {
+                name,
+                width,
+                height,
+                visible: layer_visible,
+                data,
+                encoding,
+                compression,
+                properties,
+            } => {
+                if (*width, *height) != (self.tilemap.width(), self.tilemap.height()) {
+                    return Err(parse_error(
+                        self.path,
+                        format!("layer `{}` isn't the size of the map", name),
+                    ));
+                }
+                let gids = decode(self.path, data, encoding.as_deref(), compression.as_deref())?;
+                if gids.len() != *width as usize * *height as usize {
+                    return Err(parse_error(
+                        self.path,
+                        format!("layer `{}` has {} tiles", name, gids.len()),
+                    ));
+                }
+                let collides = property(self.path, name, properties, "collides")?;
+                let collides = match collides {
+                    None => false,
+                    Some(PropertyValue::Bool(collides)) => collides,
+                    Some(_) => {
+                        return Err(LevelError::BadProperty {
+                            object: format!("layer `{}`", name),
+                            property: "collides".into(),
+                            expected: "a bool",
+                        })
+                    }
+                };
+                self.tiles(name, &gids, visible && *layer_visible, collides)
+            }
+            RawLayer::Objects { objects, .. } => {
+                for object in objects {
+                    self.objects.push(object.build(self.path)?);
+                }
+                Ok(())
+            }
+            RawLayer::Group {
+                layers,
+                visible: group_visible,
+                ..
+            } => {
+                for layer in layers {
+                    self.layer(layer, visible && *group_visible)?;
+                }
+                Ok(())
+            }
+            RawLayer::Image {} => {
+                warn!("Skipping an image layer of `{}`", self.path);
+                Ok(())
+            }
+        }
+    }
+
+    fn tiles(
+        &mut self,
+        name: &str,
+        gids: &[u32],
+        visible: bool,
+        collides: bool,
+    ) -> Result<(), LevelError> {
+        // The cells of the layer by the tileset they come from
+        let mut split: BTreeMap<usize, Vec<(u32, u32, Tile)>> = BTreeMap::new();
+        let width = self.tilemap.width();
+        for (index, gid) in gids.iter().enumerate() {
+            if let Some((tileset, tile)) = self.tile(name, *gid)? {
+                let (x, y) = (index as u32 % width, index as u32 / width);
+                split.entry(tileset).or_default().push((x, y, tile));
+            }
+        }
+        if split.is_empty() {
+            // Still there to be edited
+            if let Some((_, tileset, _)) = self.tilesets.first() {
+                split.insert(*tileset, Vec::new());
+            }
+        }
+        if collides && (split.len() > 1 || self.tilemap.collision.is_some()) {
+            return Err(LevelError::Unsupported {
+                path: self.path.into(),
+                feature: format!(
+                    "the collision layer `{}` besides another one or several tilesets",
+                    name
+                ),
+            });
+        }
+        for (tileset, cells) in split {
+            let layer = self.tilemap.add_layer(name, tileset);
+            if let Some(layer) = self.tilemap.layer_mut(layer) {
+                layer.visible = visible;
+            }
+            for (x, y, tile) in cells {
+                self.tilemap.set_tile(layer, x, y, Some(tile));
+            }
+            if collides {
+                self.tilemap.collision = Some(TileCollision::solid(layer));
+            }
+        }
+        Ok(())
+    }
+
+    fn tile(&self, layer: &str, gid: u32) -> Result<Option<(usize, Tile)>, LevelError> {
+        if gid & GID_MASK == 0 {
+            return Ok(None);
+        }
+        if gid & (FLIPPED_DIAGONALLY | ROTATED_HEXAGONAL) != 0 {
+            return Err(LevelError::Unsupported {
+                path: self.path.into(),
+                feature: format!("rotated tiles in the layer `{}`", layer),
+            });
+        }
+        let id = gid & GID_MASK;
+        let found = self
+            .tilesets
+            .iter()
+            .rev()
+            .find(|(firstgid, ..)| *firstgid <= id)
+            .filter(|(firstgid, _, count)| id - firstgid < *count);
+        match found {
+            Some((firstgid, tileset, _)) => Ok(Some((
+                *tileset,
+                Tile {
+                    id: id - firstgid,
+                    flip_x: gid & FLIPPED_X != 0,
+                    flip_y: gid & FLIPPED_Y != 0,
+                },
+            ))),
+            None => Err(parse_error(
+                self.path,
+                format!("tile {} of the layer `{}` is in no tileset", id, layer),
+            )),
+        }
+    }
+}
+
+fn decode(
+    path: &str,
+    data: &RawData,
+    encoding: Option<&str>,
+    compression: Option<&str>,
+) -> Result<Vec<u32>, LevelError> {
+    let text = match data {
+        RawData::Gids(gids) => return Ok(gids.clone()),
+        RawData::Encoded(text) => text.trim(),
+    };
+    let bytes = match encoding {
+        Some("csv") => {
+            return text
+                .split(',')
+                .map(|gid| gid.trim().parse::<u32>())
+                .collect::<Result<_, _>>()
+                .map_err(|err| parse_error(path, err))
+        }
+        Some("base64") => base64::decode(text).map_err(|err| parse_error(path, err))?,
+        Some(other) => {
+            return Err(LevelError::Unsupported {
+                path: path.into(),
+                feature: format!("the {} encoding", other),
+            })
+        }
+        None => return Err(parse_error(path, "tile data without encoding")),
+    };
+    let bytes = match compression {
+        None | Some("") => bytes,
+        Some("zlib") => {
+            inflate::inflate_bytes_zlib(&bytes).map_err(|err| parse_error(path, err))?
+        }
+        Some(other) => {
+            return Err(LevelError::Unsupported {
+                path: path.into(),
+                feature: format!("{} compressed tiles", other),
+            })
+        }
+    };
+    if bytes.len() % 4 != 0 {
+        return Err(parse_error(path, "truncated tile data"));
+    }
+    Ok(bytes
+        .chunks(4)
+        .map(|gid| u32::from_le_bytes([gid[0], gid[1], gid[2], gid[3]]))
+        .collect())
+}
+
+// The layer properties are read for the importer itself, `owner` is the layer name
+fn property(
+    path: &str,
+    owner: &str,
+    properties: &[RawProperty],
+    name: &str,
+) -> Result<Option<PropertyValue>, LevelError> {
+    match properties.iter().find(|property| property.name == name) {
+        Some(property) => Ok(Some(property.value(path, &format!("layer `{}`", owner))?)),
+        None => Ok(None),
+    }
+}
+
+fn is_json(path: &str) -> bool {
+    path.ends_with(".json") || path.ends_with(".tmj") || path.ends_with(".tsj")
+}
+
+#[derive(Debug, Deserialize)]
+struct RawMap {
+    orientation: String,
+    width: u32,
+    height: u32,
+    tilewidth: u32,
+    tileheight: u32,
+    #[serde(default)]
+    infinite: bool,
+    #[serde(default)]
+    tilesets: Vec<RawTileset>,
+    #[serde(default)]
+    layers: Vec<RawLayer>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+struct RawTileset {
+    #[serde(default)]
+    firstgid: u32,
+    source: Option<String>,
+    #[serde(default)]
+    name: String,
+    image: Option<String>,
+    #[serde(default)]
+    tilewidth: u32,
+    #[serde(default)]
+    tileheight: u32,
+    #[serde(default)]
+    tilecount: u32,
+    #[serde(default)]
+    columns: u32,
+    #[serde(default)]
+    margin: u32,
+    #[serde(default)]
+    spacing: u32,
+    #[serde(default)]
+    tiles: Vec<RawTile>,
+}
+
+impl RawTileset {
+    fn build(&self, image: impl Fn(&str) -> Option<Handle<Image>>) -> Result<Tileset, LevelError> {
+        let file = match &self.image {
+            Some(file) => file,
+            None => {
+                return Err(LevelError::MissingTileset {
+                    tileset: self.source.clone().unwrap_or_else(|| self.name.clone()),
+                    image: None,
+                })
+            }
+        };
+        let mut tileset = Tileset::grid(
+            tileset_image(image, &self.name, file)?,
+            Vector::new(self.tilewidth as f32, self.tileheight as f32),
+            self.columns,
+            self.tilecount,
+            self.margin as f32,
+            self.spacing as f32,
+        );
+        for tile in self.tiles.iter().filter(|tile| !tile.animation.is_empty()) {
+            let frames = tile
+                .animation
+                .iter()
+                .map(|frame| TileFrame {
+                    tile: frame.tileid,
+                    ticks: ms_to_ticks(frame.duration),
+                })
+                .collect();
+            tileset.animate(tile.id, frames);
+        }
+        Ok(tileset)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct RawTile {
+    id: u32,
+    #[serde(default)]
+    animation: Vec<RawFrame>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct RawFrame {
+    tileid: u32,
+    duration: u32,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type")]
+enum RawLayer {
+    #[serde(rename = "tilelayer")]
+    Tiles {
+        name: String,
+        width: u32,
+        height: u32,
+        #[serde(default = "visible")]
+        visible: bool,
+        data: RawData,
+        encoding: Option<String>,
+        compression: Option<String>,
+        #[serde(default)]
+        properties: Vec<RawProperty>,
+    },
+    #[serde(rename = "objectgroup")]
+    Objects {
+        #[serde(default)]
+        objects: Vec<RawObject>,
+    },
+    #[serde(rename = "group")]
+    Group {
+        #[serde(default = "visible")]
+        visible: bool,
+        #[serde(default)]
+        layers: Vec<RawLayer>,
+    },
+    #[serde(rename = "imagelayer")]
+    Image {},
+}
+
+fn visible() -> bool {
+    true
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum RawData {
+    Gids(Vec<u32>),
+    Encoded(String),
+}
+
+#[derive(Debug, Deserialize)]
+struct RawObject {
+    #[serde(default)]
+    name: String,
+    #[serde(default, rename = "type")]
+    kind: String,
+    #[serde(default)]
+    class: String,
+    x: f32,
+    y: f32,
+    #[serde(default)]
+    width: f32,
+    #[serde(default)]
+    height: f32,
+    gid: Option<u32>,
+    #[serde(default)]
+    properties: Vec<RawProperty>,
+}
+
+impl RawObject {
+    fn build(&self, path: &str) -> Result<LevelObject, LevelError> {
+        let size = Vector::new(self.width, self.height);
+        // The tile objects are placed by their bottom left corner
+        let corner = match self.gid {
+            Some(_) => Vector::new(self.x, self.y - self.height),
+            None => Vector::new(self.x, self.y),
+        };
+        let mut object = LevelObject {
+            name: self.name.clone(),
+            kind: if self.kind.is_empty() {
+                self.class.clone()
+            } else {
+                self.kind.clone()
+            },
+            position: corner + size / 2.,
+            size,
+            properties: Properties::default(),
+        };
+        for property in self.properties.iter() {
+            let value = property.value(path, &object.to_string())?;
+            object.properties.insert(property.name.clone(), value);
+        }
+        Ok(object)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct RawProperty {
+    name: String,
+    #[serde(default = "string_type", rename = "type")]
+    kind: String,
+    value: Value,
+}
+
+fn string_type() -> String {
+    "string".into()
+}
+
+impl RawProperty {
+    // The TMX values are all strings, parsed by the type
+    fn value(&self, path: &str, owner: &str) -> Result<PropertyValue, LevelError> {
+        let bad = |expected| LevelError::BadProperty {
+            object: owner.into(),
+            property: self.name.clone(),
+            expected,
+        };
+        let value = match (self.kind.as_str(), &self.value) {
+            ("bool", Value::Bool(value)) => PropertyValue::Bool(*value),
+            ("bool", Value::String(value)) => {
+                PropertyValue::Bool(value.parse().map_err(|_| bad("a bool"))?)
+            }
+            ("bool", _) => return Err(bad("a bool")),
+            ("int", Value::String(value)) => {
+                PropertyValue::Int(value.parse().map_err(|_| bad("an int"))?)
+            }
+            ("int", value) => PropertyValue::Int(value.as_i64().ok_or_else(|| bad("an int"))?),
+            ("float", Value::String(value)) => {
+                PropertyValue::Float(value.parse().map_err(|_| bad("a float"))?)
+            }
+            ("float", value) => {
+                PropertyValue::Float(value.as_f64().ok_or_else(|| bad("a float"))? as f32)
+            }
+            ("string", Value::String(value))
+            | ("file", Value::String(value))
+            | ("color", Value::String(value)) => PropertyValue::String(value.clone()),
+            ("string", _) | ("file", _) | ("color", _) => return Err(bad("a string")),
+            (other, _) => {
+                return Err(LevelError::Unsupported {
+                    path: path.into(),
+                    feature: format!("the {} property `{}` of {}", other, self.name, owner),
+                })
+            }
+        };
+        Ok(value)
+    }
+}
+
+/// The XML flavour, read into the same raw structures as the JSON.
+mod tmx {
+    use super::*;
+    use std::str::FromStr;
+    use xml::reader::{EventReader, XmlEvent};
+
+    struct Element {
+        name: String,
+        attributes: Vec<(String, String)>,
+        children: Vec<Element>,
+        text: String,
+    }
+
+    impl Element {
+        fn attr(&self, name: &str) -> Option<&str> {
+            self.attributes
+                .iter()
+                .find(|(attribute, _)| attribute == name)
+                .map(|(_, value)| value.as_str())
+        }
+        fn parse<T: FromStr>(&self, path: &str, name: &str) -> Result<Option<T>, LevelError> {
+            match self.attr(name) {
+                Some(value) => value.parse().map(Some).map_err(|_| {
+                    parse_error(
+                        path,
+                        format!("`{}` of <{}> is `{}`", name, self.name, value),
+                    )
+                }),
+                None => Ok(None),
+            }
+        }
+        fn required<T: FromStr>(&self, path: &str, name: &str) -> Result<T, LevelError> {
+            self.parse(path, name)?
+                .ok_or_else(|| parse_error(path, format!("<{}> has no `{}`", self.name, name)))
+        }
+        fn or_default<T: FromStr + Default>(
+            &self,
+            path: &str,
+            name: &str,
+        ) -> Result<T, LevelError> {
+            Ok(self.parse(path, name)?.unwrap_or_default())
+        }
+        fn string(&self, name: &str) -> String {
+            self.attr(name).unwrap_or_default().into()
+        }
+        fn visible(&self) -> bool {
+            self.attr("visible") != Some("0")
+        }
+        fn child(&self, name: &str) -> Option<&Element> {
+            self.children.iter().find(|child| child.name == name)
+        }
+        fn children<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Element> {
+            self.children.iter().filter(move |child| child.name == name)
+        }
+    }
+
+    fn document(path: &str, bytes: &[u8]) -> Result<Element, LevelError> {
+        let mut open: Vec<Element> = Vec::new();
+        for event in EventReader::new(bytes) {
+            match event.map_err(|err| parse_error(path, err))? {
+                XmlEvent::StartElement {
+                    name, attributes, ..
+                } => open.push(Element {
+                    name: name.local_name,
+                    attributes: attributes
+                        .into_iter()
+                        .map(|attribute| (attribute.name.local_name, attribute.value))
+                        .collect(),
+                    children: Vec::new(),
+                    text: String::new(),
+                }),
+                XmlEvent::EndElement { .. } => {
+                    let element = open.pop().expect("The reader checks the nesting");
+                    match open.last_mut() {
+                        Some(parent) => parent.children.push(element),
+                        None => return Ok(element),
+                    }
+                }
+                XmlEvent::Characters(text) | XmlEvent::CData(text) => {
+                    if let Some(element) = open.last_mut() {
+                        element.text.push_str(&text);
+                    }
+                }
+                _ => {}
+            }
+        }
+        Err(parse_error(path, "no root element"))
+    }
+
+    pub(super) fn map(path: &str, bytes: &[u8]) -> Result<RawMap, LevelError> {
+        let root = document(path, bytes)?;
+        if root.name != "map" {
+            return Err(parse_error(
+                path,
+                format!("<{}> instead of <map>", root.name),
+            ));
+        }
+        Ok(RawMap {
+            orientation: root.string("orientation"),
+            width: root.required(path, "width")?,
+            height: root.required(path, "height")?,
+            tilewidth: root.required(path, "tilewidth")?,
+            tileheight: root.required(path, "tileheight")?,
+            infinite: root.attr("infinite") == Some("1"),
+            tilesets: root
+                .children("tileset")
+                .map(|tileset| raw_tileset(path, tileset))
+                .collect::<Result<_, _>>()?,
+            layers: raw_layers(path, &root)?,
+        })
+    }
+
+    pub(super) fn tileset(path: &str, bytes: &[u8]) -> Result<RawTileset, LevelError> {
+        let root = document(path, bytes)?;
+        if root.name != "tileset" {
+            return Err(parse_error(
+                path,
+                format!("<{}> instead of <tileset>", root.name),
+            ));
+        }
+        raw_tileset(path, &root)
+    }
+
+    fn raw_tileset(path: &str, tileset: &Element) -> Result<RawTileset, LevelError> {
+        let tiles = tileset
+            .children("tile")
+            .map(|tile| {
+                let animation = match tile.child("animation") {
+                    Some(animation) => animation
+                        .children("frame")
+                        .map(|frame| {
+                            Ok(RawFrame {
+                                tileid: frame.required(path, "tileid")?,
+                                duration: frame.required(path, "duration")?,
+                            })
+                        })
+                        .collect::<Result<_, LevelError>>()?,
+                    None => Vec::new(),
+                };
+                Ok(RawTile {
+                    id: tile.required(path, "id")?,
+                    animation,
+                })
+            })
+            .collect::<Result<_, LevelError>>()?;
+        Ok(RawTileset {
+            firstgid: tileset.or_default(path, "firstgid")?,
+            source: tileset.attr("source").map(Into::into),
+            name: tileset.string("name"),
+            image: tileset
+                .child("image")
+                .and_then(|image| image.attr("source"))
+                .map(Into::into),
+            tilewidth: tileset.or_default(path, "tilewidth")?,
+            tileheight: tileset.or_default(path, "tileheight")?,
+            tilecount: tileset.or_default(path, "tilecount")?,
+            columns: tileset.or_default(path, "columns")?,
+            margin: tileset.or_default(path, "margin")?,
+            spacing: tileset.or_default(path, "spacing")?,
+            tiles,
+        })
+    }
+
+    fn raw_layers(path: &str, parent: &Element) -> Result<Vec<RawLayer>, LevelError> {
+        let mut layers = Vec::new();
+        for element in parent.children.iter() {
+            let layer = match element.name.as_str() {
+                "layer" => {
+                    let data = element
+                        .child("data")
+                        .ok_or_else(|| parse_error(path, "a layer has no <data>"))?;
+                    let encoding = data.attr("encoding").map(String::from);
+                    // Without encoding the tiles are elements of their own
+                    let gids = match encoding {
+                        Some(_) => RawData::Encoded(data.text.clone()),
+                        None => RawData::Gids(
+                            data.children("tile")
+                                .map(|tile| tile.or_default(path, "gid"))
+                                .collect::<Result<_, _>>()?,
+                        ),
+                    };
+                    RawLayer::Tiles {
+                        name: element.string("name"),
+                        width: element.required(path, "width")?,
+                        height: element.required(path, "height")?,
+                        visible: element.visible(),
+                        data: gids,
+                        encoding,
+                        compression: data.attr("compression").map(Into::into),
+                        properties: properties(element),
+                    }
+                }
+                "objectgroup" => RawLayer::Objects {
+                    objects: element
+                        .children("object")
+                        .map(|object| raw_object(path, object))
+                        .collect::<Result<_, _>>()?,
+                },
+                "group" => RawLayer::Group {
+                    visible: element.visible(),
+                    layers: raw_layers(path, element)?,
+                },
+                "imagelayer" => RawLayer::Image {},
+                _ => continue,
+            };
+            layers.push(layer);
+        }
+        Ok(layers)
+    }
+
+    fn raw_object(path: &str, object: &Element) -> Result<RawObject, LevelError> {
+        Ok(RawObject {
+            name: object.string("name"),
+            kind: object.string("type"),
+            class: object.string("class"),
+            x: object.or_default(path, "x")?,
+            y: object.or_default(path, "y")?,
+            width: object.or_default(path, "width")?,
+            height: object.or_default(path, "height")?,
+            gid: object.parse(path, "gid")?,
+            properties: properties(object),
+        })
+    }
+
+    fn properties(element: &Element) -> Vec<RawProperty> {
+        element
+            .child("properties")
+            .map(|properties| {
+                properties
+                    .children("property")
+                    .map(|property| RawProperty {
+                        name: property.string("name"),
+                        kind: property.attr("type").unwrap_or("string").into(),
+                        // The multi line strings are the text of the element
+                        value: Value::String(
+                            property
+                                .attr("value")
+                                .map(String::from)
+                                .unwrap_or_else(|| property.text.clone()),
+                        ),
+                    })
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::assets::AssetStore;
+
+    const TMX: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<map version="1.2" orientation="orthogonal" width="3" height="2" tilewidth="8" tileheight="8" infinite="0">
+ <tileset firstgid="1" name="tiles" tilewidth="8" tileheight="8" tilecount="4" columns="2">
+  <image source="../tiles.png" width="16" height="16"/>
+  <tile id="1">
+   <animation>
+    <frame tileid="1" duration="100"/>
+    <frame tileid="2" duration="100"/>
+   </animation>
+  </tile>
+ </tileset>
+ <layer name="ground" width="3" height="2">
+  <properties>
+   <property name="collides" type="bool" value="true"/>
+  </properties>
+  <data encoding="csv">
+0,2,0,
+1,2147483649,4
+</data>
+ </layer>
+ <objectgroup name="things">
+  <object id="1" name="hero" type="player" x="4" y="2" width="8" height="12">
+   <properties>
+    <property name="speed" type="float" value="1.5"/>
+    <property name="lives" type="int" value="3"/>
+    <property name="image" value="slime"/>
+   </properties>
+  </object>
+  <object id="2" type="obstacle" gid="3" x="16" y="16" width="8" height="8"/>
+ </objectgroup>
+</map>
+"#;
+
+    fn loaded(names: &[&str]) -> impl Fn(&str) -> Option<Handle<Image>> {
+        let mut store = AssetStore::<Image>::default();
+        let handles: Vec<_> = names
+            .iter()
+            .map(|name| (name.to_string(), store.reserve(name)))
+            .collect();
+        move |name| {
+            handles
+                .iter()
+                .find(|(loaded, _)| loaded == name)
+                .map(|(_, handle)| *handle)
+        }
+    }
+
+    fn ground(tilemap: &Tilemap) -> Vec<Option<Tile>> {
+        (0..2)
+            .flat_map(|y| (0..3).map(move |x| (x, y)))
+            .map(|(x, y)| tilemap.tile(0, x, y))
+            .collect()
+    }
+
+    #[test]
+    fn tmx_layers_and_objects() {
+        let map = TiledMap::parse("levels/test.tmx", TMX.as_bytes()).unwrap();
+        assert!(map.external_tilesets().is_empty());
+        let level = map.into_level_with(loaded(&["tiles"])).unwrap();
+        assert_eq!(level.bounds, Rectangle::new((0., 0.), (24., 16.)));
+
+        let (position, tilemap) = &level.tilemaps[0];
+        assert_eq!(*position, Vector::ZERO);
+        assert_eq!(tilemap.collision, Some(TileCollision::solid(0)));
+        let flipped = Tile {
+            flip_x: true,
+            ..Tile::new(0)
+        };
+        assert_eq!(
+            ground(tilemap),
+            vec![
+                None,
+                Some(Tile::new(1)),
+                None,
+                Some(Tile::new(0)),
+                Some(flipped),
+                Some(Tile::new(3))
+            ]
+        );
+        let tileset = tilemap.tileset(0).unwrap();
+        assert!(tileset.is_animated(1));
+        assert_eq!(tileset.region(3), Some(Rectangle::new((8., 8.), (8., 8.))));
+
+        let hero = &level.objects[0];
+        assert_eq!(
+            (hero.kind.as_str(), hero.position),
+            ("player", Vector::new(8., 8.))
+        );
+        assert_eq!(hero.float("speed").unwrap(), Some(1.5));
+        assert_eq!(hero.float("lives").unwrap(), Some(3.));
+        assert_eq!(hero.string("image").unwrap(), Some("slime"));
+        assert!(hero.bool("lives").is_err());
+        // Tile objects hang from their bottom left corner
+        assert_eq!(level.objects[1].position, Vector::new(20., 12.));
+    }
+
+    #[test]
+    fn json_with_external_tileset() {
+        // The ground layer of the TMX map, zlib compressed
+        let json = r#"{
+            "orientation": "orthogonal", "width": 3, "height": 2,
+            "tilewidth": 8, "tileheight": 8, "infinite": false,
+            "tilesets": [{ "firstgid": 1, "source": "tiles.tsx" }],
+            "layers": [{
+                "type": "group", "name": "background", "visible": false,
+                "layers": [{
+                    "type": "tilelayer", "name": "ground", "width": 3, "height": 2,
+                    "encoding": "base64", "compression": "zlib",
+                    "data": "eJxjYGBgYGKAAEYIbmAB0gAC5ACJ"
+                }]
+            }, {
+                "type": "objectgroup", "name": "things",
+                "objects": [{
+                    "id": 1, "class": "zone", "x": 0, "y": 0, "width": 16, "height": 8,
+                    "properties": [{ "name": "solid", "type": "bool", "value": false }]
+                }]
+            }]
+        }"#;
+        let tsx = r#"<?xml version="1.0" encoding="UTF-8"?>
+<tileset name="tiles" tilewidth="8" tileheight="8" tilecount="4" columns="2">
+ <image source="tiles.png" width="16" height="16"/>
+</tileset>
+"#;
+        let mut map = TiledMap::parse("levels/test.json", json.as_bytes()).unwrap();
+        assert_eq!(map.external_tilesets(), vec!["tiles.tsx".to_string()]);
+        map.resolve_tileset("tiles.tsx", "levels/tiles.tsx", tsx.as_bytes())
+            .unwrap();
+        let level = map.into_level_with(loaded(&["tiles"])).unwrap();
+
+        let (_, tilemap) = &level.tilemaps[0];
+        assert!(!tilemap.layers()[0].visible);
+        assert_eq!(tilemap.collision, None);
+        assert_eq!(ground(tilemap)[4].map(|tile| tile.flip_x), Some(true));
+        assert_eq!(ground(tilemap)[5], Some(Tile::new(3)));
+
+        let zone = &level.objects[0];
+        assert_eq!(
+            (zone.kind.as_str(), zone.position),
+            ("zone", Vector::new(8., 4.))
+        );
+        assert_eq!(zone.bool("solid").unwrap(), Some(false));
+    }
+
+    #[test]
+    fn clear_errors() {
+        let map = TiledMap::parse("test.tmx", TMX.as_bytes()).unwrap();
+        match map.into_level_with(loaded(&["image"])) {
+            Err(LevelError::MissingTileset { tileset, image }) => {
+                assert_eq!(
+                    (tileset.as_str(), image.as_deref()),
+                    ("tiles", Some("tiles"))
+                )
+            }
+            other => panic!("unexpected {:?}", other.map(|level| level.objects)),
+        }
+
+        let bad = TMX.replace(r#"value="3""#, r#"value="three""#);
+        let map = TiledMap::parse("test.tmx", bad.as_bytes()).unwrap();
+        match map.into_level_with(loaded(&["tiles"])) {
+            Err(LevelError::BadProperty {
+                object, property, ..
+            }) => assert_eq!((object.as_str(), property.as_str()), ("`hero`", "lives")),
+            other => panic!("unexpected {:?}", other.map(|level| level.objects)),
+        }
+
+        let unresolved = TMX.replace(r#"name="tiles""#, r#"source="tiles.tsx""#);
+        let unresolved = unresolved.replace(
+            r#"<image source="../tiles.png" width="16" height="16"/>"#,
+            "",
+        );
+        let map = TiledMap::parse("test.tmx", unresolved.as_bytes()).unwrap();
+        match map.into_level_with(loaded(&["tiles"])) {
+            Err(LevelError::MissingTileset { image: None, .. }) => {}
+            other => panic!("unexpected {:?}", other.map(|level| level.objects)),
+        }
+
+        let isometric = TMX.replace("orthogonal", "isometric");
+        let map = TiledMap::parse("test.tmx", isometric.as_bytes()).unwrap();
+        assert!(matches!(
+            map.into_level_with(loaded(&["tiles"])),
+            Err(LevelError::Unsupported { .. })
+        ));
+    }
+
+    #[test]
+    fn unknown_types_spawn_nothing() {
+        use super::super::EntityRegistry;
+        use legion::prelude::*;
+
+        let map = TiledMap::parse("test.tmx", TMX.as_bytes()).unwrap();
+        let level = map.into_level_with(loaded(&["tiles"])).unwrap();
+        let mut world = Universe::new().create_world();
+        let registry = EntityRegistry::default().with("player", |ctx, object| {
+            Ok(ctx.world.insert((), vec![(object.position,)])[0])
+        });
+        match level.spawn(
+            &mut world,
+            &Resources::default(),
+            &Images::default(),
+            &registry,
+        ) {
+            Err(LevelError::UnknownType { kind, .. }) => assert_eq!(kind, "obstacle"),
+            other => panic!("unexpected {:?}", other),
+        }
+        assert_eq!(<Read<Vector>>::query().iter(&world).count(), 0);
+    }
+}
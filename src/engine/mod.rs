@@ -1,6 +1,11 @@
+pub mod assets;
+pub mod camera;
 pub mod components;
 pub mod input;
+pub mod level;
 mod resize_strategy;
+pub mod tilemap;
 
+pub use self::camera::Camera;
 pub use self::input::ButtonsState;
 pub use self::resize_strategy::ResizeStrategy;
@@ -19,6 +19,17 @@ pub enum ResizeStrategy {
     /// 16, 9, for example, will allow any 16:9 viewport; 160, 90 will only allow 16:9 viewports
     /// that are divisible by 10
     IntegerScale { width: u32, height: u32 },
+    /// Like `IntegerScale`, but never goes below 1x, so pixel art never gets scaled by `1 / n`
+    ///
+    /// If the window is smaller than the content, content will be cut off
+    IntegerOnly { width: u32, height: u32 },
+    /// Like `Fit`, but the viewport is snapped to a whole multiple of the aspect ratio
+    ///
+    /// The viewport then has integer size and position with the exact aspect ratio
+    FitSnap { width: u32, height: u32 },
+    /// Use the integer scale closest to the one that would fit, cutting off the content that
+    /// doesn't fit in the window
+    IntegerCrop { width: u32, height: u32 },
 }
 
 impl ResizeStrategy {
@@ -43,8 +54,37 @@ impl ResizeStrategy {
                     * int_scale(new_size.x / width as f32)
                         .min(int_scale(new_size.y / height as f32))
             }
+            ResizeStrategy::IntegerOnly { width, height } => {
+                Vector::new(width, height) * fit_scale(width, height, new_size).floor().max(1.)
+            }
+            ResizeStrategy::FitSnap { width, height } => {
+                let divisor = gcd(width, height);
+                let (width, height) = (width / divisor, height / divisor);
+                Vector::new(width, height) * fit_scale(width, height, new_size).floor().max(1.)
+            }
+            ResizeStrategy::IntegerCrop { width, height } => {
+                Vector::new(width, height) * fit_scale(width, height, new_size).round().max(1.)
+            }
         };
-        Rectangle::new((new_size - content_area) / 2, content_area)
+        let offset = (new_size - content_area) / 2;
+        if self.is_pixel_perfect() {
+            // Half a pixel offset would blur the content all the same
+            Rectangle::new(
+                Vector::new(offset.x.floor(), offset.y.floor()),
+                content_area,
+            )
+        } else {
+            Rectangle::new(offset, content_area)
+        }
+    }
+
+    fn is_pixel_perfect(self) -> bool {
+        match self {
+            ResizeStrategy::IntegerOnly { .. }
+            | ResizeStrategy::FitSnap { .. }
+            | ResizeStrategy::IntegerCrop { .. } => true,
+            _ => false,
+        }
     }
 }
 
@@ -56,3 +96,227 @@ fn int_scale(value: f32) -> f32 {
         value.recip().floor().recip()
     }
 }
+
+// Largest fractional scale at which the content still fits in the window
+fn fit_scale(width: u32, height: u32, window: Vector) -> f32 {
+    (window.x / width as f32).min(window.y / height as f32)
+}
+
+fn gcd(a: u32, b: u32) -> u32 {
+    if b == 0 {
+        a.max(1)
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONTENT: (u32, u32) = (320, 180);
+
+    fn window_sizes() -> impl Iterator<Item = Vector> {
+        (1..=2600)
+            .step_by(7)
+            .flat_map(|w| (1..=1500).step_by(11).map(move |h| (w, h)))
+            .map(|(w, h)| Vector::new(w as f32, h as f32))
+    }
+
+    fn content() -> Vector {
+        Vector::new(CONTENT.0 as f32, CONTENT.1 as f32)
+    }
+
+    fn assert_centered(viewport: &Rectangle, window: Vector) {
+        let left = viewport.x();
+        let right = window.x - viewport.x() - viewport.width();
+        let top = viewport.y();
+        let bottom = window.y - viewport.y() - viewport.height();
+        assert!(
+            (left - right).abs() <= 1. && (top - bottom).abs() <= 1.,
+            "{:?} not centered in {:?}",
+            viewport,
+            window
+        );
+    }
+
+    fn assert_inside(viewport: &Rectangle, window: Vector) {
+        assert!(
+            viewport.x() >= 0.
+                && viewport.y() >= 0.
+                && viewport.x() + viewport.width() <= window.x
+                && viewport.y() + viewport.height() <= window.y,
+            "{:?} sticks out of {:?}",
+            viewport,
+            window
+        );
+    }
+
+    fn assert_aspect_ratio(viewport: &Rectangle, window: Vector) {
+        let expected = CONTENT.0 as f32 / CONTENT.1 as f32;
+        let ratio = viewport.width() / viewport.height();
+        assert!(
+            (ratio - expected).abs() < 1e-3,
+            "{:?} in {:?} has ratio {}",
+            viewport,
+            window,
+            ratio
+        );
+    }
+
+    fn assert_integer_scale(viewport: &Rectangle, window: Vector) -> f32 {
+        let scale = viewport.width() / CONTENT.0 as f32;
+        assert!(
+            scale >= 1. && scale.fract() == 0. && viewport.height() / CONTENT.1 as f32 == scale,
+            "{:?} in {:?} is not an integer scale",
+            viewport,
+            window
+        );
+        scale
+    }
+
+    fn assert_whole_pixels(viewport: &Rectangle, window: Vector) {
+        assert!(
+            viewport.x().fract() == 0.
+                && viewport.y().fract() == 0.
+                && viewport.width().fract() == 0.
+                && viewport.height().fract() == 0.,
+            "{:?} in {:?} is not pixel aligned",
+            viewport,
+            window
+        );
+    }
+
+    #[test]
+    fn fit_is_centered_inside_and_keeps_aspect_ratio() {
+        for window in window_sizes() {
+            let viewport = ResizeStrategy::Fit.resize(content(), window);
+            assert_centered(&viewport, window);
+            assert_aspect_ratio(&viewport, window);
+            // Float rounding can make the fitting side a tiny bit bigger
+            let shrunk = Rectangle::new(viewport.pos, viewport.size - Vector::new(1e-3, 1e-3));
+            assert_inside(&shrunk, window);
+        }
+    }
+
+    #[test]
+    fn integer_scale_falls_back_to_fractions() {
+        let strategy = ResizeStrategy::IntegerScale {
+            width: CONTENT.0,
+            height: CONTENT.1,
+        };
+        let viewport = strategy.resize(content(), Vector::new(150., 80.));
+        assert_eq!(viewport.width(), 160.);
+        assert_eq!(viewport.height(), 90.);
+    }
+
+    #[test]
+    fn integer_only_never_goes_below_one() {
+        let strategy = ResizeStrategy::IntegerOnly {
+            width: CONTENT.0,
+            height: CONTENT.1,
+        };
+        for window in window_sizes() {
+            let viewport = strategy.resize(content(), window);
+            let scale = assert_integer_scale(&viewport, window);
+            assert_centered(&viewport, window);
+            assert_whole_pixels(&viewport, window);
+            if window.x >= content().x && window.y >= content().y {
+                assert_inside(&viewport, window);
+                // The next scale would not fit anymore
+                assert!(
+                    content().x * (scale + 1.) > window.x || content().y * (scale + 1.) > window.y
+                );
+            } else {
+                assert_eq!(scale, 1.);
+            }
+        }
+    }
+
+    #[test]
+    fn fit_snap_is_pixel_aligned_with_exact_ratio() {
+        let strategy = ResizeStrategy::FitSnap {
+            width: CONTENT.0,
+            height: CONTENT.1,
+        };
+        // 320x180 reduces to 16:9
+        for window in window_sizes().filter(|window| window.x >= 16. && window.y >= 9.) {
+            let viewport = strategy.resize(content(), window);
+            assert_centered(&viewport, window);
+            assert_inside(&viewport, window);
+            assert_aspect_ratio(&viewport, window);
+            assert_whole_pixels(&viewport, window);
+            let fit = ResizeStrategy::Fit.resize(content(), window);
+            // Loses less than a single step of the ratio compared to the plain fit
+            assert!(fit.width() - viewport.width() < 16.);
+            assert!(fit.height() - viewport.height() < 9.);
+        }
+    }
+
+    #[test]
+    fn integer_crop_picks_nearest_scale() {
+        let strategy = ResizeStrategy::IntegerCrop {
+            width: CONTENT.0,
+            height: CONTENT.1,
+        };
+        for window in window_sizes() {
+            let viewport = strategy.resize(content(), window);
+            let scale = assert_integer_scale(&viewport, window);
+            assert_centered(&viewport, window);
+            assert_whole_pixels(&viewport, window);
+            let fit = fit_scale(CONTENT.0, CONTENT.1, window);
+            assert!(
+                (scale - fit).abs() <= 0.5 || (fit < 1. && scale == 1.),
+                "scale {} is not the nearest to {}",
+                scale,
+                fit
+            );
+        }
+    }
+
+    #[test]
+    fn integer_crop_cuts_off_at_most_half_a_scale() {
+        let strategy = ResizeStrategy::IntegerCrop {
+            width: CONTENT.0,
+            height: CONTENT.1,
+        };
+        let viewport = strategy.resize(content(), Vector::new(1200., 680.));
+        assert_eq!(viewport.width(), 1280.);
+        assert_eq!(viewport.height(), 720.);
+        assert_eq!(viewport.x(), -40.);
+        assert_eq!(viewport.y(), -20.);
+    }
+
+    #[test]
+    fn exact_multiples_fill_the_window() {
+        for scale in 1..=8 {
+            let window = content() * scale as f32;
+            for strategy in &[
+                ResizeStrategy::IntegerScale {
+                    width: CONTENT.0,
+                    height: CONTENT.1,
+                },
+                ResizeStrategy::IntegerOnly {
+                    width: CONTENT.0,
+                    height: CONTENT.1,
+                },
+                ResizeStrategy::FitSnap {
+                    width: CONTENT.0,
+                    height: CONTENT.1,
+                },
+                ResizeStrategy::IntegerCrop {
+                    width: CONTENT.0,
+                    height: CONTENT.1,
+                },
+            ] {
+                let viewport = strategy.resize(content(), window);
+                assert_eq!(
+                    viewport,
+                    Rectangle::new(Vector::ZERO, window),
+                    "{:?}",
+                    strategy
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,293 @@
+/*!
+Grids of tiles for the level geometry.
+
+A map has any number of layers of the same size, each takes its tiles from one tileset. The map
+is split into square chunks. Editing a tile bumps the revision of its chunk, so whatever is built
+from the tiles only rebuilds the chunks that changed.
+*/
+use super::assets::Handle;
+use super::components::Position;
+use crate::gfx::tilemap::TileChunks;
+use crate::gfx::Atlas;
+use crate::phx::{TileColliders, TileCollision};
+use fxhash::FxHashMap;
+use legion::prelude::*;
+use quicksilver::geom::{Rectangle, Vector};
+use quicksilver::graphics::Image;
+
+/// Width and height of a chunk, in tiles.
+pub const CHUNK_SIZE: u32 = 16;
+
+/// Cell of a layer, the index of the tile in the tileset.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Tile {
+    pub id: u32,
+    pub flip_x: bool,
+    pub flip_y: bool,
+}
+
+impl Tile {
+    pub fn new(id: u32) -> Self {
+        Self {
+            id,
+            flip_x: false,
+            flip_y: false,
+        }
+    }
+}
+
+/// Step of an animated tile.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TileFrame {
+    pub tile: u32,
+    pub ticks: u32,
+}
+
+/// The tiles of an image, addressed by their index.
+#[derive(Debug, Clone)]
+pub struct Tileset {
+    pub image: Handle<Image>,
+    regions: Vec<Rectangle>,
+    animations: FxHashMap<u32, Vec<TileFrame>>,
+}
+
+impl Tileset {
+    /// `count` tiles in rows of `columns`, with `margin` around the grid and `spacing` between
+    /// the tiles.
+    pub fn grid(
+        image: Handle<Image>,
+        tile_size: Vector,
+        columns: u32,
+        count: u32,
+        margin: f32,
+        spacing: f32,
+    ) -> Self {
+        let columns = columns.max(1);
+        let regions = (0..count)
+            .map(|id| {
+                let (column, row) = ((id % columns) as f32, (id / columns) as f32);
+                let pos = Vector::new(
+                    margin + column * (tile_size.x + spacing),
+                    margin + row * (tile_size.y + spacing),
+                );
+                Rectangle::new(pos, tile_size)
+            })
+            .collect();
+        Self {
+            image,
+            regions,
+            animations: FxHashMap::default(),
+        }
+    }
+    /// The frames of the atlas as the tiles, in the given order. `None` if the atlas misses any.
+    pub fn from_atlas(atlas: &Atlas, frames: &[&str]) -> Option<Self> {
+        let regions = frames
+            .iter()
+            .map(|frame| atlas.frame(frame).map(|frame| frame.region))
+            .collect::<Option<_>>()?;
+        Some(Self {
+            image: atlas.image,
+            regions,
+            animations: FxHashMap::default(),
+        })
+    }
+
+    pub fn tile_count(&self) -> usize {
+        self.regions.len()
+    }
+    /// Part of the image the tile is drawn from.
+    pub fn region(&self, id: u32) -> Option<Rectangle> {
+        self.regions.get(id as usize).copied()
+    }
+
+    /// Cycle the tile through the frames, the layers keep referring to `id`.
+    pub fn animate(&mut self, id: u32, frames: Vec<TileFrame>) {
+        if frames.iter().all(|frame| frame.ticks == 0) {
+            self.animations.remove(&id);
+        } else {
+            self.animations.insert(id, frames);
+        }
+    }
+    pub fn with_animation(mut self, id: u32, frames: Vec<TileFrame>) -> Self {
+        self.animate(id, frames);
+        self
+    }
+    pub fn is_animated(&self, id: u32) -> bool {
+        self.animations.contains_key(&id)
+    }
+    /// Tile shown in place of `id` at the tick of the map clock.
+    pub fn animated(&self, id: u32, tick: u32) -> u32 {
+        let frames = match self.animations.get(&id) {
+            Some(frames) => frames,
+            None => return id,
+        };
+        let total: u32 = frames.iter().map(|frame| frame.ticks).sum();
+        let mut tick = tick % total;
+        for frame in frames {
+            if tick < frame.ticks {
+                return frame.tile;
+            }
+            tick -= frame.ticks;
+        }
+        id
+    }
+}
+
+pub struct TileLayer {
+    pub name: String,
+    /// Index of the tileset in the map
+    pub tileset: usize,
+    pub visible: bool,
+    tiles: Vec<Option<Tile>>,
+}
+
+/// Placed by its top left corner at the `Position` of the entity.
+pub struct Tilemap {
+    width: u32,
+    height: u32,
+    pub tile_size: Vector,
+    /// The layer with the solid tiles, `None` when the map doesn't collide
+    pub collision: Option<TileCollision>,
+    tilesets: Vec<Tileset>,
+    layers: Vec<TileLayer>,
+    // Per layer, the chunks in rows
+    revisions: Vec<Vec<u32>>,
+}
+
+impl Tilemap {
+    /// Empty map of `width` by `height` tiles.
+    pub fn new(width: u32, height: u32, tile_size: Vector) -> Self {
+        Self {
+            width,
+            height,
+            tile_size,
+            collision: None,
+            tilesets: Vec::new(),
+            layers: Vec::new(),
+            revisions: Vec::new(),
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+    /// In pixels.
+    pub fn size(&self) -> Vector {
+        Vector::new(
+            self.width as f32 * self.tile_size.x,
+            self.height as f32 * self.tile_size.y,
+        )
+    }
+
+    pub fn add_tileset(&mut self, tileset: Tileset) -> usize {
+        self.tilesets.push(tileset);
+        self.tilesets.len() - 1
+    }
+    pub fn tileset(&self, index: usize) -> Option<&Tileset> {
+        self.tilesets.get(index)
+    }
+    pub fn tilesets(&self) -> &[Tileset] {
+        &self.tilesets
+    }
+
+    /// Empty layer on top of the others.
+    pub fn add_layer(&mut self, name: &str, tileset: usize) -> usize {
+        self.layers.push(TileLayer {
+            name: name.into(),
+            tileset,
+            visible: true,
+            tiles: vec![None; self.width as usize * self.height as usize],
+        });
+        let (columns, rows) = self.chunks();
+        self.revisions
+            .push(vec![0; columns as usize * rows as usize]);
+        self.layers.len() - 1
+    }
+    pub fn layers(&self) -> &[TileLayer] {
+        &self.layers
+    }
+    pub fn layer_mut(&mut self, layer: usize) -> Option<&mut TileLayer> {
+        self.layers.get_mut(layer)
+    }
+    /// Index of the first layer with the name.
+    pub fn find_layer(&self, name: &str) -> Option<usize> {
+        self.layers.iter().position(|layer| layer.name == name)
+    }
+
+    /// `None` for the empty cells and outside of the map.
+    pub fn tile(&self, layer: usize, x: u32, y: u32) -> Option<Tile> {
+        let index = self.index(x, y)?;
+        self.layers.get(layer)?.tiles[index]
+    }
+    /// Replace the cell, returns whether anything changed.
+    pub fn set_tile(&mut self, layer: usize, x: u32, y: u32, tile: Option<Tile>) -> bool {
+        let index = match self.index(x, y) {
+            Some(index) => index,
+            None => return false,
+        };
+        let cell = match self.layers.get_mut(layer) {
+            Some(layer) => &mut layer.tiles[index],
+            None => return false,
+        };
+        if *cell == tile {
+            return false;
+        }
+        *cell = tile;
+        let chunk = self.chunk_index((x / CHUNK_SIZE, y / CHUNK_SIZE));
+        let revision = &mut self.revisions[layer][chunk];
+        *revision = revision.wrapping_add(1);
+        true
+    }
+    /// Cell under the point, relative to the top left corner of the map.
+    pub fn cell_at(&self, point: Vector) -> Option<(u32, u32)> {
+        let (x, y) = (point.x / self.tile_size.x, point.y / self.tile_size.y);
+        if x < 0. || y < 0. || x >= self.width as f32 || y >= self.height as f32 {
+            return None;
+        }
+        Some((x as u32, y as u32))
+    }
+
+    /// Number of chunk columns and rows.
+    pub fn chunks(&self) -> (u32, u32) {
+        let count = |tiles: u32| (tiles + CHUNK_SIZE - 1) / CHUNK_SIZE;
+        (count(self.width), count(self.height))
+    }
+    /// Changes every time a tile of the chunk is edited.
+    pub fn chunk_revision(&self, layer: usize, chunk: (u32, u32)) -> u32 {
+        self.revisions[layer][self.chunk_index(chunk)]
+    }
+    /// The cells of the chunk, clipped to the map, in rows.
+    pub fn chunk_cells(&self, chunk: (u32, u32)) -> impl Iterator<Item = (u32, u32)> {
+        let (x0, y0) = (chunk.0 * CHUNK_SIZE, chunk.1 * CHUNK_SIZE);
+        let x1 = (x0 + CHUNK_SIZE).min(self.width);
+        let y1 = (y0 + CHUNK_SIZE).min(self.height);
+        (y0..y1).flat_map(move |y| (x0..x1).map(move |x| (x, y)))
+    }
+
+    fn index(&self, x: u32, y: u32) -> Option<usize> {
+        if x < self.width && y < self.height {
+            Some(y as usize * self.width as usize + x as usize)
+        } else {
+            None
+        }
+    }
+    fn chunk_index(&self, chunk: (u32, u32)) -> usize {
+        (chunk.1 * self.chunks().0 + chunk.0) as usize
+    }
+}
+
+/// Put the map into the world with its top left corner at `position`.
+pub fn spawn(world: &mut World, position: Vector, tilemap: Tilemap) -> Entity {
+    world.insert(
+        (),
+        vec![(
+            Position { src: position },
+            tilemap,
+            TileChunks::default(),
+            TileColliders::default(),
+        )],
+    )[0]
+}
@@ -1,35 +1,43 @@
-use crate::game::Game;
+use crate::engine::input::{Cursor, GamepadState};
+use crate::engine::ButtonsState;
+use crate::game::{Button, Game};
 use quicksilver::geom::Vector;
-use quicksilver::graphics::Graphics;
 use quicksilver::lifecycle::{Event, EventCache, EventStream, Window};
 
-use crate::DIMENSIONS;
+pub async fn handle_events(window: &Window, events: &mut EventStream, game_data: &mut Game) {
+    let mut resized = false;
+    {
+        let mut input_cache = game_data
+            .resources
+            .get_mut::<EventCache>()
+            .expect("No button_state!");
+        let mut gamepad = game_data
+            .resources
+            .get_mut::<GamepadState>()
+            .expect("No gamepad state!");
+        let mut cursor = game_data.resources.get_mut::<Cursor>().expect("No cursor!");
+        let mut buttons_state = game_data
+            .resources
+            .get_mut::<ButtonsState<Button>>()
+            .expect("No button_state!");
 
-pub async fn handle_events(
-    window: &Window,
-    gfx: &Graphics,
-    events: &mut EventStream,
-    game_data: &mut Game,
-) {
-    let mut input_cache = game_data
-        .resources
-        .get_mut::<EventCache>()
-        .expect("No button_state!");
-
-    while let Some(event) = events.next_event().await {
-        input_cache.process_event(&event);
-        match event {
-            Event::Resized(resized) => {
-                let win_size = Vector::from(resized.logical_size()) * window.scale_factor();
-                let new_viewport = game_data.resize_strategy.resize(DIMENSIONS, win_size);
-                gfx.set_viewport(
-                    new_viewport.x() as u32,
-                    new_viewport.y() as u32,
-                    new_viewport.width() as u32,
-                    new_viewport.height() as u32,
-                );
+        while let Some(event) = events.next_event().await {
+            input_cache.process_event(&event);
+            gamepad.process_event(&event);
+            cursor.process_event(&event, window.scale_factor());
+            buttons_state.capture_event(&event);
+            match event {
+                Event::Resized(resized_event) => {
+                    game_data.window_size =
+                        Vector::from(resized_event.logical_size()) * window.scale_factor();
+                    resized = true;
+                }
+                _ => {}
             }
-            _ => {}
         }
     }
+    // Refitting updates the cursor, so the resources above have to be released first
+    if resized {
+        game_data.refit();
+    }
 }
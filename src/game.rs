@@ -1,33 +1,107 @@
-use crate::engine::ResizeStrategy;
+use crate::engine::{Camera, ResizeStrategy};
 use legion::prelude::*;
-use quicksilver::geom::Vector;
+use quicksilver::geom::{Rectangle, Vector};
 
-//320 x 180 or 480 x 270
+/// Logical resolution the game starts with
 pub const DIMENSIONS: Vector = Vector { x: 320., y: 180. };
+/// The "wide view" option, toggled with `Button::WideView`
+pub const WIDE_DIMENSIONS: Vector = Vector { x: 480., y: 270. };
 pub const UPDATE_RATE: f32 = 60.;
+/// Ticks between the two presses of a double tap
+const DOUBLE_TAP_WINDOW: usize = 15;
+/// Ticks a button has to be held down for a long press
+const LONG_PRESS: usize = 30;
+/// Ticks of input kept per action, the longest query looks one tick past a long press
+const INPUT_HISTORY_LEN: usize = LONG_PRESS + 1;
+/// Screen shake of the player landing, see `Camera::add_trauma`
+const LANDING_TRAUMA: f32 = 0.3;
+/// Input context of the gameplay systems, the menus go on top of it
+pub const GAMEPLAY: &str = "gameplay";
+/// Input context of the pause menu, hides the movement and Jump from the gameplay
+pub const PAUSE: &str = "pause";
+#[cfg(not(target_arch = "wasm32"))]
+pub const BINDINGS_PATH: &str = "bindings.ron";
 
 // test button system
-use crate::engine::input::Button;
+use crate::engine::input::{
+    binding_name, AxisDirection, Binding, CaptureMode, CaptureOutcome, Cursor, GamepadState,
+    InputContext, InputReplay, CANCEL_KEY,
+};
 use crate::engine::ButtonsState;
-use quicksilver::lifecycle::EventCache;
+use enum_map::Enum;
+use quicksilver::lifecycle::{EventCache, GamepadAxis, GamepadButton, Key};
 
 // images
+use crate::gfx::Images;
 use fxhash::FxHashMap;
+// The frame data is thread safe unlike the images, so the systems can use it
+// Keyed by the image of the atlas, same as `Sprite::src`
+use crate::engine::assets::{AssetGroup, Assets, Handle};
+use crate::gfx::Atlas;
 use quicksilver::graphics::Image;
-type ImageStorage = FxHashMap<String, Image>;
+pub type AtlasStorage = FxHashMap<Handle<Image>, Atlas>;
 
 // collisions
 use crate::phx::PhysicsWorld;
 
+/// Treat as if the game had dedicated controller with these buttons.
+#[derive(Debug, Enum, Clone, Copy, PartialEq)]
+pub enum Button {
+    Left,
+    Right,
+    Up,
+    Down,
+    Jump,
+    Pause,
+    WideView,
+}
+
+/// Logical resolution of the game, the size of the visible part of the world in game pixels.
+///
+/// Can be switched at runtime, the renderer and the viewport follow on the next frame.
+pub struct Resolution {
+    size: Vector,
+    changed: bool,
+}
+
+impl Resolution {
+    pub fn new(size: Vector) -> Self {
+        Self {
+            size,
+            changed: false,
+        }
+    }
+    pub fn size(&self) -> Vector {
+        self.size
+    }
+    pub fn set(&mut self, size: Vector) {
+        if size != self.size {
+            self.size = size;
+            self.changed = true;
+        }
+    }
+    /// Whether the resolution changed since the last call.
+    pub fn take_changed(&mut self) -> bool {
+        std::mem::replace(&mut self.changed, false)
+    }
+}
+
 pub struct Game {
     pub universe: Universe,
     pub resources: Resources,
     pub resize_strategy: ResizeStrategy,
+    /// In physical pixels
+    pub window_size: Vector,
+    /// Part of the window the game is upscaled into
+    pub viewport: Rectangle,
+    /// Apply the fractional part of the camera offset when upscaling, instead of snapping to
+    /// whole game pixels
+    pub subpixel_smoothing: bool,
     // world might become state specific
     pub world: World,
     // schedule most definitely will become state specific
     pub schedule: Schedule,
-    pub images: ImageStorage,
+    pub images: Images,
 }
 
 impl Game {
@@ -43,7 +117,7 @@ impl Game {
         let resize_strategy = ResizeStrategy::Stretch;
 
         // Texture is not thread safe can't put as resource for now!
-        let images = ImageStorage::default();
+        let images = Images::default();
 
         Game {
             universe,
@@ -51,61 +125,318 @@ impl Game {
             world,
             schedule,
             resize_strategy,
+            window_size: DIMENSIONS,
+            viewport: Rectangle::new_sized(DIMENSIONS),
+            subpixel_smoothing: true,
             images,
         }
     }
+
+    pub fn resolution(&self) -> Vector {
+        self.resources
+            .get::<Resolution>()
+            .expect("Resolution missing somehow")
+            .size()
+    }
+
+    /// Give up the references the scene took when loading its assets.
+    pub fn release(&mut self, group: &AssetGroup) {
+        for handle in group.images.iter() {
+            self.images.release(*handle);
+        }
+        let mut assets = self
+            .resources
+            .get_mut::<Assets>()
+            .expect("Assets missing somehow");
+        for handle in group.levels.iter() {
+            assets.levels.release(*handle);
+        }
+        for handle in group.sounds.iter() {
+            assets.sounds.release(*handle);
+        }
+    }
+
+    /// Drop every asset no scene holds a reference to anymore.
+    pub fn unload_unused(&mut self) {
+        let mut atlases = self
+            .resources
+            .get_mut::<AtlasStorage>()
+            .expect("AtlasStorage missing somehow");
+        for handle in self.images.unload_unused() {
+            atlases.remove(&handle);
+        }
+        let mut assets = self
+            .resources
+            .get_mut::<Assets>()
+            .expect("Assets missing somehow");
+        assets.levels.unload_unused();
+        assets.sounds.unload_unused();
+    }
+
+    /// Recalculate the viewport after the window or the resolution changed.
+    pub fn refit(&mut self) {
+        let resolution = self.resolution();
+        self.viewport = self.resize_strategy.resize(resolution, self.window_size);
+        self.resources
+            .get_mut::<Cursor>()
+            .expect("Cursor missing somehow")
+            .set_viewport(self.viewport, resolution);
+    }
+    /// Put the camera and the input back the way `Game::new` left them, for the looping replay
+    /// that starts over.
+    pub fn reset_for_replay(&mut self) {
+        *self
+            .resources
+            .get_mut::<Camera>()
+            .expect("Camera missing somehow") = Camera::new(DIMENSIONS / 2);
+        let mut buttons_state = self
+            .resources
+            .get_mut::<ButtonsState<Button>>()
+            .expect("ButtonsState missing somehow");
+        while buttons_state.pop_context().is_some() {}
+        buttons_state.push_context(InputContext::new(GAMEPLAY));
+        buttons_state.forget_history();
+    }
 }
 
 fn init_resources() -> Resources {
     let mut resources = Resources::default();
     resources.insert(EventCache::default());
-    resources.insert(ButtonsState::default());
+    resources.insert(GamepadState::default());
+    resources.insert(Resolution::new(DIMENSIONS));
+    resources.insert(Cursor::new(DIMENSIONS));
+    resources.insert(Camera::new(DIMENSIONS / 2));
+    resources.insert(AtlasStorage::default());
+    resources.insert(Assets::default());
+    let mut buttons_state = load_bindings();
+    buttons_state.set_history_len(INPUT_HISTORY_LEN);
+    buttons_state.push_context(InputContext::new(GAMEPLAY));
+    resources.insert(buttons_state);
+    resources.insert(InputReplay::Live);
     resources.insert(PhysicsWorld::new());
     resources
 }
 
+#[cfg(not(target_arch = "wasm32"))]
+fn load_bindings() -> ButtonsState<Button> {
+    use crate::engine::input::ConfigError;
+    match ButtonsState::load(BINDINGS_PATH, &default_bindings()) {
+        Ok(buttons_state) => buttons_state,
+        Err(ConfigError::Io(ref err)) if err.kind() == std::io::ErrorKind::NotFound => {
+            info!("No {} found, writing the default bindings", BINDINGS_PATH);
+            let buttons_state = default_bindings();
+            if let Err(err) = buttons_state.save(BINDINGS_PATH) {
+                warn!("{}", err);
+            }
+            buttons_state
+        }
+        Err(err) => {
+            warn!("{}, falling back to the default bindings", err);
+            default_bindings()
+        }
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+fn load_bindings() -> ButtonsState<Button> {
+    default_bindings()
+}
+
+fn default_bindings() -> ButtonsState<Button> {
+    let mut buttons_state = ButtonsState::default();
+    buttons_state.bind(Button::Up, Key::W);
+    buttons_state.bind(Button::Up, Key::Up);
+    buttons_state.bind(Button::Left, Key::A);
+    buttons_state.bind(Button::Left, Key::Left);
+    buttons_state.bind(Button::Down, Key::S);
+    buttons_state.bind(Button::Down, Key::Down);
+    buttons_state.bind(Button::Right, Key::D);
+    buttons_state.bind(Button::Right, Key::Right);
+    buttons_state.bind(Button::Jump, Key::Space);
+    buttons_state.bind(Button::Pause, Key::Escape);
+    buttons_state.bind(Button::WideView, Key::V);
+
+    buttons_state.bind(Button::Up, GamepadButton::DPadUp);
+    buttons_state.bind(Button::Left, GamepadButton::DPadLeft);
+    buttons_state.bind(Button::Down, GamepadButton::DPadDown);
+    buttons_state.bind(Button::Right, GamepadButton::DPadRight);
+    buttons_state.bind(Button::Jump, GamepadButton::South);
+    buttons_state.bind(Button::Pause, GamepadButton::Start);
+    buttons_state.bind(Button::WideView, GamepadButton::Select);
+    use AxisDirection::{Negative, Positive};
+    buttons_state.bind(Button::Up, Binding::axis(GamepadAxis::LeftStickY, Negative));
+    buttons_state.bind(
+        Button::Left,
+        Binding::axis(GamepadAxis::LeftStickX, Negative),
+    );
+    buttons_state.bind(
+        Button::Down,
+        Binding::axis(GamepadAxis::LeftStickY, Positive),
+    );
+    buttons_state.bind(
+        Button::Right,
+        Binding::axis(GamepadAxis::LeftStickX, Positive),
+    );
+    buttons_state.take_changed();
+    buttons_state
+}
+
 fn init_schedule() -> Schedule {
     use crate::phx::Velocity;
     use crate::Player;
     let test_button_state = SystemBuilder::new("test_button_state")
         .read_resource::<EventCache>()
-        .write_resource::<ButtonsState>()
+        .read_resource::<GamepadState>()
+        .write_resource::<Cursor>()
+        .write_resource::<InputReplay>()
+        .write_resource::<ButtonsState<Button>>()
         .with_query(<(Read<Player>, Write<Velocity>)>::query())
-        .build(move |_, mut world, (event_cache, button_state), query| {
-            button_state.update(&event_cache);
-            // if button_state.is_pressed(Button::Up) {
-            //     debug!("Holding UP!");
-            // }
-            if button_state.pressed(Button::Jump) {
-                debug!("Wow you just pressed the Jump button.");
-            }
-            if button_state.released(Button::Jump) {
-                debug!("Congrats on releasing the Jump button");
-            }
-            const KEYS: &'static [(Button, f32, f32)] = &[
-                (Button::Up, 0., -1.),
-                (Button::Down, 0., 1.),
-                (Button::Left, -1., 0.),
-                (Button::Right, 1., 0.),
-            ];
-            let dir = KEYS.into_iter().fold((0., 0.), |acc, (button, x, y)| {
-                if button_state.is_pressed(*button) {
-                    (acc.0 + x, acc.1 + y)
-                } else {
-                    acc
+        .build(
+            move |_, mut world, (event_cache, gamepad, cursor, replay, button_state), query| {
+                replay.update(
+                    &mut **button_state,
+                    &**event_cache,
+                    &**gamepad,
+                    &mut **cursor,
+                );
+                // Only the clicks in the view press the mouse bindings
+                if cursor.take_clicked_outside() {
+                    debug!("Clicked outside of the game area");
                 }
-            });
-            for (_, mut vel) in query.iter_mut(&mut world) {
-                vel.src.x = dir.0 * 64.;
-                vel.src.y = dir.1 * 64.;
-            }
-        });
+                #[cfg(not(target_arch = "wasm32"))]
+                {
+                    if button_state.take_changed() {
+                        if let Err(err) = button_state.save(BINDINGS_PATH) {
+                            warn!("{}", err);
+                        }
+                    }
+                }
+                let paused = button_state
+                    .top_context()
+                    .map_or(false, |context| context.name == PAUSE);
+                if paused && button_state.view(PAUSE).pressed(Button::Pause) {
+                    button_state.pop_context();
+                    debug!("Unpaused");
+                } else if !paused && button_state.view(GAMEPLAY).pressed(Button::Pause) {
+                    use Button::*;
+                    let pause = InputContext::new(PAUSE).consuming(&[Left, Right, Up, Down, Jump]);
+                    button_state.push_context(pause);
+                    debug!("Paused");
+                } else if paused
+                    && button_state.capturing().is_none()
+                    && button_state.view(PAUSE).pressed(Button::Jump)
+                {
+                    // Another key to jump with, picked from the pause menu
+                    button_state.start_capture(Button::Jump, CaptureMode::Add);
+                    info!("Press a key for Jump, {:?} cancels", CANCEL_KEY);
+                }
+                match button_state.take_capture_outcome() {
+                    Some(CaptureOutcome::Bound {
+                        action,
+                        binding,
+                        clashes,
+                    }) => {
+                        let name = binding_name(&binding).unwrap_or_default();
+                        info!("`{}` bound to {:?}", name, action);
+                        if !clashes.is_empty() {
+                            warn!("`{}` is bound to {:?} as well", name, clashes);
+                        }
+                    }
+                    Some(CaptureOutcome::Cancelled { action }) => {
+                        info!("Binding {:?} cancelled", action)
+                    }
+                    None => {}
+                }
+                // if button_state.is_pressed(Button::Up) {
+                //     debug!("Holding UP!");
+                // }
+                let input = button_state.view(GAMEPLAY);
+                if input.pressed(Button::Jump) {
+                    debug!("Wow you just pressed the Jump button.");
+                }
+                if input.released(Button::Jump) {
+                    debug!("Congrats on releasing the Jump button");
+                }
+                if input.double_tapped(Button::Jump, DOUBLE_TAP_WINDOW) {
+                    debug!("That was a double tap on Jump");
+                }
+                if input.held_for(Button::Jump, LONG_PRESS)
+                    && !input.held_for(Button::Jump, LONG_PRESS + 1)
+                {
+                    debug!("Jump has been held for {} ticks", LONG_PRESS);
+                }
+                if input.released_after_hold(Button::Jump, LONG_PRESS) {
+                    debug!("Jump released after a long press");
+                }
+                const KEYS: &'static [(Button, f32, f32)] = &[
+                    (Button::Up, 0., -1.),
+                    (Button::Down, 0., 1.),
+                    (Button::Left, -1., 0.),
+                    (Button::Right, 1., 0.),
+                ];
+                let dir = KEYS.into_iter().fold((0., 0.), |acc, (button, x, y)| {
+                    let value = input.axis(*button);
+                    (acc.0 + x * value, acc.1 + y * value)
+                });
+                for (_, mut vel) in query.iter_mut(&mut world) {
+                    vel.src.x = dir.0 * 64.;
+                    vel.src.y = dir.1 * 64.;
+                }
+            },
+        );
 
     Schedule::builder()
         .add_system(test_button_state)
+        .add_system(player_jump())
+        .add_system(toggle_wide_view())
+        .add_system(crate::phx::update_tile_colliders())
+        .add_system(crate::phx::remove_orphan_bodies())
         // also runs physics step
         .add_system(crate::phx::physics_pre_sync())
         .add_system(crate::phx::physics_post_sync())
         // here the position is already corrected... OR IS IT?
+        .add_system(crate::engine::camera::camera_follow())
+        .add_system(crate::gfx::animation::animate())
+        .add_system(crate::gfx::tilemap::update_tilemaps())
         .build()
 }
+
+fn player_jump() -> Box<dyn Schedulable> {
+    use crate::phx::{jump, Hitbox, JumpControl, Velocity};
+    SystemBuilder::new("player_jump")
+        .read_resource::<ButtonsState<Button>>()
+        .read_resource::<PhysicsWorld>()
+        .write_resource::<Camera>()
+        .with_query(<(Write<JumpControl>, Read<Hitbox>, Write<Velocity>)>::query())
+        .build(move |_, mut world, (button_state, pworld, camera), query| {
+            let input = button_state.view(GAMEPLAY);
+            for (mut jump_control, hitbox, mut vel) in query.iter_mut(&mut world) {
+                let was_grounded = jump_control.is_grounded();
+                let grounded = jump::is_grounded(&pworld, hitbox.src);
+                let pressed_within = |frames| input.pressed_within(Button::Jump, frames);
+                if jump_control.tick(grounded, pressed_within) {
+                    vel.src.y = -jump_control.speed;
+                }
+                if jump_control.is_grounded() && !was_grounded {
+                    camera.add_trauma(LANDING_TRAUMA);
+                }
+            }
+        })
+}
+
+/// Switch between the default and the wide view, the renderer follows on the next frame.
+fn toggle_wide_view() -> Box<dyn Schedulable> {
+    SystemBuilder::new("toggle_wide_view")
+        .read_resource::<ButtonsState<Button>>()
+        .write_resource::<Resolution>()
+        .build(move |_, _, (button_state, resolution), _| {
+            if button_state.view(GAMEPLAY).pressed(Button::WideView) {
+                let size = if resolution.size() == WIDE_DIMENSIONS {
+                    DIMENSIONS
+                } else {
+                    WIDE_DIMENSIONS
+                };
+                resolution.set(size);
+            }
+        })
+}
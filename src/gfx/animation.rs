@@ -0,0 +1,315 @@
+/*!
+Frame based sprite animation, advanced once per update tick.
+
+The clips only name the atlas frames, so the whole state machine runs without touching the
+graphics; `animate` then points the `Sprite` at the current frame.
+*/
+use super::Sprite;
+use crate::game::AtlasStorage;
+use crate::UPDATE_RATE;
+use fxhash::FxHashMap;
+use legion::prelude::*;
+use std::sync::Arc;
+
+/// Convert the frame duration in milliseconds (as Aseprite stores it) into update ticks.
+pub fn ms_to_ticks(ms: u32) -> u32 {
+    ((ms as f32 * UPDATE_RATE / 1000.).round() as u32).max(1)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Playback {
+    Loop,
+    /// Stop at the last frame
+    Once,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClipFrame {
+    /// Name of the frame in the atlas
+    pub name: String,
+    pub ticks: u32,
+    /// Fired when the frame is entered
+    pub events: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Clip {
+    pub frames: Vec<ClipFrame>,
+    pub playback: Playback,
+}
+
+impl Clip {
+    pub fn new(playback: Playback) -> Self {
+        Self {
+            frames: Vec::new(),
+            playback,
+        }
+    }
+    pub fn frame(mut self, name: &str, ticks: u32) -> Self {
+        self.frames.push(ClipFrame {
+            name: name.into(),
+            ticks: ticks.max(1),
+            events: Vec::new(),
+        });
+        self
+    }
+    /// Fire `event` whenever the frame with the given index is entered.
+    pub fn event(mut self, frame: usize, event: &str) -> Self {
+        self.frames
+            .get_mut(frame)
+            .expect("Animation event on a frame the clip doesn't have")
+            .events
+            .push(event.into());
+        self
+    }
+}
+
+/// Named clips, shared by all the entities animated the same way.
+#[derive(Debug, Clone, Default)]
+pub struct Clips {
+    clips: FxHashMap<String, Clip>,
+}
+
+impl Clips {
+    pub fn insert(&mut self, name: &str, clip: Clip) {
+        self.clips.insert(name.into(), clip);
+    }
+    pub fn with(mut self, name: &str, clip: Clip) -> Self {
+        self.insert(name, clip);
+        self
+    }
+    pub fn get(&self, name: &str) -> Option<&Clip> {
+        self.clips.get(name)
+    }
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut Clip> {
+        self.clips.get_mut(name)
+    }
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &Clip)> {
+        self.clips.iter().map(|(name, clip)| (name.as_str(), clip))
+    }
+}
+
+pub struct Animation {
+    clips: Arc<Clips>,
+    clip: String,
+    frame: usize,
+    // Ticks the current frame has been shown for
+    elapsed: u32,
+    // The first frame wasn't shown yet
+    entering: bool,
+    finished: bool,
+    events: Vec<String>,
+}
+
+impl Animation {
+    pub fn new(clips: Arc<Clips>, clip: &str) -> Self {
+        Self {
+            clips,
+            clip: clip.into(),
+            frame: 0,
+            elapsed: 0,
+            entering: true,
+            finished: false,
+            events: Vec::new(),
+        }
+    }
+
+    /// Switch to another clip, keeps going if it's already playing.
+    ///
+    /// Returns `false` if there's no such clip.
+    pub fn play(&mut self, clip: &str) -> bool {
+        if self.clip == clip {
+            return self.clips.get(clip).is_some();
+        }
+        self.restart(clip)
+    }
+    /// Play the clip from the start, even if it's already playing.
+    pub fn restart(&mut self, clip: &str) -> bool {
+        self.clip = clip.into();
+        self.frame = 0;
+        self.elapsed = 0;
+        self.entering = true;
+        self.finished = false;
+        self.clips.get(clip).is_some()
+    }
+
+    /// Advance by a single update tick, returns whether the shown frame changed.
+    ///
+    /// The events of the entered frames are available through `events` until the next tick.
+    pub fn tick(&mut self) -> bool {
+        self.events.clear();
+        let clip = match self.clips.get(&self.clip) {
+            Some(clip) if !clip.frames.is_empty() => clip,
+            _ => return false,
+        };
+        if self.entering {
+            self.entering = false;
+            self.elapsed = 1;
+            self.events.extend_from_slice(&clip.frames[0].events);
+            return true;
+        }
+        if self.finished {
+            return false;
+        }
+        if self.elapsed < clip.frames[self.frame].ticks {
+            self.elapsed += 1;
+            return false;
+        }
+
+        let next = if self.frame + 1 < clip.frames.len() {
+            self.frame + 1
+        } else {
+            match clip.playback {
+                Playback::Loop => 0,
+                Playback::Once => {
+                    self.finished = true;
+                    return false;
+                }
+            }
+        };
+        self.elapsed = 1;
+        self.events.extend_from_slice(&clip.frames[next].events);
+        let changed = clip.frames[next].name != clip.frames[self.frame].name;
+        self.frame = next;
+        changed
+    }
+
+    pub fn clips(&self) -> &Clips {
+        &self.clips
+    }
+    pub fn clip(&self) -> &str {
+        &self.clip
+    }
+    /// Name of the atlas frame to show, `None` if the clip is missing or empty.
+    pub fn frame(&self) -> Option<&str> {
+        let clip = self.clips.get(&self.clip)?;
+        clip.frames.get(self.frame).map(|frame| frame.name.as_str())
+    }
+    pub fn frame_index(&self) -> usize {
+        self.frame
+    }
+    /// A one-shot clip reached the end of its last frame.
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+    /// Events fired during the last tick.
+    pub fn events(&self) -> &[String] {
+        &self.events
+    }
+}
+
+pub fn animate() -> Box<dyn Schedulable> {
+    SystemBuilder::new("animate")
+        .read_resource::<AtlasStorage>()
+        .with_query(<(Write<Animation>, Write<Sprite>)>::query())
+        .build(move |_, mut world, atlases, query| {
+            for (mut animation, mut sprite) in query.iter_mut(&mut world) {
+                if !animation.tick() {
+                    continue;
+                }
+                let atlas = match atlases.get(&sprite.src) {
+                    Some(atlas) => atlas,
+                    None => continue,
+                };
+                if let Some(frame) = animation.frame() {
+                    if !sprite.set_frame(atlas, frame) {
+                        warn!("Atlas of {:?} has no frame `{}`", sprite.src, frame);
+                    }
+                }
+            }
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn clips() -> Arc<Clips> {
+        Arc::new(
+            Clips::default()
+                .with(
+                    "walk",
+                    Clip::new(Playback::Loop)
+                        .frame("walk 0", 2)
+                        .frame("walk 1", 1)
+                        .frame("walk 2", 3)
+                        .event(2, "footstep"),
+                )
+                .with(
+                    "squash",
+                    Clip::new(Playback::Once)
+                        .frame("squash 0", 1)
+                        .frame("squash 1", 2),
+                ),
+        )
+    }
+
+    // Frame shown after each of the ticks
+    fn run(animation: &mut Animation, ticks: usize) -> Vec<String> {
+        (0..ticks)
+            .map(|_| {
+                animation.tick();
+                animation.frame().unwrap().to_string()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn loop_follows_frame_durations() {
+        let mut animation = Animation::new(clips(), "walk");
+        let shown = run(&mut animation, 12);
+        let expected = [
+            "walk 0", "walk 0", "walk 1", "walk 2", "walk 2", "walk 2", "walk 0", "walk 0",
+            "walk 1", "walk 2", "walk 2", "walk 2",
+        ];
+        assert_eq!(shown, expected);
+        assert!(!animation.is_finished());
+    }
+
+    #[test]
+    fn once_stops_at_the_last_frame() {
+        let mut animation = Animation::new(clips(), "squash");
+        let shown = run(&mut animation, 6);
+        assert_eq!(
+            shown,
+            ["squash 0", "squash 1", "squash 1", "squash 1", "squash 1", "squash 1"]
+        );
+        assert!(animation.is_finished());
+        assert!(animation.restart("squash"));
+        assert!(!animation.is_finished());
+        assert_eq!(animation.frame_index(), 0);
+    }
+
+    #[test]
+    fn events_fire_once_per_entered_frame() {
+        let mut animation = Animation::new(clips(), "walk");
+        let fired: Vec<usize> = (0..12)
+            .filter(|_| {
+                animation.tick();
+                animation.events() == ["footstep"]
+            })
+            .collect();
+        assert_eq!(fired, [3, 9]);
+    }
+
+    #[test]
+    fn play_keeps_the_current_clip_going() {
+        let mut animation = Animation::new(clips(), "walk");
+        run(&mut animation, 3);
+        assert!(animation.play("walk"));
+        assert_eq!(animation.frame(), Some("walk 1"));
+        assert!(animation.play("squash"));
+        assert!(animation.tick());
+        assert_eq!(animation.frame(), Some("squash 0"));
+        assert!(!animation.play("run"));
+        assert!(!animation.tick());
+        assert_eq!(animation.frame(), None);
+    }
+
+    #[test]
+    fn durations_round_to_ticks() {
+        assert_eq!(ms_to_ticks(100), 6);
+        assert_eq!(ms_to_ticks(1), 1);
+        assert_eq!(ms_to_ticks(1000), 60);
+    }
+}
@@ -0,0 +1,470 @@
+/*!
+Texture atlases, sheets with many sprites packed in them.
+
+The frame description is read from the Aseprite JSON export, both the "hash" and the "array"
+layout work. Trimmed frames keep their original placement, and the pivot defaults to the
+center of the untrimmed frame. A slice with the same name as a frame and a pivot set in
+Aseprite moves the pivot of that frame.
+
+Every frame tag becomes an animation clip with the frame durations from the export. The clips
+loop, unless the tag has a repeat count set.
+*/
+use super::animation::{self, Clip, Clips, Playback};
+use crate::engine::assets::Handle;
+use fxhash::FxHashMap;
+use golem::TextureFilter;
+use quicksilver::geom::{Rectangle, Vector};
+use quicksilver::graphics::{Graphics, Image};
+use quicksilver::QuicksilverError;
+use serde::de::{Deserializer, MapAccess, Visitor};
+use serde::Deserialize;
+use std::fmt;
+
+/// Single sprite inside the sheet.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    /// Part of the sheet to draw
+    pub region: Rectangle,
+    /// Top left corner of the region relative to the pivot
+    pub offset: Vector,
+}
+
+pub struct Atlas {
+    /// The sheet the frames are in
+    pub image: Handle<Image>,
+    /// Path of the sheet relative to the description
+    pub sheet: String,
+    frames: FxHashMap<String, Frame>,
+    clips: Clips,
+}
+
+#[derive(Debug)]
+pub enum AtlasError {
+    Load(QuicksilverError),
+    Parse(serde_json::Error),
+    /// Packers can rotate the frames to save space, drawing them that way is not supported
+    Rotated(String),
+    /// The frame tag points past the last frame
+    TagOutOfRange(String),
+}
+
+impl fmt::Display for AtlasError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AtlasError::Load(err) => write!(f, "atlas can't be loaded: {}", err),
+            AtlasError::Parse(err) => write!(f, "atlas description is malformed: {}", err),
+            AtlasError::Rotated(frame) => write!(f, "atlas frame `{}` is rotated", frame),
+            AtlasError::TagOutOfRange(tag) => {
+                write!(f, "atlas tag `{}` refers to missing frames", tag)
+            }
+        }
+    }
+}
+
+impl std::error::Error for AtlasError {}
+
+impl From<QuicksilverError> for AtlasError {
+    fn from(err: QuicksilverError) -> Self {
+        AtlasError::Load(err)
+    }
+}
+
+impl From<serde_json::Error> for AtlasError {
+    fn from(err: serde_json::Error) -> Self {
+        AtlasError::Parse(err)
+    }
+}
+
+impl Atlas {
+    /// Parse the Aseprite export describing the sheet stored under `image`.
+    pub fn from_aseprite(image: Handle<Image>, json: &[u8]) -> Result<Self, AtlasError> {
+        Self::from_file(image, serde_json::from_slice(json)?)
+    }
+
+    /// Load the description at `path` and the sheet it points to, next to it.
+    ///
+    /// The sheet has to be put into the image storage under `image`.
+    pub async fn load(
+        gfx: &Graphics,
+        path: &str,
+        image: Handle<Image>,
+    ) -> Result<(Self, Image), AtlasError> {
+        let json = quicksilver::load_file(path).await?;
+        let atlas = Self::from_aseprite(image, &json)?;
+        let sheet = Image::load(gfx, &sheet_path(path, &atlas.sheet)).await?;
+        sheet.set_magnification(TextureFilter::Nearest)?;
+        Ok((atlas, sheet))
+    }
+
+    fn from_file(image: Handle<Image>, file: AsepriteFile) -> Result<Self, AtlasError> {
+        let pivots: FxHashMap<&str, Vector> = file
+            .meta
+            .slices
+            .iter()
+            .filter_map(|slice| {
+                let key = slice.keys.iter().find(|key| key.pivot.is_some())?;
+                let pivot = key.pivot.as_ref()?;
+                Some((
+                    slice.name.as_str(),
+                    Vector::new(key.bounds.x + pivot.x, key.bounds.y + pivot.y),
+                ))
+            })
+            .collect();
+
+        let entries: Vec<(String, AsepriteFrame)> = match file.frames {
+            AsepriteFrames::Hash(OrderedFrames(frames)) => frames,
+            AsepriteFrames::Array(frames) => frames
+                .into_iter()
+                .map(|frame| (frame.filename, frame.frame))
+                .collect(),
+        };
+        let mut clips = Clips::default();
+        for tag in file.meta.frame_tags.iter() {
+            let tagged = entries
+                .get(tag.from..=tag.to)
+                .ok_or_else(|| AtlasError::TagOutOfRange(tag.name.clone()))?;
+            let mut order: Vec<&(String, AsepriteFrame)> = tagged.iter().collect();
+            match tag.direction.as_str() {
+                "reverse" => order.reverse(),
+                // Without repeating the end frames
+                "pingpong" => order.extend(
+                    tagged
+                        .iter()
+                        .rev()
+                        .skip(1)
+                        .take(tagged.len().saturating_sub(2)),
+                ),
+                _ => {}
+            }
+            let playback = match tag.repeat.as_ref().map(|repeat| repeat.as_str()) {
+                None | Some("0") => Playback::Loop,
+                Some(_) => Playback::Once,
+            };
+            let clip = order
+                .into_iter()
+                .fold(Clip::new(playback), |clip, (name, frame)| {
+                    clip.frame(name, animation::ms_to_ticks(frame.duration))
+                });
+            clips.insert(&tag.name, clip);
+        }
+
+        let mut frames = FxHashMap::default();
+        for (name, frame) in entries {
+            if frame.rotated {
+                return Err(AtlasError::Rotated(name));
+            }
+            let pivot = pivots
+                .get(name.as_str())
+                .copied()
+                .unwrap_or_else(|| Vector::new(frame.source_size.w, frame.source_size.h) / 2.);
+            let trim = &frame.sprite_source_size;
+            let region = &frame.frame;
+            frames.insert(
+                name,
+                Frame {
+                    region: Rectangle::new(
+                        Vector::new(region.x, region.y),
+                        Vector::new(region.w, region.h),
+                    ),
+                    offset: Vector::new(trim.x, trim.y) - pivot,
+                },
+            );
+        }
+        Ok(Self {
+            image,
+            sheet: file.meta.image,
+            frames,
+            clips,
+        })
+    }
+
+    pub fn frame(&self, name: &str) -> Option<&Frame> {
+        self.frames.get(name)
+    }
+
+    /// Clips from the frame tags, copy them to add the events.
+    pub fn clips(&self) -> &Clips {
+        &self.clips
+    }
+
+    pub fn frames(&self) -> impl Iterator<Item = (&str, &Frame)> {
+        self.frames
+            .iter()
+            .map(|(name, frame)| (name.as_str(), frame))
+    }
+}
+
+/// Path of the sheet next to the description at `path`.
+pub fn sheet_path(path: &str, sheet: &str) -> String {
+    match path.rfind('/') {
+        Some(idx) => format!("{}/{}", &path[..idx], sheet),
+        None => sheet.into(),
+    }
+}
+
+#[derive(Deserialize)]
+struct AsepriteFile {
+    frames: AsepriteFrames,
+    meta: AsepriteMeta,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum AsepriteFrames {
+    Hash(OrderedFrames),
+    Array(Vec<AsepriteNamedFrame>),
+}
+
+// The tags refer to the frames by their index, so the order of the hash layout matters
+struct OrderedFrames(Vec<(String, AsepriteFrame)>);
+
+impl<'de> Deserialize<'de> for OrderedFrames {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct FramesVisitor;
+
+        impl<'de> Visitor<'de> for FramesVisitor {
+            type Value = OrderedFrames;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a map of frames")
+            }
+
+            fn visit_map<M: MapAccess<'de>>(self, mut map: M) -> Result<Self::Value, M::Error> {
+                let mut frames = Vec::new();
+                while let Some(entry) = map.next_entry()? {
+                    frames.push(entry);
+                }
+                Ok(OrderedFrames(frames))
+            }
+        }
+
+        deserializer.deserialize_map(FramesVisitor)
+    }
+}
+
+#[derive(Deserialize)]
+struct AsepriteNamedFrame {
+    filename: String,
+    #[serde(flatten)]
+    frame: AsepriteFrame,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AsepriteFrame {
+    frame: AsepriteRect,
+    #[serde(default)]
+    rotated: bool,
+    sprite_source_size: AsepriteRect,
+    source_size: AsepriteSize,
+    #[serde(default = "default_duration")]
+    duration: u32,
+}
+
+fn default_duration() -> u32 {
+    100
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AsepriteMeta {
+    image: String,
+    #[serde(default)]
+    frame_tags: Vec<AsepriteTag>,
+    #[serde(default)]
+    slices: Vec<AsepriteSlice>,
+}
+
+#[derive(Deserialize)]
+struct AsepriteTag {
+    name: String,
+    from: usize,
+    to: usize,
+    #[serde(default)]
+    direction: String,
+    repeat: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct AsepriteSlice {
+    name: String,
+    keys: Vec<AsepriteSliceKey>,
+}
+
+#[derive(Deserialize)]
+struct AsepriteSliceKey {
+    bounds: AsepriteRect,
+    pivot: Option<AsepritePoint>,
+}
+
+#[derive(Deserialize)]
+struct AsepriteRect {
+    x: f32,
+    y: f32,
+    w: f32,
+    h: f32,
+}
+
+#[derive(Deserialize)]
+struct AsepriteSize {
+    w: f32,
+    h: f32,
+}
+
+#[derive(Deserialize)]
+struct AsepritePoint {
+    x: f32,
+    y: f32,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::assets::AssetStore;
+
+    // Out of the alphabetical order, the tags count on the order of the export
+    const NAMES: [&str; 4] = ["stand", "crouch", "jump", "fall"];
+    const TAGS: &str = r#""frameTags": [
+        {"name": "all", "from": 0, "to": 3, "direction": "forward"},
+        {"name": "back", "from": 1, "to": 3, "direction": "reverse", "repeat": "1"},
+        {"name": "bounce", "from": 0, "to": 3, "direction": "pingpong"}
+    ]"#;
+    const SLICES: &str = r##""slices": [{"name": "jump", "color": "#0000ffff", "keys": [
+        {"frame": 0, "bounds": {"x": 2, "y": 2, "w": 12, "h": 12}, "pivot": {"x": 6, "y": 10}}
+    ]}]"##;
+
+    // Export of the 16x16 frames in a row, `crouch` trimmed to 10x12
+    fn export(array: bool, meta: &str) -> String {
+        let frames: Vec<String> = NAMES
+            .iter()
+            .enumerate()
+            .map(|(i, name)| {
+                let (x, y, w, h) = if *name == "crouch" {
+                    (3, 4, 10, 12)
+                } else {
+                    (0, 0, 16, 16)
+                };
+                let duration = [100, 50, 100, 200][i];
+                let frame = format!(
+                    r#""frame": {{"x": {}, "y": 0, "w": {}, "h": {}}}, "rotated": false,
+                    "trimmed": {}, "spriteSourceSize": {{"x": {}, "y": {}, "w": {}, "h": {}}},
+                    "sourceSize": {{"w": 16, "h": 16}}, "duration": {}"#,
+                    i * 16,
+                    w,
+                    h,
+                    w != 16,
+                    x,
+                    y,
+                    w,
+                    h,
+                    duration
+                );
+                if array {
+                    format!(r#"{{"filename": "{}", {}}}"#, name, frame)
+                } else {
+                    format!(r#""{}": {{{}}}"#, name, frame)
+                }
+            })
+            .collect();
+        let frames = if array {
+            format!("[{}]", frames.join(", "))
+        } else {
+            format!("{{{}}}", frames.join(", "))
+        };
+        format!(
+            r#"{{"frames": {}, "meta": {{"image": "slime.png", {}}}}}"#,
+            frames, meta
+        )
+    }
+
+    fn parse(json: &str) -> Result<Atlas, AtlasError> {
+        let image = AssetStore::<Image>::default().reserve("slime");
+        Atlas::from_aseprite(image, json.as_bytes())
+    }
+
+    fn clip_frames(atlas: &Atlas, tag: &str) -> Vec<(String, u32)> {
+        let clip = atlas.clips().get(tag).expect("Clip missing");
+        clip.frames
+            .iter()
+            .map(|frame| (frame.name.clone(), frame.ticks))
+            .collect()
+    }
+
+    #[test]
+    fn hash_and_array_layouts_match() {
+        let meta = format!("{}, {}", TAGS, SLICES);
+        let hash = parse(&export(false, &meta)).unwrap();
+        let array = parse(&export(true, &meta)).unwrap();
+        assert_eq!(hash.sheet, "slime.png");
+        for name in NAMES.iter() {
+            assert_eq!(hash.frame(name), array.frame(name), "{}", name);
+        }
+        for tag in ["all", "back", "bounce"].iter() {
+            assert_eq!(hash.clips().get(tag), array.clips().get(tag), "{}", tag);
+        }
+        assert_eq!(
+            hash.frame("stand"),
+            Some(&Frame {
+                region: Rectangle::new((0., 0.), (16., 16.)),
+                offset: Vector::new(-8., -8.),
+            })
+        );
+    }
+
+    #[test]
+    fn trimmed_frame_keeps_its_placement() {
+        let atlas = parse(&export(true, TAGS)).unwrap();
+        assert_eq!(
+            atlas.frame("crouch"),
+            Some(&Frame {
+                region: Rectangle::new((16., 0.), (10., 12.)),
+                offset: Vector::new(-5., -4.),
+            })
+        );
+    }
+
+    #[test]
+    fn slice_pivot_moves_the_frame() {
+        let atlas = parse(&export(false, SLICES)).unwrap();
+        let frame = atlas.frame("jump").unwrap();
+        assert_eq!(frame.offset, Vector::new(-8., -12.));
+        // The other frames keep the centered pivot
+        assert_eq!(atlas.frame("fall").unwrap().offset, Vector::new(-8., -8.));
+    }
+
+    #[test]
+    fn tag_directions() {
+        let atlas = parse(&export(false, TAGS)).unwrap();
+        let frames = |names: &[&str]| -> Vec<(String, u32)> {
+            names
+                .iter()
+                .map(|name| {
+                    let ticks = match *name {
+                        "crouch" => 3,
+                        "fall" => 12,
+                        _ => 6,
+                    };
+                    (name.to_string(), ticks)
+                })
+                .collect()
+        };
+        assert_eq!(clip_frames(&atlas, "all"), frames(&NAMES));
+        assert_eq!(
+            clip_frames(&atlas, "back"),
+            frames(&["fall", "jump", "crouch"])
+        );
+        assert_eq!(
+            clip_frames(&atlas, "bounce"),
+            frames(&["stand", "crouch", "jump", "fall", "jump", "crouch"])
+        );
+        assert_eq!(atlas.clips().get("all").unwrap().playback, Playback::Loop);
+        assert_eq!(atlas.clips().get("back").unwrap().playback, Playback::Once);
+    }
+
+    #[test]
+    fn tag_past_the_last_frame_is_an_error() {
+        let meta = r#""frameTags": [{"name": "broken", "from": 2, "to": 4}]"#;
+        let result = parse(&export(true, meta));
+        assert!(matches!(result, Err(AtlasError::TagOutOfRange(tag)) if tag == "broken"));
+    }
+}
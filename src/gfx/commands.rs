@@ -0,0 +1,246 @@
+/*!
+Draw calls recorded as plain data, then replayed by a backend.
+
+The frame is built into a `CommandBuffer` without touching the GPU, `submit` draws it with
+quicksilver and `raster::Rasterizer` on the CPU, so the rendering can be checked in the tests
+and on CI machines without a GPU.
+*/
+use super::Images;
+use crate::engine::assets::Handle;
+use quicksilver::geom::{Circle, Rectangle, Transform, Vector};
+use quicksilver::graphics::{Color, Graphics, Image};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DrawCommand {
+    /// `region` of the image drawn into `dest`
+    Image {
+        image: Handle<Image>,
+        region: Rectangle,
+        dest: Rectangle,
+        tint: Color,
+        transform: Transform,
+    },
+    FillRect {
+        rect: Rectangle,
+        color: Color,
+        transform: Transform,
+    },
+    StrokeRect {
+        rect: Rectangle,
+        color: Color,
+        transform: Transform,
+    },
+    StrokeCircle {
+        circle: Circle,
+        color: Color,
+        transform: Transform,
+    },
+    StrokePath {
+        points: Vec<Vector>,
+        color: Color,
+        transform: Transform,
+    },
+}
+
+/// Records the draw calls, mirroring the drawing methods of `Graphics`.
+#[derive(Debug)]
+pub struct CommandBuffer {
+    commands: Vec<DrawCommand>,
+    transform: Transform,
+}
+
+impl Default for CommandBuffer {
+    fn default() -> Self {
+        Self {
+            commands: Vec::new(),
+            transform: Transform::IDENTITY,
+        }
+    }
+}
+
+impl CommandBuffer {
+    /// Applies to the commands recorded after it.
+    pub fn set_transform(&mut self, transform: Transform) {
+        self.transform = transform;
+    }
+    pub fn draw_image(
+        &mut self,
+        image: Handle<Image>,
+        region: Rectangle,
+        dest: Rectangle,
+        tint: Color,
+    ) {
+        self.commands.push(DrawCommand::Image {
+            image,
+            region,
+            dest,
+            tint,
+            transform: self.transform,
+        });
+    }
+    pub fn fill_rect(&mut self, rect: &Rectangle, color: Color) {
+        self.commands.push(DrawCommand::FillRect {
+            rect: *rect,
+            color,
+            transform: self.transform,
+        });
+    }
+    pub fn stroke_rect(&mut self, rect: &Rectangle, color: Color) {
+        self.commands.push(DrawCommand::StrokeRect {
+            rect: *rect,
+            color,
+            transform: self.transform,
+        });
+    }
+    pub fn stroke_circle(&mut self, circle: &Circle, color: Color) {
+        self.commands.push(DrawCommand::StrokeCircle {
+            circle: *circle,
+            color,
+            transform: self.transform,
+        });
+    }
+    pub fn stroke_path(&mut self, points: &[Vector], color: Color) {
+        self.commands.push(DrawCommand::StrokePath {
+            points: points.to_vec(),
+            color,
+            transform: self.transform,
+        });
+    }
+
+    pub fn commands(&self) -> &[DrawCommand] {
+        &self.commands
+    }
+}
+
+/// Draw the commands with the GPU, the missing images are replaced with the placeholder.
+pub fn submit(gfx: &mut Graphics, images: &Images, commands: &[DrawCommand]) {
+    for command in commands {
+        match command {
+            DrawCommand::Image {
+                image,
+                region,
+                dest,
+                tint,
+                transform,
+            } => {
+                let image = match images.get_or_placeholder(*image) {
+                    Some(image) => image,
+                    None => continue,
+                };
+                gfx.set_transform(*transform);
+                gfx.draw_subimage_tinted(image, *region, *dest, *tint);
+            }
+            DrawCommand::FillRect {
+                rect,
+                color,
+                transform,
+            } => {
+                gfx.set_transform(*transform);
+                gfx.fill_rect(rect, *color);
+            }
+            DrawCommand::StrokeRect {
+                rect,
+                color,
+                transform,
+            } => {
+                gfx.set_transform(*transform);
+                gfx.stroke_rect(rect, *color);
+            }
+            DrawCommand::StrokeCircle {
+                circle,
+                color,
+                transform,
+            } => {
+                gfx.set_transform(*transform);
+                gfx.stroke_circle(circle, *color);
+            }
+            DrawCommand::StrokePath {
+                points,
+                color,
+                transform,
+            } => {
+                gfx.set_transform(*transform);
+                gfx.stroke_path(points, *color);
+            }
+        }
+    }
+    gfx.set_transform(Transform::IDENTITY);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::assets::AssetStore;
+    use crate::engine::components::Position;
+    use crate::gfx::{draw_sprites, Layer, Sprite};
+    use legion::prelude::*;
+
+    fn sprite(src: Handle<Image>) -> Sprite {
+        Sprite {
+            src,
+            frame: None,
+            region: Rectangle::new((0., 0.), (4., 4.)),
+            offset: Vector::new(-2., -2.),
+            flip_x: false,
+            flip_y: false,
+            rotation: 0.,
+            scale: Vector::ONE,
+            tint: Color::WHITE,
+        }
+    }
+
+    #[test]
+    fn sprites_are_recorded_back_to_front() {
+        let mut store = AssetStore::<Image>::default();
+        let (front, back) = (store.reserve("front"), store.reserve("back"));
+        let mut world = Universe::new().create_world();
+        world.insert(
+            (),
+            vec![(
+                Position {
+                    src: Vector::new(10., 10.),
+                },
+                sprite(front),
+                Layer::new(1),
+            )],
+        );
+        let mut flipped = sprite(back);
+        flipped.flip_x = true;
+        world.insert(
+            (),
+            vec![(
+                Position {
+                    src: Vector::new(20., 10.),
+                },
+                flipped,
+            )],
+        );
+
+        let mut canvas = CommandBuffer::default();
+        draw_sprites(&mut canvas, &world);
+
+        match canvas.commands() {
+            [DrawCommand::Image {
+                image: first,
+                transform,
+                dest: flipped_dest,
+                ..
+            }, DrawCommand::Image {
+                image: second,
+                dest,
+                transform: identity,
+                ..
+            }] => {
+                assert_eq!((*first, *second), (back, front));
+                assert_eq!(*flipped_dest, Rectangle::new((-2., -2.), (4., 4.)));
+                assert_eq!(
+                    *transform,
+                    Transform::translate((20., 10.)) * Transform::scale((-1., 1.))
+                );
+                assert_eq!(*dest, Rectangle::new((8., 8.), (4., 4.)));
+                assert_eq!(*identity, Transform::IDENTITY);
+            }
+            commands => panic!("unexpected commands {:?}", commands),
+        }
+    }
+}
@@ -1,23 +1,28 @@
-use crate::game::Game;
+use super::CommandBuffer;
 use quicksilver::{
     geom::{Circle, Rectangle, Vector},
-    graphics::{Color, Graphics},
+    graphics::Color,
 };
 
+use crate::engine::input::Cursor;
+use crate::engine::Camera;
 use crate::phx::Hitbox;
 use crate::phx::PhysicsWorld;
+use crate::phx::TileColliders;
 use legion::prelude::*;
-use resphys::{BodyState, Shape};
+use resphys::{BodyHandle, BodyState, Shape};
 
-pub fn visualize_hitbox(gfx: &mut Graphics, game_data: &Game) {
-    let query = <Read<Hitbox>>::query();
-    let pworld = game_data
-        .resources
-        .get::<PhysicsWorld>()
-        .expect("PhysicsWorld missing somehow");
-    for hitbox in query.iter(&game_data.world) {
+pub fn visualize_hitbox(canvas: &mut CommandBuffer, world: &World, pworld: &PhysicsWorld) {
+    let mut bodies: Vec<BodyHandle> = <Read<Hitbox>>::query()
+        .iter(world)
+        .map(|hitbox| hitbox.src)
+        .collect();
+    for colliders in <Read<TileColliders>>::query().iter(world) {
+        bodies.extend(colliders.bodies());
+    }
+    for body in bodies {
         let physics_body = pworld
-            .get_body(hitbox.src)
+            .get_body(body)
             .expect("Debug_Info: Handle to invalid collision object");
         let position = physics_body.position;
         use Shape::*;
@@ -31,32 +36,43 @@ pub fn visualize_hitbox(gfx: &mut Graphics, game_data: &Game) {
                     BodyState::Solid => Color::BLUE,
                     BodyState::Sensor => Color::YELLOW,
                 };
-                gfx.fill_rect(&area, color.with_alpha(0.2));
-                gfx.stroke_rect(&area, color);
+                canvas.fill_rect(&area, color.with_alpha(0.2));
+                canvas.stroke_rect(&area, color);
             }
         }
-        // visualise the contacts
-        for (_, _, manifold) in pworld.manifolds.iter() {
-            for (contact, color) in manifold
-                .contacts
-                .iter()
-                .zip([Color::ORANGE, Color::RED].iter())
-            {
-                match contact {
-                    None => break,
-                    Some(c) => {
-                        let points: Vec<Vector> = vec![
-                            mint::Vector2::from(c.contact_point).into(),
-                            mint::Vector2::from(c.contact_point - c.normal * c.depth).into(),
-                        ];
-                        gfx.stroke_circle(
-                            &Circle::new(mint::Vector2::from(c.contact_point), 2.),
-                            *color,
-                        );
-                        gfx.stroke_path(&points, *color);
-                    }
+    }
+    // visualise the contacts
+    for (_, _, manifold) in pworld.manifolds.iter() {
+        for (contact, color) in manifold
+            .contacts
+            .iter()
+            .zip([Color::ORANGE, Color::RED].iter())
+        {
+            match contact {
+                None => break,
+                Some(c) => {
+                    let points: Vec<Vector> = vec![
+                        mint::Vector2::from(c.contact_point).into(),
+                        mint::Vector2::from(c.contact_point - c.normal * c.depth).into(),
+                    ];
+                    canvas.stroke_circle(
+                        &Circle::new(mint::Vector2::from(c.contact_point), 2.),
+                        *color,
+                    );
+                    canvas.stroke_path(&points, *color);
                 }
             }
         }
     }
 }
+
+pub fn visualize_camera(canvas: &mut CommandBuffer, camera: &Camera) {
+    canvas.stroke_rect(&camera.deadzone_area(), Color::MAGENTA);
+    canvas.stroke_circle(&Circle::new(camera.center, 1.), Color::MAGENTA);
+}
+
+pub fn visualize_cursor(canvas: &mut CommandBuffer, cursor: &Cursor, camera: &Camera) {
+    if let Some(position) = cursor.world_position(camera) {
+        canvas.stroke_circle(&Circle::new(position, 3.), Color::WHITE);
+    }
+}
@@ -0,0 +1,143 @@
+/*!
+Image storage with a placeholder for the missing textures.
+
+A typo in a sprite name shouldn't take the whole game down mid-frame. The missing images are
+drawn as a magenta checkerboard instead, with a single warning per image. `check` finds every
+missing reference up front, in the strict mode the game refuses to start with any of them.
+*/
+use super::{Animation, Sprite};
+use crate::engine::assets::{AssetError, AssetStore, Handle, MissingAsset};
+use crate::engine::tilemap::Tilemap;
+use crate::game::AtlasStorage;
+use fxhash::FxHashSet;
+use golem::TextureFilter;
+use legion::prelude::*;
+use quicksilver::graphics::{Graphics, Image, PixelFormat};
+use std::cell::RefCell;
+use std::ops::{Deref, DerefMut};
+
+#[derive(Default)]
+pub struct Images {
+    store: AssetStore<Image>,
+    placeholder: Option<Image>,
+    /// Fail the `check` and the loading instead of just warning
+    pub strict: bool,
+    // Handles already warned about, rendering only has a shared reference
+    warned: RefCell<FxHashSet<Handle<Image>>>,
+}
+
+impl Deref for Images {
+    type Target = AssetStore<Image>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.store
+    }
+}
+
+impl DerefMut for Images {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.store
+    }
+}
+
+impl Images {
+    /// Store the image under the name, the handles to the old one draw the new one.
+    pub fn insert(&mut self, name: &str, image: Image) -> Handle<Image> {
+        let handle = self.store.insert(name, image);
+        self.warned.borrow_mut().remove(&handle);
+        handle
+    }
+
+    /// Create the checkerboard drawn in place of the missing images.
+    pub fn create_placeholder(&mut self, gfx: &Graphics) -> quicksilver::Result<()> {
+        const MAGENTA: [u8; 4] = [255, 0, 255, 255];
+        const BLACK: [u8; 4] = [0, 0, 0, 255];
+        let pixels: Vec<u8> = [MAGENTA, BLACK, BLACK, MAGENTA].concat();
+        let placeholder = Image::from_raw(gfx, Some(&pixels), 2, 2, PixelFormat::RGBA)?;
+        placeholder.set_magnification(TextureFilter::Nearest)?;
+        self.placeholder = Some(placeholder);
+        Ok(())
+    }
+
+    /// The image, or the placeholder if it's missing.
+    ///
+    /// Warns the first time an image is missing, `None` only before the placeholder is created.
+    pub fn get_or_placeholder(&self, handle: Handle<Image>) -> Option<&Image> {
+        if let Some(image) = self.store.get(handle) {
+            return Some(image);
+        }
+        if self.warned.borrow_mut().insert(handle) {
+            warn!(
+                "Image `{}` is missing, drawing the placeholder",
+                self.store.name(handle)
+            );
+        }
+        self.placeholder.as_ref()
+    }
+
+    /// Every image and atlas frame the sprites, animations and tilemaps in the world refer to,
+    /// but which isn't loaded.
+    pub fn missing(&self, world: &World, atlases: &AtlasStorage) -> Vec<MissingAsset> {
+        let mut missing = FxHashSet::default();
+        let mut check_frame = |image: Handle<Image>, frame: &str| {
+            if let Some(atlas) = atlases.get(&image) {
+                if atlas.frame(frame).is_none() {
+                    missing.insert(MissingAsset::Frame {
+                        image: self.store.name(image).into(),
+                        frame: frame.into(),
+                    });
+                }
+            }
+        };
+
+        for sprite in <Read<Sprite>>::query().iter(world) {
+            if let Some(frame) = &sprite.frame {
+                check_frame(sprite.src, frame);
+            }
+        }
+        for (sprite, animation) in <(Read<Sprite>, Read<Animation>)>::query().iter(world) {
+            for (_, clip) in animation.clips().iter() {
+                for frame in clip.frames.iter() {
+                    check_frame(sprite.src, &frame.name);
+                }
+            }
+        }
+        let mut check_image = |image: Handle<Image>| {
+            if !self.store.is_loaded(image) {
+                missing.insert(MissingAsset::Image(self.store.name(image).into()));
+            }
+        };
+        for sprite in <Read<Sprite>>::query().iter(world) {
+            check_image(sprite.src);
+        }
+        for tilemap in <Read<Tilemap>>::query().iter(world) {
+            for tileset in tilemap.tilesets() {
+                check_image(tileset.image);
+            }
+        }
+
+        let mut missing: Vec<_> = missing.into_iter().collect();
+        missing.sort_by_key(|asset| asset.to_string());
+        missing
+    }
+
+    /// Report every missing reference at once, as an error in the strict mode.
+    pub fn check(&self, world: &World, atlases: &AtlasStorage) -> Result<(), AssetError> {
+        let missing = self.missing(world, atlases);
+        if missing.is_empty() {
+            return Ok(());
+        }
+        if self.strict {
+            return Err(AssetError::Missing(missing));
+        }
+        for asset in missing {
+            warn!("The world refers to a {}", asset);
+            if let MissingAsset::Image(image) = asset {
+                if let Some(handle) = self.store.handle(&image) {
+                    self.warned.borrow_mut().insert(handle);
+                }
+            }
+        }
+        Ok(())
+    }
+}
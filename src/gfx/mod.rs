@@ -5,27 +5,144 @@ use quicksilver::{
     graphics::{Color, Graphics},
 };
 
-use crate::engine::components::{Position, Sprite};
+use crate::engine::components::Position;
+use crate::engine::input::Cursor;
+use crate::engine::Camera;
+use crate::phx::PhysicsWorld;
 use legion::prelude::*;
 
+pub mod animation;
+pub mod atlas;
+pub mod commands;
 mod debug_info;
+pub mod images;
+#[cfg(test)]
+mod raster;
+mod sprite;
+mod target;
+pub mod tilemap;
 
-pub fn render(window: &Window, gfx: &mut Graphics, game_data: &Game) {
-    let fill = Rectangle::new_sized(Vector::new(320., 180.));
-    gfx.clear(Color::BLACK);
-    gfx.set_transform(Transform::IDENTITY);
-    gfx.fill_rect(&fill, Color::CYAN);
+pub use self::animation::Animation;
+pub use self::atlas::Atlas;
+pub use self::commands::{CommandBuffer, DrawCommand};
+pub use self::images::Images;
+pub use self::sprite::{Layer, Sprite};
+pub use self::target::LowResTarget;
+
+pub fn render(window: &Window, gfx: &mut Graphics, game_data: &Game, target: &LowResTarget) {
+    let resolution = game_data.resolution();
+    let origin = game_data
+        .resources
+        .get::<Camera>()
+        .expect("Camera missing somehow")
+        .origin(resolution);
+    let canvas = record_frame(game_data, origin, target.size());
+    let subpixel_offset = target.begin(gfx, origin, game_data.subpixel_smoothing);
+    commands::submit(gfx, &game_data.images, canvas.commands());
 
-    let query = <(Read<Position>, Read<Sprite>)>::query();
-    for (pos, img) in query.iter(&game_data.world) {
-        // TODO: Handle the error by using default texture
-        let image = game_data.images.get(&img.src).unwrap();
-        gfx.draw_image(&image, Rectangle::new(pos.src + img.offset, image.size()));
+    let upscaled = target.finish(
+        gfx,
+        game_data.window_size,
+        &game_data.viewport,
+        resolution,
+        subpixel_offset,
+    );
+    if let Err(err) = upscaled {
+        error!("Failed to upscale the frame: {}", err);
     }
 
+    let _ = gfx.present(&window);
+}
+
+/// The draw calls of a frame, in world coordinates, for a view at `origin` of `size`.
+pub fn record_frame(game_data: &Game, origin: Vector, size: Vector) -> CommandBuffer {
+    let mut canvas = CommandBuffer::default();
+    // Clearing would hit the window, so the background is drawn instead
+    let fill = Rectangle::new(origin - Vector::ONE, size + Vector::ONE * 2.);
+    canvas.fill_rect(&fill, Color::CYAN);
+
+    let view = Rectangle::new(origin, size);
+    self::tilemap::draw_tilemaps(&mut canvas, &game_data.world, view);
+    draw_sprites(&mut canvas, &game_data.world);
+
     if cfg!(feature = "debug-info") {
-        self::debug_info::visualize_hitbox(gfx, game_data);
+        let pworld = game_data
+            .resources
+            .get::<PhysicsWorld>()
+            .expect("PhysicsWorld missing somehow");
+        let camera = game_data
+            .resources
+            .get::<Camera>()
+            .expect("Camera missing somehow");
+        self::debug_info::visualize_hitbox(&mut canvas, &game_data.world, &pworld);
+        self::debug_info::visualize_camera(&mut canvas, &camera);
+        let cursor = game_data
+            .resources
+            .get::<Cursor>()
+            .expect("Cursor missing somehow");
+        self::debug_info::visualize_cursor(&mut canvas, &cursor, &camera);
     }
+    canvas
+}
+
+/// Progress bar in the middle of the window, drawn between the loaded assets.
+pub fn render_loading(window: &Window, gfx: &mut Graphics, progress: f32) {
+    let size = Vector::from(window.size());
+    let physical = size * window.scale_factor();
+    gfx.set_viewport(0, 0, physical.x as u32, physical.y as u32);
+    gfx.set_projection(Transform::orthographic(Rectangle::new_sized(size)));
+    gfx.set_transform(Transform::IDENTITY);
+    gfx.clear(Color::BLACK);
+
+    let bar = Vector::new(size.x / 2., 8.);
+    let bar_pos = (size - bar) / 2.;
+    gfx.fill_rect(
+        &Rectangle::new(bar_pos, Vector::new(bar.x * progress, bar.y)),
+        Color::WHITE,
+    );
+    gfx.stroke_rect(&Rectangle::new(bar_pos, bar), Color::WHITE);
 
     let _ = gfx.present(&window);
 }
+
+/// Every sprite of the world, back to front.
+pub fn draw_sprites(canvas: &mut CommandBuffer, world: &World) {
+    let layered = <(Read<Position>, Read<Sprite>, Read<Layer>)>::query();
+    let unlayered = <(Read<Position>, Read<Sprite>)>::query().filter(!component::<Layer>());
+    let mut sprites: Vec<_> = layered
+        .iter_entities(world)
+        .map(|(entity, (pos, img, layer))| (entity, *layer, pos, img))
+        .chain(
+            unlayered
+                .iter_entities(world)
+                .map(|(entity, (pos, img))| (entity, Layer::default(), pos, img)),
+        )
+        .collect();
+    // The entity breaks the ties, the order of the world changes along with the archetypes
+    sprites.sort_by(
+        |(entity, layer, pos, _), (other_entity, other, other_pos, _)| {
+            let order = layer.draw_order(pos.src, other, other_pos.src);
+            order.then(entity.index().cmp(&other_entity.index()))
+        },
+    );
+
+    for (_, _, pos, img) in sprites.iter() {
+        if img.is_transformed() {
+            canvas.set_transform(img.transform(pos.src));
+            canvas.draw_image(
+                img.src,
+                img.region,
+                Rectangle::new(img.offset, img.region.size()),
+                img.tint,
+            );
+            canvas.set_transform(Transform::IDENTITY);
+        } else {
+            canvas.draw_image(
+                img.src,
+                img.region,
+                Rectangle::new(pos.src + img.offset, img.region.size()),
+                img.tint,
+            );
+        }
+    }
+}
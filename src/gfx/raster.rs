@@ -0,0 +1,645 @@
+/*!
+CPU backend for the render commands, so the frames can be checked in the tests without a GPU.
+
+Every pixel is covered or not by its center, there is no antialiasing, so the same commands
+always give the same pixels. The frames are compared against the golden PNGs in
+`tests/golden`, set the `UPDATE_GOLDEN` environment variable to write them anew.
+*/
+use super::DrawCommand;
+use crate::engine::assets::Handle;
+use fxhash::FxHashMap;
+use quicksilver::geom::{Rectangle, Transform, Vector};
+use quicksilver::graphics::{Color, Image};
+use std::fmt;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+
+const GOLDEN_ENV: &str = "UPDATE_GOLDEN";
+
+#[derive(Debug)]
+pub enum RasterError {
+    Io(std::io::Error),
+    Decode(png::DecodingError),
+    Encode(png::EncodingError),
+    /// There is nothing to compare with, it's only written with `UPDATE_GOLDEN`
+    MissingGolden(PathBuf),
+    /// The frame differs from the golden image
+    Mismatch {
+        golden: PathBuf,
+        /// Where the frame was written for a look
+        actual: PathBuf,
+        pixels: usize,
+    },
+}
+
+impl fmt::Display for RasterError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RasterError::Io(err) => write!(f, "{}", err),
+            RasterError::Decode(err) => write!(f, "can't decode the PNG: {}", err),
+            RasterError::Encode(err) => write!(f, "can't encode the PNG: {}", err),
+            RasterError::MissingGolden(golden) => write!(
+                f,
+                "there is no `{}`, set `{}` to write it",
+                golden.display(),
+                GOLDEN_ENV
+            ),
+            RasterError::Mismatch {
+                golden,
+                actual,
+                pixels,
+            } => write!(
+                f,
+                "{} pixels differ from `{}`, the frame is in `{}`",
+                pixels,
+                golden.display(),
+                actual.display()
+            ),
+        }
+    }
+}
+
+impl std::error::Error for RasterError {}
+
+impl From<std::io::Error> for RasterError {
+    fn from(err: std::io::Error) -> Self {
+        RasterError::Io(err)
+    }
+}
+
+impl From<png::DecodingError> for RasterError {
+    fn from(err: png::DecodingError) -> Self {
+        RasterError::Decode(err)
+    }
+}
+
+impl From<png::EncodingError> for RasterError {
+    fn from(err: png::EncodingError) -> Self {
+        RasterError::Encode(err)
+    }
+}
+
+/// RGBA image in the memory, 8 bits per channel.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pixels {
+    pub width: u32,
+    pub height: u32,
+    /// Rows from the top
+    pub data: Vec<u8>,
+}
+
+impl Pixels {
+    /// Transparent black.
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            data: vec![0; width as usize * height as usize * 4],
+        }
+    }
+
+    pub fn from_png(bytes: &[u8]) -> Result<Self, RasterError> {
+        let mut decoder = png::Decoder::new(bytes);
+        decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+        let (info, mut reader) = decoder.read_info()?;
+        let mut buffer = vec![0; info.buffer_size()];
+        reader.next_frame(&mut buffer)?;
+
+        let data = match info.color_type {
+            png::ColorType::RGBA => buffer,
+            png::ColorType::RGB => buffer
+                .chunks(3)
+                .flat_map(|rgb| vec![rgb[0], rgb[1], rgb[2], 255])
+                .collect(),
+            png::ColorType::GrayscaleAlpha => buffer
+                .chunks(2)
+                .flat_map(|ga| vec![ga[0], ga[0], ga[0], ga[1]])
+                .collect(),
+            // Indexed is expanded to RGB(A) already
+            png::ColorType::Grayscale | png::ColorType::Indexed => {
+                buffer.iter().flat_map(|&g| vec![g, g, g, 255]).collect()
+            }
+        };
+        Ok(Self {
+            width: info.width,
+            height: info.height,
+            data,
+        })
+    }
+    pub fn load_png(path: impl AsRef<Path>) -> Result<Self, RasterError> {
+        Self::from_png(&std::fs::read(path)?)
+    }
+
+    pub fn to_png(&self) -> Result<Vec<u8>, RasterError> {
+        let mut bytes = Vec::new();
+        self.write_png(&mut bytes)?;
+        Ok(bytes)
+    }
+    pub fn save_png(&self, path: impl AsRef<Path>) -> Result<(), RasterError> {
+        self.write_png(BufWriter::new(File::create(path)?))
+    }
+
+    fn write_png(&self, writer: impl std::io::Write) -> Result<(), RasterError> {
+        let mut encoder = png::Encoder::new(writer, self.width, self.height);
+        encoder.set_color(png::ColorType::RGBA);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.write_header()?.write_image_data(&self.data)?;
+        Ok(())
+    }
+
+    pub fn get(&self, x: u32, y: u32) -> [u8; 4] {
+        let i = self.index(x, y);
+        [
+            self.data[i],
+            self.data[i + 1],
+            self.data[i + 2],
+            self.data[i + 3],
+        ]
+    }
+    pub fn set(&mut self, x: u32, y: u32, rgba: [u8; 4]) {
+        let i = self.index(x, y);
+        self.data[i..i + 4].copy_from_slice(&rgba);
+    }
+
+    fn index(&self, x: u32, y: u32) -> usize {
+        (y as usize * self.width as usize + x as usize) * 4
+    }
+}
+
+/// Draws the commands into `Pixels`, the images are looked up by their handles.
+pub struct Rasterizer<'a> {
+    target: Pixels,
+    origin: Vector,
+    images: &'a FxHashMap<Handle<Image>, Pixels>,
+}
+
+impl<'a> Rasterizer<'a> {
+    /// Frame of `width` by `height` pixels, with `origin` in the world at its top left corner.
+    pub fn new(
+        width: u32,
+        height: u32,
+        origin: Vector,
+        images: &'a FxHashMap<Handle<Image>, Pixels>,
+    ) -> Self {
+        Self {
+            target: Pixels::new(width, height),
+            origin,
+            images,
+        }
+    }
+
+    pub fn clear(&mut self, color: Color) {
+        let rgba = to_rgba(color);
+        for pixel in self.target.data.chunks_mut(4) {
+            pixel.copy_from_slice(&rgba);
+        }
+    }
+
+    pub fn draw(&mut self, commands: &[DrawCommand]) {
+        for command in commands {
+            self.draw_command(command);
+        }
+    }
+
+    pub fn finish(self) -> Pixels {
+        self.target
+    }
+
+    fn draw_command(&mut self, command: &DrawCommand) {
+        match command {
+            DrawCommand::Image {
+                image,
+                region,
+                dest,
+                tint,
+                transform,
+            } => {
+                let images = self.images;
+                let image = images.get(image);
+                self.cover(*transform, *dest, |local| {
+                    let source = match image {
+                        Some(image) => sample(image, *region, *dest, local),
+                        // The placeholder of the GPU backend is magenta as well
+                        None => Color::MAGENTA,
+                    };
+                    Some(multiply(source, *tint))
+                });
+            }
+            DrawCommand::FillRect {
+                rect,
+                color,
+                transform,
+            } => self.cover(*transform, *rect, |local| {
+                if contains(rect, local) {
+                    Some(*color)
+                } else {
+                    None
+                }
+            }),
+            DrawCommand::StrokeRect {
+                rect,
+                color,
+                transform,
+            } => {
+                let inner = Rectangle::new(rect.pos + Vector::ONE, rect.size - Vector::ONE * 2.);
+                self.cover(*transform, *rect, |local| {
+                    if contains(rect, local) && !contains(&inner, local) {
+                        Some(*color)
+                    } else {
+                        None
+                    }
+                })
+            }
+            DrawCommand::StrokeCircle {
+                circle,
+                color,
+                transform,
+            } => {
+                let bounds = Rectangle::new(
+                    circle.pos - Vector::ONE * circle.radius,
+                    Vector::ONE * circle.radius * 2.,
+                );
+                self.cover(*transform, grow(bounds, 1.), |local| {
+                    if (distance(local, circle.pos) - circle.radius).abs() <= 0.5 {
+                        Some(*color)
+                    } else {
+                        None
+                    }
+                })
+            }
+            DrawCommand::StrokePath {
+                points,
+                color,
+                transform,
+            } => {
+                if points.is_empty() {
+                    return;
+                }
+                self.cover(*transform, grow(bounds_of(points), 1.), |local| {
+                    let on_path = points
+                        .windows(2)
+                        .any(|segment| distance_to_segment(local, segment[0], segment[1]) <= 0.5);
+                    if on_path {
+                        Some(*color)
+                    } else {
+                        None
+                    }
+                })
+            }
+        }
+    }
+
+    // Blend `shade` into the pixels whose centers fall into `bounds` under the transform,
+    // the shading gets the center in the coordinates of the command
+    fn cover(
+        &mut self,
+        transform: Transform,
+        bounds: Rectangle,
+        shade: impl Fn(Vector) -> Option<Color>,
+    ) {
+        let (pos, size) = (bounds.pos, bounds.size);
+        let corners: Vec<Vector> = [
+            pos,
+            pos + Vector::new(size.x, 0.),
+            pos + Vector::new(0., size.y),
+            pos + size,
+        ]
+        .iter()
+        .map(|corner| transform * *corner - self.origin)
+        .collect();
+        let screen = bounds_of(&corners);
+        let clamp = |value: f32, limit: u32| value.max(0.).min(limit as f32) as u32;
+        let (width, height) = (self.target.width, self.target.height);
+        let x0 = clamp(screen.pos.x.floor(), width);
+        let x1 = clamp((screen.pos.x + screen.size.x).ceil(), width);
+        let y0 = clamp(screen.pos.y.floor(), height);
+        let y1 = clamp((screen.pos.y + screen.size.y).ceil(), height);
+
+        let inverse = transform.inverse();
+        for y in y0..y1 {
+            for x in x0..x1 {
+                let center = Vector::new(x as f32 + 0.5, y as f32 + 0.5) + self.origin;
+                let local = inverse * center;
+                if !contains(&bounds, local) {
+                    continue;
+                }
+                if let Some(color) = shade(local) {
+                    let blended = blend(self.target.get(x, y), color);
+                    self.target.set(x, y, blended);
+                }
+            }
+        }
+    }
+}
+
+/// Compare the frame with the golden PNG, the frame becomes the golden image only when
+/// `UPDATE_GOLDEN` is set.
+pub fn compare_golden(frame: &Pixels, golden: impl AsRef<Path>) -> Result<(), RasterError> {
+    let golden = golden.as_ref();
+    if std::env::var_os(GOLDEN_ENV).is_some() {
+        if let Some(dir) = golden.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        return frame.save_png(golden);
+    }
+    if !golden.exists() {
+        return Err(RasterError::MissingGolden(golden.into()));
+    }
+
+    let expected = Pixels::load_png(golden)?;
+    let pixels = if (expected.width, expected.height) != (frame.width, frame.height) {
+        frame.width as usize * frame.height as usize
+    } else {
+        expected
+            .data
+            .chunks(4)
+            .zip(frame.data.chunks(4))
+            .filter(|(expected, actual)| expected != actual)
+            .count()
+    };
+    if pixels == 0 {
+        return Ok(());
+    }
+    let actual = golden.with_extension("actual.png");
+    frame.save_png(&actual)?;
+    Err(RasterError::Mismatch {
+        golden: golden.into(),
+        actual,
+        pixels,
+    })
+}
+
+// Half open, like the pixels
+fn contains(rect: &Rectangle, point: Vector) -> bool {
+    point.x >= rect.pos.x
+        && point.y >= rect.pos.y
+        && point.x < rect.pos.x + rect.size.x
+        && point.y < rect.pos.y + rect.size.y
+}
+
+// Nearest neighbour, like the images loaded for the game
+fn sample(image: &Pixels, region: Rectangle, dest: Rectangle, local: Vector) -> Color {
+    let u = (local.x - dest.pos.x) / dest.size.x;
+    let v = (local.y - dest.pos.y) / dest.size.y;
+    let x = (region.pos.x + u * region.size.x).floor();
+    let y = (region.pos.y + v * region.size.y).floor();
+    if x < 0. || y < 0. || x >= image.width as f32 || y >= image.height as f32 {
+        return Color::from_rgba(0, 0, 0, 0.);
+    }
+    let [r, g, b, a] = image.get(x as u32, y as u32);
+    Color::from_rgba(r, g, b, a as f32 / 255.)
+}
+
+fn multiply(color: Color, tint: Color) -> Color {
+    Color {
+        r: color.r * tint.r,
+        g: color.g * tint.g,
+        b: color.b * tint.b,
+        a: color.a * tint.a,
+    }
+}
+
+// Source over
+fn blend(dst: [u8; 4], src: Color) -> [u8; 4] {
+    let a = src.a;
+    let mix = |dst: u8, src: f32| to_byte(src * a + dst as f32 / 255. * (1. - a));
+    [
+        mix(dst[0], src.r),
+        mix(dst[1], src.g),
+        mix(dst[2], src.b),
+        to_byte(a + dst[3] as f32 / 255. * (1. - a)),
+    ]
+}
+
+fn to_rgba(color: Color) -> [u8; 4] {
+    [
+        to_byte(color.r),
+        to_byte(color.g),
+        to_byte(color.b),
+        to_byte(color.a),
+    ]
+}
+
+fn to_byte(value: f32) -> u8 {
+    (value.max(0.).min(1.) * 255.).round() as u8
+}
+
+fn distance_to_segment(point: Vector, start: Vector, end: Vector) -> f32 {
+    let (dx, dy) = (end.x - start.x, end.y - start.y);
+    let length = dx * dx + dy * dy;
+    let t = if length == 0. {
+        0.
+    } else {
+        (((point.x - start.x) * dx + (point.y - start.y) * dy) / length)
+            .max(0.)
+            .min(1.)
+    };
+    distance(point, Vector::new(start.x + dx * t, start.y + dy * t))
+}
+
+fn distance(a: Vector, b: Vector) -> f32 {
+    (a.x - b.x).hypot(a.y - b.y)
+}
+
+// Smallest rectangle around the points
+fn bounds_of(points: &[Vector]) -> Rectangle {
+    let (mut min, mut max) = (points[0], points[0]);
+    for point in points {
+        min = Vector::new(min.x.min(point.x), min.y.min(point.y));
+        max = Vector::new(max.x.max(point.x), max.y.max(point.y));
+    }
+    Rectangle::new(min, max - min)
+}
+
+fn grow(rect: Rectangle, by: f32) -> Rectangle {
+    Rectangle::new(
+        rect.pos - Vector::ONE * by,
+        rect.size + Vector::ONE * by * 2.,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::assets::AssetStore;
+    use crate::engine::components::Position;
+    use crate::gfx::{draw_sprites, CommandBuffer, Layer, Sprite};
+    use legion::prelude::*;
+
+    const RED: [u8; 4] = [255, 0, 0, 255];
+    const GREEN: [u8; 4] = [0, 255, 0, 255];
+    const BLUE: [u8; 4] = [0, 0, 255, 255];
+    const NOTHING: [u8; 4] = [0, 0, 0, 0];
+
+    fn rasterize(
+        canvas: &CommandBuffer,
+        size: u32,
+        origin: Vector,
+        images: &FxHashMap<Handle<Image>, Pixels>,
+    ) -> Pixels {
+        let mut rasterizer = Rasterizer::new(size, size, origin, images);
+        rasterizer.draw(canvas.commands());
+        rasterizer.finish()
+    }
+
+    #[test]
+    fn rect_covers_pixel_centers_in_view() {
+        let mut canvas = CommandBuffer::default();
+        canvas.fill_rect(&Rectangle::new((11., 11.), (2., 2.)), Color::RED);
+        let frame = rasterize(&canvas, 4, Vector::new(10., 10.), &FxHashMap::default());
+
+        assert_eq!(frame.get(1, 1), RED);
+        assert_eq!(frame.get(2, 2), RED);
+        assert_eq!(frame.get(0, 0), NOTHING);
+        assert_eq!(frame.get(3, 2), NOTHING);
+    }
+
+    #[test]
+    fn stroked_rect_is_one_pixel_wide() {
+        let mut canvas = CommandBuffer::default();
+        canvas.stroke_rect(&Rectangle::new((0., 0.), (4., 4.)), Color::GREEN);
+        let frame = rasterize(&canvas, 4, Vector::new(0., 0.), &FxHashMap::default());
+
+        assert_eq!(frame.get(0, 0), GREEN);
+        assert_eq!(frame.get(3, 1), GREEN);
+        assert_eq!(frame.get(1, 1), NOTHING);
+        assert_eq!(frame.get(2, 2), NOTHING);
+    }
+
+    #[test]
+    fn flipped_image_is_mirrored() {
+        let handle = AssetStore::<Image>::default().reserve("image");
+        let mut image = Pixels::new(2, 1);
+        image.set(0, 0, RED);
+        image.set(1, 0, GREEN);
+        let mut images = FxHashMap::default();
+        images.insert(handle, image);
+
+        let mut canvas = CommandBuffer::default();
+        canvas.set_transform(Transform::translate((1., 0.)) * Transform::scale((-1., 1.)));
+        canvas.draw_image(
+            handle,
+            Rectangle::new((0., 0.), (2., 1.)),
+            Rectangle::new((-1., 0.), (2., 1.)),
+            Color::WHITE,
+        );
+        let frame = rasterize(&canvas, 2, Vector::new(0., 0.), &images);
+
+        assert_eq!(frame.get(0, 0), GREEN);
+        assert_eq!(frame.get(1, 0), RED);
+    }
+
+    #[test]
+    fn missing_image_is_magenta() {
+        let handle = AssetStore::<Image>::default().reserve("missing");
+        let mut canvas = CommandBuffer::default();
+        canvas.draw_image(
+            handle,
+            Rectangle::new((0., 0.), (1., 1.)),
+            Rectangle::new((0., 0.), (1., 1.)),
+            Color::WHITE,
+        );
+        let frame = rasterize(&canvas, 1, Vector::new(0., 0.), &FxHashMap::default());
+
+        assert_eq!(frame.get(0, 0), [255, 0, 255, 255]);
+    }
+
+    // Two by two pixels, the bottom right one transparent
+    fn quarters() -> Pixels {
+        let mut image = Pixels::new(2, 2);
+        image.set(0, 0, RED);
+        image.set(1, 0, GREEN);
+        image.set(0, 1, BLUE);
+        image
+    }
+
+    fn sprite(src: Handle<Image>) -> Sprite {
+        Sprite {
+            src,
+            frame: None,
+            region: Rectangle::new((0., 0.), (2., 2.)),
+            offset: Vector::new(-1., -1.),
+            flip_x: false,
+            flip_y: false,
+            rotation: 0.,
+            scale: Vector::ONE,
+            tint: Color::WHITE,
+        }
+    }
+
+    #[test]
+    fn sprites_match_golden() {
+        let handle = AssetStore::<Image>::default().reserve("quarters");
+        let mut images = FxHashMap::default();
+        images.insert(handle, quarters());
+
+        let mut world = Universe::new().create_world();
+        let mut scaled = sprite(handle);
+        scaled.scale = Vector::new(2., 2.);
+        let mut flipped = sprite(handle);
+        flipped.flip_x = true;
+        world.insert(
+            (),
+            vec![
+                (
+                    Position {
+                        src: Vector::new(4., 4.),
+                    },
+                    scaled,
+                ),
+                (
+                    Position {
+                        src: Vector::new(10., 4.),
+                    },
+                    flipped,
+                ),
+            ],
+        );
+        // Over the transparent corner of the scaled one
+        world.insert(
+            (),
+            vec![(
+                Position {
+                    src: Vector::new(5., 5.),
+                },
+                sprite(handle),
+                Layer::new(1),
+            )],
+        );
+
+        let mut canvas = CommandBuffer::default();
+        draw_sprites(&mut canvas, &world);
+        canvas.stroke_rect(&Rectangle::new((0., 0.), (16., 16.)), Color::WHITE);
+        let mut rasterizer = Rasterizer::new(16, 16, Vector::ZERO, &images);
+        rasterizer.clear(Color::CYAN);
+        rasterizer.draw(canvas.commands());
+
+        let golden = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden/sprites.png");
+        if let Err(err) = compare_golden(&rasterizer.finish(), golden) {
+            panic!("{}", err);
+        }
+    }
+
+    #[test]
+    fn missing_golden_is_an_error() {
+        let golden = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden/missing.png");
+        if std::env::var_os(GOLDEN_ENV).is_some() {
+            return;
+        }
+        match compare_golden(&Pixels::new(1, 1), &golden) {
+            Err(RasterError::MissingGolden(path)) => assert_eq!(path, golden),
+            result => panic!("unexpected {:?}", result),
+        }
+        assert!(!golden.exists());
+    }
+
+    #[test]
+    fn png_round_trip() {
+        let mut pixels = Pixels::new(3, 2);
+        pixels.set(0, 0, RED);
+        pixels.set(2, 1, [1, 2, 3, 4]);
+        let decoded = Pixels::from_png(&pixels.to_png().unwrap()).unwrap();
+        assert_eq!(decoded, pixels);
+    }
+}
@@ -0,0 +1,137 @@
+use super::atlas::{Atlas, Frame};
+use crate::engine::assets::Handle;
+use quicksilver::geom::{Rectangle, Transform, Vector};
+use quicksilver::graphics::{Color, Image};
+use std::cmp::Ordering;
+
+// Sprites are referenced by their pivot
+pub struct Sprite {
+    pub src: Handle<Image>,
+    /// Name of the atlas frame, `None` when the whole image is drawn
+    pub frame: Option<String>,
+    /// Part of the image to draw
+    pub region: Rectangle,
+    /// Top left corner of the region relative to the position
+    pub offset: Vector,
+    pub flip_x: bool,
+    pub flip_y: bool,
+    /// Clockwise, in degrees, around the pivot
+    pub rotation: f32,
+    pub scale: Vector,
+    /// Multiplied with the colors of the image, white keeps them as they are
+    pub tint: Color,
+}
+
+impl Sprite {
+    /// Whole image, centered on the position.
+    pub fn new(src: Handle<Image>, image: &Image) -> Self {
+        Self::with_region(
+            src,
+            None,
+            Rectangle::new_sized(image.size()),
+            -image.size() / 2.,
+        )
+    }
+    /// Named frame of the atlas, `None` if the atlas doesn't have it.
+    pub fn from_atlas(atlas: &Atlas, frame: &str) -> Option<Self> {
+        let Frame { region, offset } = atlas.frame(frame)?.clone();
+        Some(Self::with_region(
+            atlas.image,
+            Some(frame.into()),
+            region,
+            offset,
+        ))
+    }
+    /// Switch to another frame of the same atlas, keeping the current one if it's missing.
+    pub fn set_frame(&mut self, atlas: &Atlas, frame: &str) -> bool {
+        match atlas.frame(frame) {
+            Some(found) => {
+                self.frame = Some(frame.into());
+                self.region = found.region;
+                self.offset = found.offset;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Follow the image drawn whole, as `Sprite::new` does, to its new size.
+    ///
+    /// The pivot moves along to stay at the same spot relative to the center. Returns whether
+    /// the sprite drew the whole image of `old_size`, the other ones are left as they are.
+    pub fn resize_whole_image(&mut self, old_size: Vector, new_size: Vector) -> bool {
+        if self.frame.is_some() || self.region != Rectangle::new_sized(old_size) {
+            return false;
+        }
+        self.region = Rectangle::new_sized(new_size);
+        self.offset = self.offset + (old_size - new_size) / 2.;
+        true
+    }
+
+    /// Whether drawing needs more than moving the region to the position.
+    pub fn is_transformed(&self) -> bool {
+        self.flip_x || self.flip_y || self.rotation != 0. || self.scale != Vector::ONE
+    }
+    /// Transform of the sprite drawn at `position`, the region is drawn at `offset` under it.
+    pub fn transform(&self, position: Vector) -> Transform {
+        let flip = |flipped: bool| if flipped { -1. } else { 1. };
+        Transform::translate(position)
+            * Transform::rotate(self.rotation)
+            * Transform::scale(Vector::new(
+                self.scale.x * flip(self.flip_x),
+                self.scale.y * flip(self.flip_y),
+            ))
+    }
+
+    fn with_region(
+        src: Handle<Image>,
+        frame: Option<String>,
+        region: Rectangle,
+        offset: Vector,
+    ) -> Self {
+        Self {
+            src,
+            frame,
+            region,
+            offset,
+            flip_x: false,
+            flip_y: false,
+            rotation: 0.,
+            scale: Vector::ONE,
+            tint: Color::WHITE,
+        }
+    }
+}
+
+/// Draw order of the sprite, the ones without a layer are drawn as if it was `Layer::default()`.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Layer {
+    /// Higher layers are drawn on top
+    pub z: i32,
+    /// Sprites lower on the screen are drawn over the ones above them, for top-down scenes
+    ///
+    /// In the same layer the sprites without y-sorting go below the y-sorted ones.
+    pub y_sort: bool,
+}
+
+impl Layer {
+    pub fn new(z: i32) -> Self {
+        Self { z, y_sort: false }
+    }
+    pub fn y_sorted(z: i32) -> Self {
+        Self { z, y_sort: true }
+    }
+
+    /// Order of two sprites at the given positions, sprites that compare equal keep their order.
+    pub fn draw_order(&self, position: Vector, other: &Layer, other_position: Vector) -> Ordering {
+        let y = |layer: &Layer, position: Vector| if layer.y_sort { position.y } else { 0. };
+        self.z
+            .cmp(&other.z)
+            .then(self.y_sort.cmp(&other.y_sort))
+            .then_with(|| {
+                y(self, position)
+                    .partial_cmp(&y(other, other_position))
+                    .unwrap_or(Ordering::Equal)
+            })
+    }
+}
@@ -0,0 +1,111 @@
+use golem::TextureFilter;
+use quicksilver::{
+    geom::{Rectangle, Transform, Vector},
+    graphics::{Color, Graphics, Image, PixelFormat, Surface},
+    Result,
+};
+
+/// Offscreen framebuffer the game is drawn into at its native resolution,
+/// then upscaled to the window in a single draw.
+pub struct LowResTarget {
+    // Shares the texture with the surface
+    image: Image,
+    surface: Surface,
+    size: Vector,
+}
+
+impl LowResTarget {
+    /// The target is a pixel larger than `size` in both directions,
+    /// so the subpixel camera offset never reveals its edge.
+    pub fn new(gfx: &Graphics, size: Vector) -> Result<Self> {
+        let (width, height) = (size.x as u32 + 1, size.y as u32 + 1);
+        let image = Image::from_raw(gfx, None, width, height, PixelFormat::RGBA)?;
+        image.set_magnification(TextureFilter::Nearest)?;
+        let surface = Surface::new(gfx, image.clone())?;
+        Ok(Self {
+            image,
+            surface,
+            size: Vector::new(width, height),
+        })
+    }
+
+    pub fn size(&self) -> Vector {
+        self.size
+    }
+
+    /// Start drawing the world with the camera at `camera` (top left corner of the view).
+    ///
+    /// Returns the part of the camera offset that has to be applied when upscaling,
+    /// it's non-zero only with the subpixel smoothing.
+    pub fn begin(&self, gfx: &mut Graphics, camera: Vector, subpixel_smoothing: bool) -> Vector {
+        // Flooring keeps the offset positive, so the extra pixel covers it
+        let snapped = Vector::new(camera.x.floor(), camera.y.floor());
+        gfx.set_projection(Transform::orthographic(Rectangle::new(snapped, self.size)));
+        gfx.set_transform(Transform::IDENTITY);
+        if subpixel_smoothing {
+            camera - snapped
+        } else {
+            Vector::ZERO
+        }
+    }
+
+    /// Flush the world into the target and draw it scaled into the viewport of the window,
+    /// clearing the rest of the window to black.
+    ///
+    /// The viewport may start outside of the window when the content is cropped.
+    pub fn finish(
+        &self,
+        gfx: &mut Graphics,
+        window_size: Vector,
+        viewport: &Rectangle,
+        dimensions: Vector,
+        subpixel_offset: Vector,
+    ) -> Result<()> {
+        gfx.set_viewport(0, 0, self.size.x as u32, self.size.y as u32);
+        gfx.flush(Some(&self.surface))?;
+
+        gfx.clear(Color::BLACK);
+        gfx.set_viewport(0, 0, window_size.x as u32, window_size.y as u32);
+        gfx.set_projection(Transform::orthographic(Rectangle::new_sized(window_size)));
+        gfx.set_transform(Transform::IDENTITY);
+        let area = upscaled(viewport, dimensions, self.size, subpixel_offset);
+        gfx.draw_image(&self.image, area);
+        Ok(())
+    }
+}
+
+/// Area of the window the target of `size` covers, once the `dimensions` of the game are
+/// scaled into the viewport.
+fn upscaled(viewport: &Rectangle, dimensions: Vector, size: Vector, offset: Vector) -> Rectangle {
+    let scale = Vector::new(
+        viewport.width() / dimensions.x,
+        viewport.height() / dimensions.y,
+    );
+    Rectangle::new(
+        Vector::new(
+            viewport.x() - offset.x * scale.x,
+            viewport.y() - offset.y * scale.y,
+        ),
+        Vector::new(size.x * scale.x, size.y * scale.y),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cropped_viewport_keeps_its_origin() {
+        // IntegerCrop of 320x180 at 3x into a 900x500 window
+        let viewport = Rectangle::new(Vector::new(-30., -20.), Vector::new(960., 540.));
+        let dimensions = Vector::new(320., 180.);
+        let size = Vector::new(321., 181.);
+
+        let area = upscaled(&viewport, dimensions, size, Vector::ZERO);
+        assert_eq!(area.pos, Vector::new(-30., -20.));
+        assert_eq!(area.size, Vector::new(963., 543.));
+
+        let area = upscaled(&viewport, dimensions, size, Vector::new(0.5, 0.25));
+        assert_eq!(area.pos, Vector::new(-31.5, -20.75));
+    }
+}
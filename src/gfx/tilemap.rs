@@ -0,0 +1,317 @@
+/*!
+Drawing the tilemaps chunk by chunk.
+
+The tiles of a chunk are resolved into quads once and kept until the revision of the chunk
+changes. Only the chunks overlapping the view are drawn. The animated tiles are kept apart and
+resolved every frame against the clock of the map.
+*/
+use super::CommandBuffer;
+use crate::engine::components::Position;
+use crate::engine::tilemap::{Tile, Tilemap, Tileset, CHUNK_SIZE};
+use legion::prelude::*;
+use quicksilver::geom::{Rectangle, Transform, Vector};
+use quicksilver::graphics::Color;
+
+struct TileQuad {
+    region: Rectangle,
+    /// Relative to the map
+    dest: Rectangle,
+    flip_x: bool,
+    flip_y: bool,
+}
+
+#[derive(Default)]
+struct Chunk {
+    built: Option<u32>,
+    quads: Vec<TileQuad>,
+    animated: Vec<(Rectangle, Tile)>,
+}
+
+/// Render cache of a `Tilemap`, on the same entity.
+#[derive(Default)]
+pub struct TileChunks {
+    // Per layer, the chunks in rows
+    layers: Vec<Vec<Chunk>>,
+    tick: u32,
+}
+
+impl TileChunks {
+    /// Rebuild the chunks edited since the last call, returns how many there were.
+    pub fn rebuild(&mut self, tilemap: &Tilemap) -> usize {
+        let (columns, rows) = tilemap.chunks();
+        let mut rebuilt = 0;
+        self.layers.resize_with(tilemap.layers().len(), Vec::new);
+        for (index, layer) in tilemap.layers().iter().enumerate() {
+            let chunks = &mut self.layers[index];
+            chunks.resize_with(columns as usize * rows as usize, Chunk::default);
+            let tileset = match tilemap.tileset(layer.tileset) {
+                Some(tileset) => tileset,
+                None => continue,
+            };
+            for row in 0..rows {
+                for column in 0..columns {
+                    let revision = tilemap.chunk_revision(index, (column, row));
+                    let chunk = &mut chunks[(row * columns + column) as usize];
+                    if chunk.built == Some(revision) {
+                        continue;
+                    }
+                    build_chunk(chunk, tilemap, index, tileset, (column, row));
+                    chunk.built = Some(revision);
+                    rebuilt += 1;
+                }
+            }
+        }
+        rebuilt
+    }
+
+    /// Advance the animated tiles.
+    pub fn tick(&mut self) {
+        self.tick = self.tick.wrapping_add(1);
+    }
+
+    /// Emit the built chunks of the map at `position` that overlap the view.
+    pub fn draw(
+        &self,
+        canvas: &mut CommandBuffer,
+        tilemap: &Tilemap,
+        position: Vector,
+        view: Rectangle,
+    ) {
+        let (columns, rows) = tilemap.chunks();
+        let chunk_size = tilemap.tile_size * CHUNK_SIZE as f32;
+        let first = |start: f32, size: f32| (start / size).floor().max(0.) as u32;
+        let last =
+            |end: f32, size: f32, count: u32| ((end / size).ceil().max(0.) as u32).min(count);
+        let local = view.pos - position;
+        let (x0, x1) = (
+            first(local.x, chunk_size.x),
+            last(local.x + view.size.x, chunk_size.x, columns),
+        );
+        let (y0, y1) = (
+            first(local.y, chunk_size.y),
+            last(local.y + view.size.y, chunk_size.y, rows),
+        );
+
+        for (index, layer) in tilemap.layers().iter().enumerate() {
+            let (chunks, tileset) = match (self.layers.get(index), tilemap.tileset(layer.tileset)) {
+                (Some(chunks), Some(tileset)) if layer.visible => (chunks, tileset),
+                _ => continue,
+            };
+            for row in y0..y1 {
+                for column in x0..x1 {
+                    let chunk = match chunks.get((row * columns + column) as usize) {
+                        Some(chunk) => chunk,
+                        None => continue,
+                    };
+                    for quad in chunk.quads.iter() {
+                        draw_quad(canvas, tileset, quad, position);
+                    }
+                    for (dest, tile) in chunk.animated.iter() {
+                        let id = tileset.animated(tile.id, self.tick);
+                        if let Some(quad) = tile_quad(tileset, Tile { id, ..*tile }, *dest) {
+                            draw_quad(canvas, tileset, &quad, position);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn build_chunk(
+    chunk: &mut Chunk,
+    tilemap: &Tilemap,
+    layer: usize,
+    tileset: &Tileset,
+    position: (u32, u32),
+) {
+    chunk.quads.clear();
+    chunk.animated.clear();
+    for (x, y) in tilemap.chunk_cells(position) {
+        let tile = match tilemap.tile(layer, x, y) {
+            Some(tile) => tile,
+            None => continue,
+        };
+        let dest = Rectangle::new(
+            Vector::new(
+                x as f32 * tilemap.tile_size.x,
+                y as f32 * tilemap.tile_size.y,
+            ),
+            tilemap.tile_size,
+        );
+        if tileset.is_animated(tile.id) {
+            chunk.animated.push((dest, tile));
+        } else if let Some(quad) = tile_quad(tileset, tile, dest) {
+            chunk.quads.push(quad);
+        } else {
+            warn!(
+                "Tile {} at {}, {} is not in the tileset of layer `{}`",
+                tile.id,
+                x,
+                y,
+                tilemap.layers()[layer].name
+            );
+        }
+    }
+}
+
+fn tile_quad(tileset: &Tileset, tile: Tile, dest: Rectangle) -> Option<TileQuad> {
+    Some(TileQuad {
+        region: tileset.region(tile.id)?,
+        dest,
+        flip_x: tile.flip_x,
+        flip_y: tile.flip_y,
+    })
+}
+
+fn draw_quad(canvas: &mut CommandBuffer, tileset: &Tileset, quad: &TileQuad, position: Vector) {
+    if quad.flip_x || quad.flip_y {
+        let flip = |flipped: bool| if flipped { -1. } else { 1. };
+        let center = position + quad.dest.pos + quad.dest.size / 2.;
+        canvas.set_transform(
+            Transform::translate(center)
+                * Transform::scale(Vector::new(flip(quad.flip_x), flip(quad.flip_y))),
+        );
+        canvas.draw_image(
+            tileset.image,
+            quad.region,
+            Rectangle::new(-quad.dest.size / 2., quad.dest.size),
+            Color::WHITE,
+        );
+        canvas.set_transform(Transform::IDENTITY);
+    } else {
+        canvas.draw_image(
+            tileset.image,
+            quad.region,
+            Rectangle::new(position + quad.dest.pos, quad.dest.size),
+            Color::WHITE,
+        );
+    }
+}
+
+/// Every tilemap of the world overlapping the view, in the order of their layers.
+pub fn draw_tilemaps(canvas: &mut CommandBuffer, world: &World, view: Rectangle) {
+    let query = <(Read<Position>, Read<Tilemap>, Read<TileChunks>)>::query();
+    for (position, tilemap, chunks) in query.iter(world) {
+        chunks.draw(canvas, &tilemap, position.src, view);
+    }
+}
+
+pub fn update_tilemaps() -> Box<dyn Schedulable> {
+    SystemBuilder::new("update_tilemaps")
+        .with_query(<(Read<Tilemap>, Write<TileChunks>)>::query())
+        .build(move |_, mut world, _, query| {
+            for (tilemap, mut chunks) in query.iter_mut(&mut world) {
+                chunks.tick();
+                chunks.rebuild(&tilemap);
+            }
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::assets::AssetStore;
+    use crate::engine::tilemap::TileFrame;
+    use crate::gfx::DrawCommand;
+    use quicksilver::graphics::Image;
+
+    // Three chunks in a row, the last one half as wide
+    fn strip() -> Tilemap {
+        let image = AssetStore::<Image>::default().reserve("tiles");
+        let size = Vector::new(8., 8.);
+        let mut tilemap = Tilemap::new(CHUNK_SIZE * 5 / 2, 1, size);
+        let tileset = tilemap.add_tileset(Tileset::grid(image, size, 3, 3, 0., 0.).with_animation(
+            2,
+            vec![
+                TileFrame { tile: 0, ticks: 2 },
+                TileFrame { tile: 1, ticks: 1 },
+            ],
+        ));
+        let layer = tilemap.add_layer("ground", tileset);
+        for x in 0..tilemap.width() {
+            tilemap.set_tile(layer, x, 0, Some(Tile::new(1)));
+        }
+        tilemap
+    }
+
+    fn drawn(
+        chunks: &TileChunks,
+        tilemap: &Tilemap,
+        position: Vector,
+        view: Rectangle,
+    ) -> Vec<DrawCommand> {
+        let mut canvas = CommandBuffer::default();
+        chunks.draw(&mut canvas, tilemap, position, view);
+        canvas.commands().to_vec()
+    }
+
+    #[test]
+    fn only_visible_chunks_are_drawn() {
+        let tilemap = strip();
+        let mut chunks = TileChunks::default();
+        chunks.rebuild(&tilemap);
+        let chunk_width = 8. * CHUNK_SIZE as f32;
+
+        let view = Rectangle::new((0., 0.), (chunk_width / 2., 8.));
+        assert_eq!(
+            drawn(&chunks, &tilemap, Vector::new(0., 0.), view).len(),
+            CHUNK_SIZE as usize
+        );
+        let view = Rectangle::new((chunk_width - 4., 0.), (8., 8.));
+        assert_eq!(
+            drawn(&chunks, &tilemap, Vector::new(0., 0.), view).len(),
+            CHUNK_SIZE as usize * 2
+        );
+        let view = Rectangle::new((0., 0.), (chunk_width * 3., 8.));
+        assert_eq!(
+            drawn(&chunks, &tilemap, Vector::new(0., 0.), view).len(),
+            CHUNK_SIZE as usize * 5 / 2
+        );
+        // The map moved past the view
+        let position = Vector::new(0., 16.);
+        assert!(drawn(&chunks, &tilemap, position, view).is_empty());
+    }
+
+    #[test]
+    fn edits_rebuild_only_their_chunk() {
+        let mut tilemap = strip();
+        let mut chunks = TileChunks::default();
+        assert_eq!(chunks.rebuild(&tilemap), 3);
+        assert_eq!(chunks.rebuild(&tilemap), 0);
+
+        assert!(tilemap.set_tile(0, CHUNK_SIZE + 1, 0, None));
+        assert!(!tilemap.set_tile(0, CHUNK_SIZE + 1, 0, None));
+        assert!(!tilemap.set_tile(0, tilemap.width(), 0, None));
+        assert_eq!(chunks.rebuild(&tilemap), 1);
+
+        let view = Rectangle::new((0., 0.), tilemap.size());
+        assert_eq!(
+            drawn(&chunks, &tilemap, Vector::new(0., 0.), view).len(),
+            CHUNK_SIZE as usize * 5 / 2 - 1
+        );
+    }
+
+    #[test]
+    fn animated_tiles_follow_the_clock() {
+        let mut tilemap = strip();
+        tilemap.set_tile(0, 0, 0, Some(Tile::new(2)));
+        let mut chunks = TileChunks::default();
+        chunks.rebuild(&tilemap);
+        let view = Rectangle::new((0., 0.), (8., 8.));
+
+        let mut regions = Vec::new();
+        for _ in 0..4 {
+            // The animated tiles come after the rest of the chunk
+            match drawn(&chunks, &tilemap, Vector::new(0., 0.), view).last() {
+                Some(DrawCommand::Image { region, dest, .. }) => {
+                    assert_eq!(dest.pos, Vector::new(0., 0.));
+                    regions.push(region.pos.x);
+                }
+                command => panic!("unexpected command {:?}", command),
+            }
+            chunks.tick();
+        }
+        assert_eq!(regions, vec![0., 0., 8., 0.]);
+    }
+}
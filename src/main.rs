@@ -1,14 +1,20 @@
-use golem::TextureFilter;
 use quicksilver::{
-    geom::{Rectangle, Transform, Vector},
+    geom::Vector,
     graphics::{Graphics, Image},
     lifecycle::{run, EventStream, Settings, Window},
     Result, Timer,
 };
 
-use engine::ResizeStrategy;
+use engine::{Camera, ResizeStrategy};
+use gfx::LowResTarget;
 
-use game::Game;
+use engine::assets::{AssetGroup, Assets, Handle, LevelData, Loader, Manifest};
+use engine::input::InputReplay;
+use engine::level::ldtk::{LdtkProject, LevelTravel};
+use engine::level::{
+    despawn, tiled::TiledMap, EntityRegistry, LevelError, LevelObject, SpawnContext,
+};
+use game::{AtlasStorage, Game, Resolution};
 
 #[macro_use]
 extern crate log;
@@ -19,15 +25,22 @@ mod game;
 mod gfx;
 mod phx;
 
-pub use game::DIMENSIONS;
 pub use game::UPDATE_RATE;
 
+/// Level of the manifest the game starts in.
+const LEVEL: &str = "test";
+
 // To add test entities
-use crate::engine::components::{Position, Sprite};
+use crate::engine::components::Position;
+use crate::gfx::{Animation, Layer, Sprite};
+use std::sync::Arc;
 
 // To test velocity
 use crate::phx::Velocity;
 
+// To test jumping
+use crate::phx::JumpControl;
+
 // To test physics tags
 use crate::phx::BodyTag;
 
@@ -47,25 +60,65 @@ fn main() {
 struct Player;
 // This time we might return an error, so we use a Result
 async fn app(window: Window, mut gfx: Graphics, mut events: EventStream) -> Result<()> {
-    // Load the image and wait for it to finish
-    // We also use '?' to handle errors like file-not-found
-    let image = Image::load(&gfx, "image.png").await?;
-    let image_copy = image.clone();
-
-    image.set_magnification(TextureFilter::Nearest)?;
-
     let mut game_data = Game::new();
-    game_data.resize_strategy = set_resize_strategy(&window, &gfx);
-    game_data.images.insert("image".into(), image);
+    set_resize_strategy(&window, &mut game_data);
+    #[cfg(not(target_arch = "wasm32"))]
+    set_input_replay(&mut game_data);
+    game_data.images.create_placeholder(&gfx)?;
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        game_data.images.strict = std::env::args().any(|arg| arg == "--strict-assets");
+    }
+
+    // Loading screen
+    let manifest = match Manifest::load("assets.ron").await {
+        Ok(manifest) => manifest,
+        Err(err) => {
+            error!("{}", err);
+            return Ok(());
+        }
+    };
+    let mut loader = Loader::new(&manifest);
+    while !loader.is_done() {
+        crate::events::handle_events(&window, &mut events, &mut game_data).await;
+        if let Err(err) = loader.step(&gfx, &mut game_data).await {
+            if game_data.images.strict {
+                error!("{}", err);
+                return Ok(());
+            }
+            warn!("{}", err);
+        }
+        crate::gfx::render_loading(&window, &mut gfx, loader.progress());
+    }
+    // Held for the whole game, the scenes hold their own references on top
+    let _manifest_assets = loader.finish();
+    #[cfg(all(debug_assertions, not(target_arch = "wasm32")))]
+    let mut hot_reload = engine::assets::HotReload::new(&manifest, &game_data);
+
+    let image = game_data.images.reserve("image");
+    let image_copy = game_data
+        .images
+        .get_or_placeholder(image)
+        .expect("Placeholder missing somehow")
+        .clone();
 
     {
-        use crate::phx::PhysicsWorld;
-        let mut cool = game_data
+        let atlases = game_data
             .resources
-            .get_mut::<PhysicsWorld>()
-            .expect("PhysicsWorld missing somehow");
-
-        // Test add some entities with Position and Image use crate::engine::components::{Position, Sprite};
+            .get::<AtlasStorage>()
+            .expect("AtlasStorage missing somehow");
+        let atlas = game_data
+            .images
+            .handle("sheet")
+            .and_then(|sheet| atlases.get(&sheet));
+        let clips = Arc::new(atlas.map(|atlas| atlas.clips().clone()).unwrap_or_default());
+        let atlas_sprite = || {
+            atlas
+                .and_then(|atlas| Sprite::from_atlas(atlas, "image"))
+                .unwrap_or_else(|| Sprite::new(image, &image_copy))
+        };
+
+        // Test add some entities with Position and Image
         let _entities = game_data
             .world
             .insert(
@@ -73,55 +126,75 @@ async fn app(window: Window, mut gfx: Graphics, mut events: EventStream) -> Resu
                 vec![
                     (
                         Position { src: Vector::ZERO },
-                        Sprite::new("image".into(), &image_copy),
+                        atlas_sprite(),
+                        Animation::new(clips.clone(), "idle"),
                     ),
                     (
                         Position {
                             src: Vector::new(25., 25.),
                         },
-                        Sprite::new("image".into(), &image_copy),
+                        atlas_sprite(),
+                        Animation::new(clips.clone(), "idle"),
                     ),
                 ],
             )
             .to_vec();
-
-        new_player(
-            &mut game_data.world,
-            &mut cool,
-            mint::Vector2 { x: 120., y: 95. },
-            &image_copy,
-        );
-        new_obstacle(
-            &mut game_data.world,
-            &mut cool,
-            mint::Vector2 { x: 150., y: 150. },
-            &image_copy,
-        );
-        new_obstacle(
-            &mut game_data.world,
-            &mut cool,
-            mint::Vector2 { x: 200., y: 120. },
-            &image_copy,
-        );
-        new_zone(
-            &mut game_data.world,
-            &mut cool,
-            mint::Vector2 { x: 125., y: 125. },
-            &image_copy,
-        );
     }
-    let camera = Transform::orthographic(Rectangle::new(Vector::ZERO, DIMENSIONS));
-    gfx.set_projection(camera);
+    let registry = entity_registry();
+    let mut scene = match load_level(&manifest, &mut game_data, &registry, LEVEL).await {
+        Ok(scene) => scene,
+        Err(err) => {
+            error!("{}", err);
+            return Ok(());
+        }
+    };
+    {
+        let atlases = game_data
+            .resources
+            .get::<AtlasStorage>()
+            .expect("AtlasStorage missing somehow");
+        if let Err(err) = game_data.images.check(&game_data.world, &atlases) {
+            error!("{}", err);
+            return Ok(());
+        }
+    }
+    let mut render_target = LowResTarget::new(&gfx, game_data.resolution())?;
 
     let mut update_timer = Timer::time_per_second(UPDATE_RATE);
     let mut counter = 0;
     loop {
-        crate::events::handle_events(&window, &gfx, &mut events, &mut game_data).await;
+        crate::events::handle_events(&window, &mut events, &mut game_data).await;
+        #[cfg(all(debug_assertions, not(target_arch = "wasm32")))]
+        {
+            let reloaded = hot_reload.update(&gfx, &mut game_data).await;
+            if reloaded.iter().any(|level| level == LEVEL) {
+                // The old scene goes only once the new one spawned, a broken edit keeps it
+                match load_level(&manifest, &mut game_data, &registry, LEVEL).await {
+                    Ok(reloaded) => {
+                        if let Some(old) = std::mem::replace(&mut scene, reloaded) {
+                            unload_level(old, &mut game_data);
+                        }
+                    }
+                    Err(err) => error!("Can't reload level `{}`: {}", LEVEL, err),
+                }
+            }
+        }
 
         while update_timer.tick() {
+            let looped = game_data
+                .resources
+                .get_mut::<InputReplay>()
+                .expect("InputReplay missing somehow")
+                .take_loop();
+            if looped {
+                scene = restart_level(scene.take(), &manifest, &mut game_data, &registry).await;
+            }
             game_data
                 .schedule
                 .execute(&mut game_data.world, &mut game_data.resources);
+            if let Some(travel) = scene.as_mut().and_then(|scene| scene.travel.as_mut()) {
+                follow_camera_target(travel, &mut game_data, &registry);
+            }
 
             counter += 1;
             if counter >= 60 {
@@ -130,17 +203,259 @@ async fn app(window: Window, mut gfx: Graphics, mut events: EventStream) -> Resu
             }
         }
 
-        crate::gfx::render(&window, &mut gfx, &game_data);
+        let resolution_changed = game_data
+            .resources
+            .get_mut::<Resolution>()
+            .expect("Resolution missing somehow")
+            .take_changed();
+        if resolution_changed {
+            let resolution = game_data.resolution();
+            render_target = LowResTarget::new(&gfx, resolution)?;
+            game_data.resize_strategy = pixel_perfect_strategy(resolution);
+            game_data.refit();
+        }
+
+        crate::gfx::render(&window, &mut gfx, &game_data, &render_target);
+    }
+}
+
+/// The level in the world, replaced as a whole when it changes.
+struct Scene {
+    /// References the scene holds, given back with `Game::release` when it's unloaded
+    assets: AssetGroup,
+    /// Entities of a Tiled map
+    spawned: Vec<legion::prelude::Entity>,
+    /// The LDtk projects keep track of their levels themselves
+    travel: Option<LevelTravel>,
+}
+
+/// Spawn the level the manifest lists under the name, the camera stays inside of it.
+///
+/// The LDtk projects start in their first level, the `LevelTravel` of the scene moves on to the
+/// linked levels.
+async fn load_level(
+    manifest: &Manifest,
+    game_data: &mut Game,
+    registry: &EntityRegistry,
+    name: &str,
+) -> std::result::Result<Option<Scene>, LevelError> {
+    let level = {
+        let assets = game_data
+            .resources
+            .get::<Assets>()
+            .expect("Assets missing somehow");
+        assets.levels.handle(name).and_then(|handle| {
+            let bytes = assets.levels.get(handle)?.bytes.clone();
+            Some((handle, bytes))
+        })
+    };
+    let (path, (handle, bytes)) = match (manifest.levels.get(name), level) {
+        (Some(path), Some(level)) => (path, level),
+        _ => {
+            warn!("Level `{}` isn't loaded", name);
+            return Ok(None);
+        }
+    };
+    if path.ends_with(".ldtk") {
+        let mut project = LdtkProject::parse(path, &bytes)?;
+        project.load_levels().await?;
+        let first = match project.levels().next() {
+            Some(first) => first.to_string(),
+            None => {
+                warn!("Project `{}` has no levels", path);
+                return Ok(None);
+            }
+        };
+        let mut travel = LevelTravel::new(project);
+        travel.persistent = vec!["player".into()];
+        let bounds = travel.enter(
+            &first,
+            &mut game_data.world,
+            &game_data.resources,
+            &game_data.images,
+            registry,
+        )?;
+        game_data
+            .resources
+            .get_mut::<Camera>()
+            .expect("Camera missing somehow")
+            .bounds = Some(bounds);
+        return Ok(Some(Scene {
+            assets: level_assets(game_data, handle),
+            spawned: Vec::new(),
+            travel: Some(travel),
+        }));
+    }
+    let mut map = TiledMap::parse(path, &bytes)?;
+    map.load_tilesets().await?;
+    let level = map.into_level(&game_data.images)?;
+    let spawned = level.spawn(
+        &mut game_data.world,
+        &game_data.resources,
+        &game_data.images,
+        registry,
+    )?;
+    game_data
+        .resources
+        .get_mut::<Camera>()
+        .expect("Camera missing somehow")
+        .bounds = Some(level.bounds);
+    Ok(Some(Scene {
+        assets: level_assets(game_data, handle),
+        spawned,
+        travel: None,
+    }))
+}
+
+/// Take a reference to the level file for the scene.
+fn level_assets(game_data: &Game, level: Handle<LevelData>) -> AssetGroup {
+    game_data
+        .resources
+        .get_mut::<Assets>()
+        .expect("Assets missing somehow")
+        .levels
+        .acquire(level);
+    AssetGroup {
+        levels: vec![level],
+        ..AssetGroup::default()
+    }
+}
+
+/// Remove everything the scene spawned, and drop the assets no one else holds.
+fn unload_level(scene: Scene, game_data: &mut Game) {
+    {
+        let mut pworld = game_data
+            .resources
+            .get_mut::<crate::phx::PhysicsWorld>()
+            .expect("PhysicsWorld missing somehow");
+        despawn(&mut game_data.world, &mut pworld, &scene.spawned);
+        if let Some(mut travel) = scene.travel {
+            travel.leave(&mut game_data.world, &mut pworld);
+        }
+    }
+    game_data.release(&scene.assets);
+    game_data.unload_unused();
+}
+
+/// Spawn the level anew with the camera and the input reset, the looping replay starts over from
+/// the same state as the recording did.
+async fn restart_level(
+    scene: Option<Scene>,
+    manifest: &Manifest,
+    game_data: &mut Game,
+    registry: &EntityRegistry,
+) -> Option<Scene> {
+    info!("Replay looped, restarting level `{}`", LEVEL);
+    if let Some(scene) = scene {
+        unload_level(scene, game_data);
+    }
+    game_data.reset_for_replay();
+    match load_level(manifest, game_data, registry, LEVEL).await {
+        Ok(scene) => scene,
+        Err(err) => {
+            error!("Can't restart level `{}`: {}", LEVEL, err);
+            None
+        }
     }
 }
 
+/// Enter the linked level the camera target walked into.
+fn follow_camera_target(travel: &mut LevelTravel, game_data: &mut Game, registry: &EntityRegistry) {
+    use legion::prelude::*;
+
+    let target = game_data
+        .resources
+        .get::<Camera>()
+        .expect("Camera missing somehow")
+        .target;
+    let point = match target.and_then(|target| game_data.world.get_component::<Position>(target)) {
+        Some(position) => position.src,
+        None => return,
+    };
+    let level = match travel.level_to_enter(point) {
+        Some(level) => level,
+        None => return,
+    };
+    match travel.enter(
+        &level,
+        &mut game_data.world,
+        &game_data.resources,
+        &game_data.images,
+        registry,
+    ) {
+        Ok(bounds) => {
+            game_data
+                .resources
+                .get_mut::<Camera>()
+                .expect("Camera missing somehow")
+                .bounds = Some(bounds)
+        }
+        Err(err) => error!("Can't enter level `{}`: {}", level, err),
+    }
+}
+
+fn entity_registry() -> EntityRegistry {
+    EntityRegistry::default()
+        .with("player", |ctx, object| {
+            let player = spawn_with_image(ctx, object, new_player)?;
+            ctx.resources
+                .get_mut::<Camera>()
+                .expect("Camera missing somehow")
+                .target = Some(player);
+            Ok(player)
+        })
+        .with("obstacle", |ctx, object| {
+            spawn_with_image(ctx, object, new_obstacle)
+        })
+        .with("zone", |ctx, object| {
+            spawn_with_image(ctx, object, new_zone)
+        })
+}
+
+type Spawn = fn(
+    &mut legion::prelude::World,
+    &mut crate::phx::PhysicsWorld,
+    mint::Vector2<f32>,
+    Handle<Image>,
+    &Image,
+) -> legion::prelude::Entity;
+
+/// Spawn with the image named by the `image` property of the object, "image" by default.
+fn spawn_with_image(
+    ctx: &mut SpawnContext,
+    object: &LevelObject,
+    spawn: Spawn,
+) -> std::result::Result<legion::prelude::Entity, LevelError> {
+    let name = object.string("image")?.unwrap_or("image");
+    let src = match ctx.images.handle(name) {
+        Some(src) => src,
+        None => return Err(object.bad_property("image", "the name of an image")),
+    };
+    let image = ctx
+        .images
+        .get_or_placeholder(src)
+        .expect("Placeholder missing somehow");
+    let mut pworld = ctx
+        .resources
+        .get_mut::<crate::phx::PhysicsWorld>()
+        .expect("PhysicsWorld missing somehow");
+    Ok(spawn(
+        ctx.world,
+        &mut pworld,
+        object.position.into(),
+        src,
+        image,
+    ))
+}
+
 // TODO: Proper spawning instead of this... thing
 fn new_player(
     world: &mut legion::prelude::World,
     cworld: &mut crate::phx::PhysicsWorld,
     position: mint::Vector2<f32>,
+    src: Handle<Image>,
     image: &Image,
-) {
+) -> legion::prelude::Entity {
     use crate::phx::{Category, Hitbox};
     use resphys::builder::{BodyBuilder, Shape};
 
@@ -151,30 +466,31 @@ fn new_player(
         .build();
     let hitbox = Hitbox::new(cworld, body);
 
-    let _with_collision = world
-        .insert(
-            (),
-            vec![(
-                Position {
-                    src: position.into(),
-                },
-                Sprite::new("image".into(), &image),
-                hitbox,
-                Velocity {
-                    src: Vector::new(25., 16.),
-                },
-                // Player,
-            )],
-        )
-        .to_vec();
+    world.insert(
+        (),
+        vec![(
+            Position {
+                src: position.into(),
+            },
+            Sprite::new(src, image),
+            hitbox,
+            Velocity {
+                src: Vector::new(25., 16.),
+            },
+            JumpControl::new(6, 6, 160.),
+            Layer::y_sorted(1),
+            // Player,
+        )],
+    )[0]
 }
 
 fn new_obstacle(
     world: &mut legion::prelude::World,
     cworld: &mut crate::phx::PhysicsWorld,
     position: mint::Vector2<f32>,
+    src: Handle<Image>,
     image: &Image,
-) {
+) -> legion::prelude::Entity {
     use crate::phx::{Category, Hitbox};
     use resphys::builder::{BodyBuilder, Shape};
 
@@ -189,30 +505,29 @@ fn new_obstacle(
     .build();
     let hitbox = Hitbox::new(cworld, body);
 
-    let _with_collision = world
-        .insert(
-            (),
-            vec![(
-                Position {
-                    src: position.into(),
-                },
-                Sprite::new("image".into(), &image),
-                hitbox,
-                Velocity {
-                    src: Vector::new(0., 0.),
-                },
-                // Player,
-            )],
-        )
-        .to_vec();
+    world.insert(
+        (),
+        vec![(
+            Position {
+                src: position.into(),
+            },
+            Sprite::new(src, image),
+            hitbox,
+            Velocity {
+                src: Vector::new(0., 0.),
+            },
+            // Player,
+        )],
+    )[0]
 }
 
 fn new_zone(
     world: &mut legion::prelude::World,
     cworld: &mut crate::phx::PhysicsWorld,
     position: mint::Vector2<f32>,
+    src: Handle<Image>,
     image: &Image,
-) {
+) -> legion::prelude::Entity {
     use crate::phx::{Category, Hitbox};
     use resphys::builder::{BodyBuilder, Shape};
 
@@ -228,45 +543,76 @@ fn new_zone(
     .build();
     let hitbox = Hitbox::new(cworld, body);
 
-    let _with_collision = world
-        .insert(
-            (),
-            vec![(
-                Position {
-                    src: position.into(),
-                },
-                Sprite::new("image".into(), &image),
-                hitbox,
-                Velocity {
-                    src: Vector::new(0., 0.),
-                },
-                // Player,
-            )],
-        )
-        .to_vec();
+    world.insert(
+        (),
+        vec![(
+            Position {
+                src: position.into(),
+            },
+            Sprite::new(src, image),
+            hitbox,
+            Velocity {
+                src: Vector::new(0., 0.),
+            },
+            // Player,
+        )],
+    )[0]
 }
 
-fn set_resize_strategy(window: &Window, gfx: &Graphics) -> ResizeStrategy {
-    let win_size = Vector::from(window.size()) * window.scale_factor();
-    let resize_strategy = ResizeStrategy::IntegerScale {
-        width: DIMENSIONS.x as u32,
-        height: DIMENSIONS.y as u32,
-    };
-
-    let new_viewport = resize_strategy.resize(DIMENSIONS, win_size);
-    gfx.set_viewport(
-        new_viewport.x() as u32,
-        new_viewport.y() as u32,
-        new_viewport.width() as u32,
-        new_viewport.height() as u32,
-    );
-
-    resize_strategy
+fn set_resize_strategy(window: &Window, game_data: &mut Game) {
+    game_data.window_size = Vector::from(window.size()) * window.scale_factor();
+    game_data.resize_strategy = pixel_perfect_strategy(game_data.resolution());
+    game_data.refit();
 }
 
-//gfx.set_transform(Transform::translate(location) * Transform::translate(-image.size()/2) *Transform::rotate(30));
-//gfx.draw_image(&image, Rectangle::new(image.size()/2, image.size()));
+fn pixel_perfect_strategy(resolution: Vector) -> ResizeStrategy {
+    ResizeStrategy::IntegerOnly {
+        width: resolution.x as u32,
+        height: resolution.y as u32,
+    }
+}
 
-// gfx.set_transform(
-//     Transform::translate(center) * Transform::rotate(30.0) * Transform::translate(-center),
-// );
+/// `--record <file>` saves the input of the session, `--replay <file>` plays it back instead of the
+/// live input. Append `--loop` to keep replaying.
+#[cfg(not(target_arch = "wasm32"))]
+fn set_input_replay(game_data: &mut Game) {
+    use crate::engine::input::{Playback, Recorder};
+    use crate::engine::ButtonsState;
+    use crate::game::Button;
+
+    let args: Vec<String> = std::env::args().collect();
+    let path_after = |flag: &str| {
+        args.iter()
+            .position(|arg| arg == flag)
+            .and_then(|i| args.get(i + 1))
+    };
+    let action_count = game_data
+        .resources
+        .get::<ButtonsState<Button>>()
+        .expect("ButtonsState missing somehow")
+        .action_count();
+
+    let replay = if let Some(path) = path_after("--replay") {
+        match Playback::open(path, action_count) {
+            Ok(mut playback) => {
+                playback.looping = args.iter().any(|arg| arg == "--loop");
+                InputReplay::Playing(playback)
+            }
+            Err(err) => {
+                warn!("Can't replay {}: {}", path, err);
+                InputReplay::Live
+            }
+        }
+    } else if let Some(path) = path_after("--record") {
+        match Recorder::create(path, action_count) {
+            Ok(recorder) => InputReplay::Recording(recorder),
+            Err(err) => {
+                warn!("Can't record to {}: {}", path, err);
+                InputReplay::Live
+            }
+        }
+    } else {
+        InputReplay::Live
+    };
+    game_data.resources.insert(replay);
+}
@@ -9,7 +9,7 @@ use resphys::{Body as B, PhysicsWorld as Pworld};
 pub type PhysicsWorld = Pworld<BodyTag>;
 pub type Body = B<BodyTag>;
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum BodyTag {
     PC,
     DummyArea,
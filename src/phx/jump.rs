@@ -0,0 +1,167 @@
+use crate::phx::PhysicsWorld;
+use resphys::{BodyHandle, BodyState};
+
+/// Jump buffering and coyote time, all the durations are in `UPDATE_RATE` ticks.
+///
+/// The buffered press itself comes from the `ButtonsState` history, this only remembers
+/// when the body was last on the ground and when it last jumped.
+#[derive(Debug, Clone)]
+pub struct JumpControl {
+    /// Jump pressed up to this many ticks before landing still fires
+    pub buffer_ticks: usize,
+    /// Jump pressed up to this many ticks after walking off a ledge still works
+    pub coyote_ticks: usize,
+    pub speed: f32,
+    ticks_since_grounded: usize,
+    ticks_since_jump: usize,
+}
+
+impl JumpControl {
+    pub fn new(buffer_ticks: usize, coyote_ticks: usize, speed: f32) -> Self {
+        Self {
+            buffer_ticks,
+            coyote_ticks,
+            speed,
+            ticks_since_grounded: usize::MAX,
+            ticks_since_jump: usize::MAX,
+        }
+    }
+    /// Advance by one tick, returns whether the jump should happen now.
+    ///
+    /// `pressed_within(frames)` tells if the jump was pressed in the last `frames` frames,
+    /// see `InputView::pressed_within`.
+    pub fn tick(&mut self, grounded: bool, pressed_within: impl Fn(usize) -> bool) -> bool {
+        self.ticks_since_grounded = if grounded {
+            0
+        } else {
+            self.ticks_since_grounded.saturating_add(1)
+        };
+        self.ticks_since_jump = self.ticks_since_jump.saturating_add(1);
+
+        // Only the presses that happened after the last jump count, each press jumps once
+        let window = (self.buffer_ticks + 1).min(self.ticks_since_jump);
+        let can_jump = self.ticks_since_grounded <= self.coyote_ticks;
+        if can_jump && pressed_within(window) {
+            self.ticks_since_jump = 0;
+            // No second jump from the coyote time after leaving the ground by jumping
+            self.ticks_since_grounded = usize::MAX;
+            true
+        } else {
+            false
+        }
+    }
+    pub fn is_grounded(&self) -> bool {
+        self.ticks_since_grounded == 0
+    }
+}
+
+/// Whether the body rests on something solid, based on the contacts of the last physics step.
+pub fn is_grounded(pworld: &PhysicsWorld, handle: BodyHandle) -> bool {
+    // Contact normal points from the first body of the manifold towards the second one
+    const MIN_NORMAL_Y: f32 = 0.5;
+    pworld
+        .manifolds
+        .iter()
+        .filter_map(|(first, second, manifold)| {
+            if *first == handle {
+                Some((*second, manifold, 1.))
+            } else if *second == handle {
+                Some((*first, manifold, -1.))
+            } else {
+                None
+            }
+        })
+        .filter(|(other, _, _)| {
+            pworld
+                .get_body(*other)
+                .map_or(false, |body| matches!(body.state, BodyState::Solid))
+        })
+        .any(|(_, manifold, sign)| {
+            manifold
+                .contacts
+                .iter()
+                .flatten()
+                .any(|contact| contact.normal.y * sign > MIN_NORMAL_Y)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::input::History;
+
+    const BUFFER: usize = 4;
+    const COYOTE: usize = 3;
+
+    // The control along with the history of its jump button
+    struct Slime {
+        control: JumpControl,
+        jump: History,
+    }
+
+    impl Slime {
+        fn new() -> Self {
+            Self {
+                control: JumpControl::new(BUFFER, COYOTE, 1.),
+                jump: History::new(BUFFER + 2),
+            }
+        }
+        fn tick(&mut self, grounded: bool, jump_down: bool) -> bool {
+            self.jump.push(jump_down);
+            let jump = &self.jump;
+            self.control
+                .tick(grounded, |frames| jump.pressed_within(frames))
+        }
+    }
+
+    #[test]
+    fn buffered_press_jumps_on_landing() {
+        for &(ticks_early, jumps) in [(0, true), (BUFFER, true), (BUFFER + 1, false)].iter() {
+            let mut slime = Slime::new();
+            for tick in 0..10 {
+                assert!(!slime.tick(false, tick >= 10 - ticks_early));
+            }
+            assert_eq!(slime.tick(true, true), jumps, "{} ticks early", ticks_early);
+        }
+    }
+
+    #[test]
+    fn coyote_time_after_leaving_a_ledge() {
+        for &(ticks_late, jumps) in [(1, true), (COYOTE, true), (COYOTE + 1, false)].iter() {
+            let mut slime = Slime::new();
+            for _ in 0..5 {
+                assert!(!slime.tick(true, false));
+            }
+            for _ in 1..ticks_late {
+                assert!(!slime.tick(false, false));
+            }
+            assert_eq!(slime.tick(false, true), jumps, "{} ticks late", ticks_late);
+        }
+    }
+
+    #[test]
+    fn no_second_jump_in_the_air() {
+        let mut slime = Slime::new();
+        assert!(!slime.tick(true, false));
+        assert!(slime.tick(false, true));
+        assert!(!slime.tick(false, false));
+        assert!(!slime.tick(false, true));
+
+        // Jumping off the ground leaves no coyote time either
+        let mut slime = Slime::new();
+        assert!(slime.tick(true, true));
+        assert!(!slime.tick(false, false));
+        assert!(!slime.tick(false, true));
+    }
+
+    #[test]
+    fn held_press_jumps_once() {
+        let mut slime = Slime::new();
+        assert!(slime.tick(true, true));
+        // Still on the ground for a tick, then back on it
+        assert!(!slime.tick(true, true));
+        assert!(!slime.tick(false, true));
+        assert!(!slime.tick(true, true));
+        assert!(slime.control.is_grounded());
+    }
+}
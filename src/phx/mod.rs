@@ -1,5 +1,11 @@
 mod collision;
+pub mod jump;
 pub mod movement;
+mod orphans;
+pub mod tilemap;
 
 pub use collision::*;
+pub use jump::JumpControl;
 pub use movement::Velocity;
+pub use orphans::remove_orphan_bodies;
+pub use tilemap::{update_tile_colliders, TileColliders, TileCollision};
@@ -0,0 +1,125 @@
+/*!
+Removing the bodies of the entities that are gone.
+
+The physics world knows nothing about the entities, so deleting one with `world.delete` instead
+of `engine::level::despawn` would leave its bodies behind, still colliding. The owners of the
+bodies are noted every tick, the bodies of the ones missing since the last tick are removed.
+*/
+use super::{Hitbox, PhysicsWorld, TileColliders};
+use fxhash::FxHashMap;
+use legion::prelude::*;
+use resphys::BodyHandle;
+
+/// Bodies of the entities with a `Hitbox` or `TileColliders`, as of the last check.
+#[derive(Default)]
+struct BodyOwners {
+    // The check the entity was last seen in, and its bodies then
+    owners: FxHashMap<Entity, (u32, Vec<BodyHandle>)>,
+    check: u32,
+}
+
+impl BodyOwners {
+    /// Note the bodies the entity owns now, call for every owner before `remove_orphans`.
+    fn own(&mut self, entity: Entity, bodies: impl Iterator<Item = BodyHandle>) {
+        let check = self.check;
+        let (seen, owned) = self
+            .owners
+            .entry(entity)
+            .or_insert_with(|| (check, Vec::new()));
+        if *seen != check {
+            *seen = check;
+            owned.clear();
+        }
+        owned.extend(bodies);
+    }
+
+    /// Remove the bodies of the entities that weren't noted since the last call, returns how
+    /// many there were.
+    ///
+    /// The bodies already removed, by `despawn` or by rebuilding the tile colliders, are skipped.
+    fn remove_orphans(&mut self, pworld: &mut PhysicsWorld) -> usize {
+        let check = self.check;
+        let mut removed = 0;
+        self.owners.retain(|_, (seen, bodies)| {
+            if *seen == check {
+                return true;
+            }
+            for body in bodies.drain(..) {
+                if pworld.get_body(body).is_some() {
+                    pworld.remove_body(body);
+                    removed += 1;
+                }
+            }
+            false
+        });
+        self.check = self.check.wrapping_add(1);
+        removed
+    }
+}
+
+pub fn remove_orphan_bodies() -> Box<dyn Schedulable> {
+    let mut owners = BodyOwners::default();
+    SystemBuilder::new("remove_orphan_bodies")
+        .write_resource::<PhysicsWorld>()
+        .with_query(<Read<Hitbox>>::query())
+        .with_query(<Read<TileColliders>>::query())
+        .build(move |_, world, pworld, (hitboxes, tilemaps)| {
+            for (entity, hitbox) in hitboxes.iter_entities(&*world) {
+                owners.own(entity, std::iter::once(hitbox.src));
+            }
+            for (entity, colliders) in tilemaps.iter_entities(&*world) {
+                owners.own(entity, colliders.bodies());
+            }
+            let removed = owners.remove_orphans(pworld);
+            if removed > 0 {
+                debug!("Removed {} bodies of the deleted entities", removed);
+            }
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::phx::BodyTag;
+    use resphys::builder::{BodyBuilder, Shape};
+
+    fn hitbox(pworld: &mut PhysicsWorld, x: f32) -> Hitbox {
+        let half_extents = mint::Vector2 { x: 4., y: 4. };
+        let center = mint::Vector2 { x, y: 0. };
+        let body = BodyBuilder::new(Shape::AABB(half_extents.into()), center.into(), BodyTag::PC);
+        Hitbox::new(pworld, body.build())
+    }
+
+    #[test]
+    fn bodies_of_deleted_entities_are_removed() {
+        let mut world = Universe::new().create_world();
+        let mut resources = Resources::default();
+        let mut pworld = PhysicsWorld::new();
+        let (kept, deleted, despawned) = (
+            hitbox(&mut pworld, 0.),
+            hitbox(&mut pworld, 16.),
+            hitbox(&mut pworld, 32.),
+        );
+        let bodies = [kept.src, deleted.src, despawned.src];
+        let entities = world
+            .insert((), vec![(kept,), (deleted,), (despawned,)])
+            .to_vec();
+        resources.insert(pworld);
+        let mut schedule = Schedule::builder()
+            .add_system(remove_orphan_bodies())
+            .build();
+        schedule.execute(&mut world, &mut resources);
+
+        world.delete(entities[1]);
+        crate::engine::level::despawn(
+            &mut world,
+            &mut resources.get_mut::<PhysicsWorld>().unwrap(),
+            &entities[2..],
+        );
+        schedule.execute(&mut world, &mut resources);
+        let pworld = resources.get::<PhysicsWorld>().unwrap();
+        assert!(pworld.get_body(bodies[0]).is_some());
+        assert!(pworld.get_body(bodies[1]).is_none());
+        assert!(pworld.get_body(bodies[2]).is_none());
+    }
+}
@@ -0,0 +1,325 @@
+/*!
+Static bodies built from the solid tiles of a tilemap.
+
+Every tile of the collision layer is solid. The solid tiles of a chunk are merged into as few
+rectangles as the greedy scan finds, a rectangle never crosses the chunk border, so an edit
+only rebuilds the bodies of its own chunk.
+*/
+use super::{BodyTag, Category, PhysicsWorld};
+use crate::engine::components::Position;
+use crate::engine::tilemap::{Tilemap, CHUNK_SIZE};
+use legion::prelude::*;
+use quicksilver::geom::Vector;
+use resphys::builder::{BodyBuilder, Shape};
+use resphys::BodyHandle;
+
+/// Which layer of the map collides, and as what.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TileCollision {
+    pub layer: usize,
+    pub tag: BodyTag,
+    pub category: Category,
+}
+
+impl TileCollision {
+    /// Ground obstacles, like the hand placed ones.
+    pub fn solid(layer: usize) -> Self {
+        Self {
+            layer,
+            tag: BodyTag::Obstacle,
+            category: Category::GROUND,
+        }
+    }
+}
+
+/// Rectangle of cells.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CellRect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Cover the solid cells of a `width` by `height` grid with rectangles, each cell exactly once.
+///
+/// The rectangles grow to the right first, then down as long as the whole row below is solid.
+pub fn merge_cells(width: u32, height: u32, solid: impl Fn(u32, u32) -> bool) -> Vec<CellRect> {
+    let mut taken = vec![false; width as usize * height as usize];
+    let index = |x: u32, y: u32| y as usize * width as usize + x as usize;
+    let mut rects = Vec::new();
+    for y in 0..height {
+        for x in 0..width {
+            if taken[index(x, y)] || !solid(x, y) {
+                continue;
+            }
+            let free = |x: u32, y: u32, taken: &[bool]| !taken[index(x, y)] && solid(x, y);
+            let mut rect_width = 1;
+            while x + rect_width < width && free(x + rect_width, y, &taken) {
+                rect_width += 1;
+            }
+            let mut rect_height = 1;
+            while y + rect_height < height
+                && (x..x + rect_width).all(|column| free(column, y + rect_height, &taken))
+            {
+                rect_height += 1;
+            }
+            for row in y..y + rect_height {
+                for column in x..x + rect_width {
+                    taken[index(column, row)] = true;
+                }
+            }
+            rects.push(CellRect {
+                x,
+                y,
+                width: rect_width,
+                height: rect_height,
+            });
+        }
+    }
+    rects
+}
+
+#[derive(Default)]
+struct ColliderChunk {
+    built: Option<(TileCollision, u32)>,
+    bodies: Vec<BodyHandle>,
+}
+
+/// Bodies of a `Tilemap`, on the same entity. Built where the map was when the chunk changed,
+/// the bodies don't follow the map around.
+///
+/// Nothing owns the bodies but this component, delete its entity with `engine::level::despawn`.
+/// After `world.delete` they stay in the physics world until `remove_orphan_bodies` runs.
+#[derive(Default)]
+pub struct TileColliders {
+    // The chunks in rows
+    chunks: Vec<ColliderChunk>,
+}
+
+impl TileColliders {
+    /// Rebuild the bodies of the chunks edited since the last call, returns how many there were.
+    pub fn rebuild(
+        &mut self,
+        tilemap: &Tilemap,
+        position: Vector,
+        pworld: &mut PhysicsWorld,
+    ) -> usize {
+        let collision = match tilemap.collision {
+            Some(collision) if collision.layer < tilemap.layers().len() => collision,
+            _ => {
+                self.clear(pworld);
+                return 0;
+            }
+        };
+        let (columns, rows) = tilemap.chunks();
+        self.chunks
+            .resize_with(columns as usize * rows as usize, ColliderChunk::default);
+        let mut rebuilt = 0;
+        for row in 0..rows {
+            for column in 0..columns {
+                let revision = tilemap.chunk_revision(collision.layer, (column, row));
+                let chunk = &mut self.chunks[(row * columns + column) as usize];
+                if chunk.built == Some((collision, revision)) {
+                    continue;
+                }
+                for body in chunk.bodies.drain(..) {
+                    pworld.remove_body(body);
+                }
+                let (x0, y0) = (column * CHUNK_SIZE, row * CHUNK_SIZE);
+                let width = CHUNK_SIZE.min(tilemap.width() - x0);
+                let height = CHUNK_SIZE.min(tilemap.height() - y0);
+                let solid = |x, y| tilemap.tile(collision.layer, x0 + x, y0 + y).is_some();
+                for rect in merge_cells(width, height, solid) {
+                    let size = Vector::new(
+                        rect.width as f32 * tilemap.tile_size.x,
+                        rect.height as f32 * tilemap.tile_size.y,
+                    );
+                    let corner = Vector::new(
+                        (x0 + rect.x) as f32 * tilemap.tile_size.x,
+                        (y0 + rect.y) as f32 * tilemap.tile_size.y,
+                    );
+                    let half_extents: mint::Vector2<f32> = (size / 2.).into();
+                    let center: mint::Vector2<f32> = (position + corner + size / 2.).into();
+                    let body = BodyBuilder::new(
+                        Shape::AABB(half_extents.into()),
+                        center.into(),
+                        collision.tag,
+                    )
+                    .with_category(collision.category.bits())
+                    .make_static()
+                    .build();
+                    chunk.bodies.push(pworld.add(body));
+                }
+                chunk.built = Some((collision, revision));
+                rebuilt += 1;
+            }
+        }
+        rebuilt
+    }
+
+    /// Remove every body of the map from the physics world.
+    pub fn clear(&mut self, pworld: &mut PhysicsWorld) {
+        for chunk in self.chunks.drain(..) {
+            for body in chunk.bodies {
+                pworld.remove_body(body);
+            }
+        }
+    }
+
+    pub fn bodies(&self) -> impl Iterator<Item = BodyHandle> + '_ {
+        self.chunks
+            .iter()
+            .flat_map(|chunk| chunk.bodies.iter().copied())
+    }
+}
+
+pub fn update_tile_colliders() -> Box<dyn Schedulable> {
+    SystemBuilder::new("update_tile_colliders")
+        .write_resource::<PhysicsWorld>()
+        .with_query(<(Read<Position>, Read<Tilemap>, Write<TileColliders>)>::query())
+        .build(move |_, mut world, pworld, query| {
+            for (position, tilemap, mut colliders) in query.iter_mut(&mut world) {
+                colliders.rebuild(&tilemap, position.src, pworld);
+            }
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::assets::AssetStore;
+    use crate::engine::tilemap::{Tile, Tileset};
+    use quicksilver::graphics::Image;
+
+    fn grid<'a>(rows: &'a [&str]) -> impl Fn(u32, u32) -> bool + 'a {
+        move |x, y| rows[y as usize].as_bytes()[x as usize] == b'#'
+    }
+
+    // Every solid cell covered exactly once, nothing else covered
+    fn assert_covers(rows: &[&str], rects: &[CellRect]) {
+        for (y, row) in rows.iter().enumerate() {
+            for (x, cell) in row.bytes().enumerate() {
+                let (x, y) = (x as u32, y as u32);
+                let covering = rects
+                    .iter()
+                    .filter(|rect| {
+                        (rect.x..rect.x + rect.width).contains(&x)
+                            && (rect.y..rect.y + rect.height).contains(&y)
+                    })
+                    .count();
+                assert_eq!(covering, (cell == b'#') as usize, "cell {}, {}", x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn solid_block_is_one_rect() {
+        let rows = ["....", ".###", ".###"];
+        let rects = merge_cells(4, 3, grid(&rows));
+        assert_eq!(
+            rects,
+            vec![CellRect {
+                x: 1,
+                y: 1,
+                width: 3,
+                height: 2
+            }]
+        );
+    }
+
+    #[test]
+    fn shapes_are_covered_exactly() {
+        let rows = ["##..##", "######", "#....#", "#.##.#"];
+        let rects = merge_cells(6, 4, grid(&rows));
+        assert_covers(&rows, &rects);
+        assert_eq!(rects.len(), 6);
+
+        let checkers = ["#.#", ".#.", "#.#"];
+        let rects = merge_cells(3, 3, grid(&checkers));
+        assert_covers(&checkers, &rects);
+        assert_eq!(rects.len(), 5);
+    }
+
+    #[test]
+    fn empty_grid_has_no_rects() {
+        assert!(merge_cells(3, 2, |_, _| false).is_empty());
+        assert!(merge_cells(0, 0, |_, _| true).is_empty());
+    }
+
+    #[test]
+    fn edits_rebuild_only_their_chunk() {
+        let image = AssetStore::<Image>::default().reserve("tiles");
+        let size = Vector::new(8., 8.);
+        let mut tilemap = Tilemap::new(CHUNK_SIZE * 2, 2, size);
+        let tileset = tilemap.add_tileset(Tileset::grid(image, size, 1, 1, 0., 0.));
+        let layer = tilemap.add_layer("ground", tileset);
+        for x in 0..tilemap.width() {
+            tilemap.set_tile(layer, x, 1, Some(Tile::new(0)));
+        }
+        tilemap.collision = Some(TileCollision::solid(layer));
+
+        let mut pworld = PhysicsWorld::new();
+        let mut colliders = TileColliders::default();
+        assert_eq!(
+            colliders.rebuild(&tilemap, Vector::new(0., 0.), &mut pworld),
+            2
+        );
+        assert_eq!(colliders.bodies().count(), 2);
+        let first = colliders.bodies().next().unwrap();
+        let center = mint::Vector2::from(pworld.get_body(first).unwrap().position);
+        assert_eq!(center, mint::Vector2 { x: 64., y: 12. });
+
+        tilemap.set_tile(layer, CHUNK_SIZE + 3, 1, None);
+        assert_eq!(
+            colliders.rebuild(&tilemap, Vector::new(0., 0.), &mut pworld),
+            1
+        );
+        assert_eq!(colliders.bodies().count(), 3);
+        assert_eq!(colliders.bodies().next(), Some(first));
+
+        tilemap.collision = None;
+        colliders.rebuild(&tilemap, Vector::new(0., 0.), &mut pworld);
+        assert_eq!(colliders.bodies().count(), 0);
+        assert!(pworld.get_body(first).is_none());
+    }
+
+    #[test]
+    fn sliding_body_crosses_the_chunk_border() {
+        use crate::UPDATE_RATE;
+
+        let image = AssetStore::<Image>::default().reserve("tiles");
+        let size = Vector::new(8., 8.);
+        let mut tilemap = Tilemap::new(CHUNK_SIZE * 2, 2, size);
+        let tileset = tilemap.add_tileset(Tileset::grid(image, size, 1, 1, 0., 0.));
+        let layer = tilemap.add_layer("ground", tileset);
+        for x in 0..tilemap.width() {
+            tilemap.set_tile(layer, x, 1, Some(Tile::new(0)));
+        }
+        tilemap.collision = Some(TileCollision::solid(layer));
+        let mut pworld = PhysicsWorld::new();
+        TileColliders::default().rebuild(&tilemap, Vector::new(0., 0.), &mut pworld);
+
+        // Standing on the floor three tiles before the border, runs for a second across it
+        let start = (CHUNK_SIZE * 8 - 24) as f32;
+        let velocity = mint::Vector2 { x: 60., y: 1. };
+        let half_extents = mint::Vector2 { x: 4., y: 4. };
+        let center = mint::Vector2 { x: start, y: 4. };
+        let body = BodyBuilder::new(Shape::AABB(half_extents.into()), center.into(), BodyTag::PC)
+            .with_category(Category::ALLY.bits())
+            .build();
+        let body = pworld.add(body);
+        for _ in 0..UPDATE_RATE as usize {
+            // Pressed into the floor a little, as if by the gravity
+            pworld.mut_body(body).unwrap().velocity = velocity.into();
+            pworld.step(1. / UPDATE_RATE);
+        }
+        let position = mint::Vector2::from(pworld.get_body(body).unwrap().position);
+        assert!(
+            (position.x - (start + velocity.x)).abs() < 0.01,
+            "stopped at {}",
+            position.x
+        );
+        assert!(position.y <= 4.01, "sank to {}", position.y);
+    }
+}